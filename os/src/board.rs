@@ -0,0 +1,94 @@
+//! Pins of U7 as labeled in jag-v1.kicad_sch
+//!
+//! Generated by `pcb-generator pin-map`, do not edit by hand.
+
+#![allow(dead_code)]
+
+use hal::gpio::{gpioa, gpiob, gpioc, gpiof};
+
+/// 1PPS on PB8 (pin 45)
+pub type Pin1pps<MODE> = gpiob::PB8<MODE>;
+
+/// ADC1_BAT on PA4 (pin 14)
+pub type Adc1Bat<MODE> = gpioa::PA4<MODE>;
+
+/// EXT_LED1 on PB12 (pin 25)
+pub type ExtLed1<MODE> = gpiob::PB12<MODE>;
+
+/// EXT_LED2 on PB13 (pin 26)
+pub type ExtLed2<MODE> = gpiob::PB13<MODE>;
+
+/// GPIO_PB0 on PB0 (pin 18)
+pub type GpioPb0<MODE> = gpiob::PB0<MODE>;
+
+/// I2C1_SCL on PB6 (pin 42)
+pub type I2c1Scl<MODE> = gpiob::PB6<MODE>;
+
+/// I2C1_SDA on PB7 (pin 43)
+pub type I2c1Sda<MODE> = gpiob::PB7<MODE>;
+
+/// LED1 on PB10 (pin 21)
+pub type Led1<MODE> = gpiob::PB10<MODE>;
+
+/// LED2 on PB11 (pin 22)
+pub type Led2<MODE> = gpiob::PB11<MODE>;
+
+/// OSC32_IN on PC14 (pin 3)
+pub type Osc32In<MODE> = gpioc::PC14<MODE>;
+
+/// OSC32_OUT on PC15 (pin 4)
+pub type Osc32Out<MODE> = gpioc::PC15<MODE>;
+
+/// OSC_IN on PF0 (pin 5)
+pub type OscIn<MODE> = gpiof::PF0<MODE>;
+
+/// OSC_OUT on PF1 (pin 6)
+pub type OscOut<MODE> = gpiof::PF1<MODE>;
+
+/// SPI3_MISO on PB4 (pin 40)
+pub type Spi3Miso<MODE> = gpiob::PB4<MODE>;
+
+/// SPI3_MOSI on PB5 (pin 41)
+pub type Spi3Mosi<MODE> = gpiob::PB5<MODE>;
+
+/// SPI3_NSS on PA15 (pin 38)
+pub type Spi3Nss<MODE> = gpioa::PA15<MODE>;
+
+/// SPI3_SCK on PB3 (pin 39)
+pub type Spi3Sck<MODE> = gpiob::PB3<MODE>;
+
+/// TIM2_PWM1 on PA0 (pin 10)
+pub type Tim2Pwm1<MODE> = gpioa::PA0<MODE>;
+
+/// TIM2_PWM2 on PA1 (pin 11)
+pub type Tim2Pwm2<MODE> = gpioa::PA1<MODE>;
+
+/// TIM2_PWM3 on PA2 (pin 12)
+pub type Tim2Pwm3<MODE> = gpioa::PA2<MODE>;
+
+/// TIM2_PWM4 on PA3 (pin 13)
+pub type Tim2Pwm4<MODE> = gpioa::PA3<MODE>;
+
+/// TIM15_PWM1 on PB14 (pin 27)
+pub type Tim15Pwm1<MODE> = gpiob::PB14<MODE>;
+
+/// TIM15_PWM2 on PB15 (pin 28)
+pub type Tim15Pwm2<MODE> = gpiob::PB15<MODE>;
+
+/// TIM16_PWM1 on PA6 (pin 16)
+pub type Tim16Pwm1<MODE> = gpioa::PA6<MODE>;
+
+/// TIM17_PWM1 on PA7 (pin 17)
+pub type Tim17Pwm1<MODE> = gpioa::PA7<MODE>;
+
+/// USART1_RX on PA10 (pin 31)
+pub type Usart1Rx<MODE> = gpioa::PA10<MODE>;
+
+/// USART1_TX on PA9 (pin 30)
+pub type Usart1Tx<MODE> = gpioa::PA9<MODE>;
+
+/// USB_DM on PA11 (pin 32)
+pub type UsbDm<MODE> = gpioa::PA11<MODE>;
+
+/// USB_DP on PA12 (pin 33)
+pub type UsbDp<MODE> = gpioa::PA12<MODE>;
@@ -0,0 +1,118 @@
+//! The spi link to pi-os, the stm32 is the slave
+//!
+//! The response to a request is clocked out during the next transfer.
+
+use protocol::{
+	respond, ConfigKey, ErrorCode, OutputValues, Request, Response, Telemetry,
+	MAX_FRAME, PROTOCOL_VERSION,
+};
+
+/// Used until pi-os configures the timeout
+const DEFAULT_FAILSAFE_TIMEOUT_MS: u32 = 300;
+
+pub struct Link {
+	rx: [u8; MAX_FRAME],
+	rx_len: usize,
+	tx: [u8; MAX_FRAME],
+	tx_len: usize,
+	tx_pos: usize,
+	outputs: OutputValues,
+	failsafe_timeout_ms: u32,
+	last_outputs_ms: Option<u32>,
+}
+
+impl Link {
+	pub const fn new() -> Self {
+		Self {
+			rx: [0; MAX_FRAME],
+			rx_len: 0,
+			tx: [0; MAX_FRAME],
+			tx_len: 0,
+			tx_pos: 0,
+			outputs: OutputValues {
+				armed: false,
+				steering: 0,
+				throttle: 0,
+				aux: [0; protocol::AUX_CHANNELS],
+			},
+			failsafe_timeout_ms: DEFAULT_FAILSAFE_TIMEOUT_MS,
+			last_outputs_ms: None,
+		}
+	}
+
+	/// Returns the next byte to clock out, idle bytes are zero
+	pub fn next_tx(&mut self) -> u8 {
+		if self.tx_pos < self.tx_len {
+			self.tx_pos += 1;
+			self.tx[self.tx_pos - 1]
+		} else {
+			0
+		}
+	}
+
+	pub fn received(&mut self, byte: u8) {
+		if self.rx_len < MAX_FRAME {
+			self.rx[self.rx_len] = byte;
+			self.rx_len += 1;
+		}
+	}
+
+	/// Gets called once the pi releases the chip select
+	pub fn transfer_done(&mut self, now_ms: u32) {
+		let rx = self.rx;
+		let rx_len = self.rx_len;
+		self.rx_len = 0;
+
+		// a response is only sent once
+		let mut tx = [0; MAX_FRAME];
+		let len =
+			respond(&rx[..rx_len], &mut tx, |req| self.handle(req, now_ms));
+		self.tx = tx;
+		self.tx_len = len.unwrap_or(0);
+		self.tx_pos = 0;
+	}
+
+	/// True if pi-os stopped sending outputs
+	pub fn failsafe(&self, now_ms: u32) -> bool {
+		match self.last_outputs_ms {
+			Some(last) => now_ms.wrapping_sub(last) > self.failsafe_timeout_ms,
+			None => true,
+		}
+	}
+
+	/// Returns the outputs to apply, neutral while in failsafe
+	pub fn outputs(&self, now_ms: u32) -> OutputValues {
+		if self.failsafe(now_ms) {
+			OutputValues::default()
+		} else {
+			self.outputs
+		}
+	}
+
+	fn handle(&mut self, req: Request, now_ms: u32) -> Response {
+		match req {
+			Request::Hello => Response::Hello {
+				version: PROTOCOL_VERSION,
+			},
+			Request::SetOutputs(outputs) => {
+				self.outputs = outputs;
+				self.last_outputs_ms = Some(now_ms);
+				Response::Ack
+			}
+			Request::GetTelemetry => Response::Telemetry(Telemetry {
+				// the battery and temperature are not measured yet
+				battery_mv: 0,
+				temperature: 0,
+				uptime_ms: now_ms,
+				failsafe: self.failsafe(now_ms),
+			}),
+			Request::SetConfig(ConfigKey::FailsafeTimeoutMs, ms) if ms > 0 => {
+				self.failsafe_timeout_ms = ms as u32;
+				Response::Ack
+			}
+			Request::SetConfig(..) => {
+				Response::Error(ErrorCode::UnsupportedConfig)
+			}
+		}
+	}
+}
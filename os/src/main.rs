@@ -1,17 +1,28 @@
 #![no_std]
 #![no_main]
 
+mod board;
+mod link;
+
+use link::Link;
+
+use core::ptr;
+
 use panic_halt as _;
 
+use cortex_m::peripheral::DWT;
 use cortex_m_rt::entry;
-use hal::delay::Delay;
-use hal::pac::{CorePeripherals, Peripherals};
+use hal::gpio::{Output, PushPull, AF6};
+use hal::pac::{self, CorePeripherals, Peripherals};
 use hal::prelude::*;
 
+/// The hse without a pll
+const SYSCLK_HZ: u32 = 8_000_000;
+
 #[entry]
 fn main() -> ! {
 	// device core peripherals
-	let dcp = CorePeripherals::take().unwrap();
+	let mut dcp = CorePeripherals::take().unwrap();
 	// device peripherals
 	let dp = Peripherals::take().unwrap();
 
@@ -19,27 +30,149 @@ fn main() -> ! {
 
 	// setup clocks
 	let mut rcc = dp.RCC.constrain();
-	let clocks = rcc.cfgr.use_hse(8.MHz()).freeze(&mut flash.acr);
+	let _clocks = rcc.cfgr.use_hse(8.MHz()).freeze(&mut flash.acr);
 
-	let mut delay = Delay::new(dcp.SYST, clocks);
+	// the cycle counter is used as the clock
+	dcp.DCB.enable_trace();
+	dcp.DWT.enable_cycle_counter();
+	let mut millis = Millis::new();
 
+	let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
 	let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
 
-	// PB10
-	let mut led1 = gpiob
+	// the types make sure the pins match the schematic
+	let mut led1: board::Led1<Output<PushPull>> = gpiob
 		.pb10
 		.into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
-	let mut led2 = gpiob
+	let mut led2: board::Led2<Output<PushPull>> = gpiob
 		.pb11
 		.into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
 
+	let _sck: board::Spi3Sck<AF6<PushPull>> = gpiob.pb3.into_af_push_pull(
+		&mut gpiob.moder,
+		&mut gpiob.otyper,
+		&mut gpiob.afrl,
+	);
+	let _miso: board::Spi3Miso<AF6<PushPull>> = gpiob.pb4.into_af_push_pull(
+		&mut gpiob.moder,
+		&mut gpiob.otyper,
+		&mut gpiob.afrl,
+	);
+	let _mosi: board::Spi3Mosi<AF6<PushPull>> = gpiob.pb5.into_af_push_pull(
+		&mut gpiob.moder,
+		&mut gpiob.otyper,
+		&mut gpiob.afrl,
+	);
+	let _nss: board::Spi3Nss<AF6<PushPull>> = gpioa.pa15.into_af_push_pull(
+		&mut gpioa.moder,
+		&mut gpioa.otyper,
+		&mut gpioa.afrh,
+	);
+
+	let spi = dp.SPI3;
+	setup_spi_slave(&spi);
+
+	let mut link = Link::new();
+	let mut selected = false;
+
 	loop {
-		led1.set_low();
-		delay.delay_ms(1000u16);
-		led1.set_high();
+		// the hal has no spi slave, so the registers are polled
+		let sr = spi.sr.read();
+		if sr.rxne().bit_is_set() {
+			link.received(read_byte(&spi));
+		}
+		if sr.txe().bit_is_set() {
+			write_byte(&spi, link.next_tx());
+		}
+
+		let now = millis.now();
+		let nss_low = nss_is_low();
+		if selected && !nss_low {
+			link.transfer_done(now);
+		}
+		selected = nss_low;
+
+		// until the motors are driven the leds show the link state
+		let outputs = link.outputs(now);
+		if outputs.armed {
+			led1.set_low();
+		} else {
+			led1.set_high();
+		}
+		if link.failsafe(now) {
+			led2.set_low();
+		} else {
+			led2.set_high();
+		}
+	}
+}
+
+/// Spi mode 0, 8 bit frames with the hardware chip select
+fn setup_spi_slave(spi: &pac::SPI3) {
+	// safety: only the spi3 enable bit is changed
+	let rcc = unsafe { &*pac::RCC::ptr() };
+	rcc.apb1enr.modify(|_, w| w.spi3en().set_bit());
+
+	// rxne is set after every byte
+	spi.cr2
+		.write(|w| unsafe { w.ds().bits(0b0111) }.frxth().set_bit());
+	spi.cr1.write(|w| {
+		w.mstr()
+			.clear_bit()
+			.ssm()
+			.clear_bit()
+			.cpol()
+			.clear_bit()
+			.cpha()
+			.clear_bit()
+			.spe()
+			.set_bit()
+	});
+}
+
+fn nss_is_low() -> bool {
+	// safety: reading the input register has no side effects
+	let gpioa = unsafe { &*pac::GPIOA::ptr() };
+	gpioa.idr.read().idr15().bit_is_clear()
+}
+
+// the data register needs to be accessed with 8 bits, a 16 bit access
+// would transfer two frames
+
+fn read_byte(spi: &pac::SPI3) -> u8 {
+	unsafe { ptr::read_volatile(&spi.dr as *const _ as *const u8) }
+}
+
+fn write_byte(spi: &pac::SPI3, byte: u8) {
+	unsafe { ptr::write_volatile(&spi.dr as *const _ as *mut u8, byte) }
+}
+
+/// Milliseconds since start from the cycle counter, which overflows after
+/// a few minutes
+struct Millis {
+	last_cycles: u32,
+	rest: u32,
+	ms: u32,
+}
+
+impl Millis {
+	const CYCLES_PER_MS: u32 = SYSCLK_HZ / 1000;
+
+	fn new() -> Self {
+		Self {
+			last_cycles: DWT::cycle_count(),
+			rest: 0,
+			ms: 0,
+		}
+	}
 
-		led2.set_low();
-		delay.delay_ms(1000u16);
-		led2.set_high();
+	/// Needs to be called at least once per overflow
+	fn now(&mut self) -> u32 {
+		let cycles = DWT::cycle_count();
+		let elapsed = cycles.wrapping_sub(self.last_cycles) + self.rest;
+		self.last_cycles = cycles;
+		self.ms = self.ms.wrapping_add(elapsed / Self::CYCLES_PER_MS);
+		self.rest = elapsed % Self::CYCLES_PER_MS;
+		self.ms
 	}
 }
@@ -0,0 +1,317 @@
+use crate::board::{
+	bounds, find_board_file, Board, Footprint, Pad, Point, Shape
+};
+use crate::bom::{group_entries, read_custom_entries};
+use crate::util::{create_build_dir, escape, BUILD_DIR};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use clap::Parser;
+
+/// Height of a legend row in mm
+const LEGEND_ROW: f64 = 1.6;
+const MARGIN: f64 = 3.0;
+
+/// Renders an svg per board side with the outline, the part bodies,
+/// designators, polarity marks and a legend with the bom lines
+#[derive(Debug, Parser)]
+pub struct AssemblyDrawing {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool
+}
+
+/// A bom line as shown in the legend
+struct LegendEntry {
+	jlcpcb_part: String,
+	value: String,
+	designators: Vec<String>
+}
+
+pub fn assembly_drawing(args: AssemblyDrawing) {
+	create_build_dir();
+
+	let path = find_board_file(".")
+		.expect("assembly drawings need a .kicad_pcb file");
+	let board = Board::read(path);
+
+	let legend = read_legend(&board, args.uses_comma);
+
+	for (back, name) in [(false, "top"), (true, "bottom")] {
+		if !board.footprints.iter().any(|f| f.is_back() == back) {
+			println!("no parts on the {} side", name);
+			continue
+		}
+
+		let svg = render_side(&board, &legend, back);
+		let path = format!("{}/assembly-{}.svg", BUILD_DIR, name);
+		fs::write(&path, svg).expect("could not write assembly drawing");
+		println!("created {}", path);
+	}
+}
+
+/// Uses the bom lines if there is a bom, else groups the parts by value
+fn read_legend(board: &Board, uses_comma: bool) -> Vec<LegendEntry> {
+	let value = |designator: &str| board.footprint(designator)
+		.map(|f| f.value.clone())
+		.unwrap_or_default();
+
+	if Path::new("./bom.csv").is_file() {
+		let entries = read_custom_entries("./bom.csv", uses_comma);
+		return group_entries(&entries).into_iter()
+			.map(|(jlcpcb_part, designators)| LegendEntry {
+				jlcpcb_part: jlcpcb_part.into(),
+				value: value(designators[0]),
+				designators: designators.into_iter().map(Into::into).collect()
+			})
+			.collect()
+	}
+
+	let mut legend: Vec<LegendEntry> = vec![];
+	for fp in &board.footprints {
+		let board_only = fp.attrs.iter().any(|a| a == "board_only");
+		if fp.reference.is_empty() || board_only {
+			continue
+		}
+		match legend.iter_mut().find(|e| e.value == fp.value) {
+			Some(entry) => entry.designators.push(fp.reference.clone()),
+			None => legend.push(LegendEntry {
+				jlcpcb_part: String::new(),
+				value: fp.value.clone(),
+				designators: vec![fp.reference.clone()]
+			})
+		}
+	}
+
+	legend
+}
+
+fn color(index: usize) -> String {
+	// the golden angle gives distinct neighbouring colors
+	format!("hsl({}, 65%, 70%)", (index * 137) % 360)
+}
+
+/// Returns the pad which marks the polarity of a part
+///
+/// Diodes are marked at the cathode, parts with more than two pads at pin
+/// 1. Unpolarized two pin parts are not marked.
+fn polarity_pad(fp: &Footprint) -> Option<&Pad> {
+	let prefix: String = fp.reference.chars()
+		.take_while(|c| c.is_ascii_alphabetic())
+		.collect();
+	let lib_id = fp.lib_id.to_lowercase();
+	let numbered = fp.pads.iter().filter(|p| !p.number.is_empty()).count();
+
+	if let Some(cathode) = fp.pads.iter().find(|p| p.number == "K") {
+		return Some(cathode)
+	}
+
+	let polarized = numbered > 2 ||
+		matches!(prefix.as_str(), "D" | "LED") ||
+		lib_id.contains("cp_") || lib_id.contains("polarized") ||
+		lib_id.contains("tantalum");
+
+	if !polarized {
+		return None
+	}
+
+	fp.pads.iter().find(|p| p.number == "1")
+}
+
+/// Joins line segments to a closed polygon, starting with the first one
+fn join_lines(lines: &[(Point, Point)]) -> Option<Vec<Point>> {
+	let close = |a: Point, b: Point| {
+		(a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3
+	};
+
+	let (start, mut end) = *lines.first()?;
+	let mut used = vec![false; lines.len()];
+	used[0] = true;
+	let mut points = vec![start];
+
+	while !close(start, end) {
+		let (i, next) = lines.iter()
+			.enumerate()
+			.filter(|(i, _)| !used[*i])
+			.find_map(|(i, (a, b))| {
+				if close(*a, end) {
+					Some((i, *b))
+				} else if close(*b, end) {
+					Some((i, *a))
+				} else {
+					None
+				}
+			})?;
+		used[i] = true;
+		points.push(end);
+		end = next;
+	}
+
+	(points.len() >= 3).then_some(points)
+}
+
+fn render_side(board: &Board, legend: &[LegendEntry], back: bool) -> String {
+	let ((x0, y0), (x1, y1)) = board.bounds()
+		.unwrap_or(((0f64, 0f64), (1f64, 1f64)));
+
+	let footprints: Vec<_> = board.footprints.iter()
+		.filter(|f| f.is_back() == back)
+		.collect();
+	let on_side: Vec<_> = legend.iter()
+		.enumerate()
+		.filter(|(_, e)| e.designators.iter().any(|d| {
+			footprints.iter().any(|f| &f.reference == d)
+		}))
+		.collect();
+
+	let group_of: HashMap<_, _> = legend.iter()
+		.enumerate()
+		.flat_map(|(i, e)| e.designators.iter().map(move |d| (d.as_str(), i)))
+		.collect();
+
+	// the back side is viewed from below
+	let mirror = |x: f64| if back { x0 + x1 - x } else { x };
+
+	let legend_x = x1 + MARGIN * 2f64;
+	let legend_width = 70f64;
+	let width = x1 - x0 + legend_width + MARGIN * 4f64;
+	let height = (y1 - y0 + MARGIN * 2f64)
+		.max((on_side.len() + 2) as f64 * LEGEND_ROW + MARGIN * 2f64);
+
+	let mut svg = format!(
+		"<svg xmlns=\"http://www.w3.org/2000/svg\" \
+		width=\"{w}mm\" height=\"{h}mm\" viewBox=\"{} {} {w} {h}\">\n\
+		<style>\
+		.edge {{ fill: none; stroke: #000; stroke-width: 0.2; }}\
+		.body {{ stroke: #333; stroke-width: 0.1; }}\
+		.pad {{ fill: #ccc; stroke: #888; stroke-width: 0.02; }}\
+		.pol {{ fill: #c00; }}\
+		text {{ font-family: sans-serif; fill: #000; }}\
+		</style>\n\
+		<rect x=\"{}\" y=\"{}\" width=\"{w}\" height=\"{h}\" fill=\"#fff\"/>\n",
+		x0 - MARGIN, y0 - MARGIN, x0 - MARGIN, y0 - MARGIN,
+		w = width, h = height
+	);
+
+	if back {
+		writeln!(
+			svg, "<g transform=\"translate({} 0) scale(-1 1)\">", x0 + x1
+		).unwrap();
+	} else {
+		svg.push_str("<g>\n");
+	}
+
+	for g in &board.outline {
+		writeln!(svg, "<path class=\"edge\" d=\"{}\"/>", g.shape.svg_path())
+			.unwrap();
+	}
+
+	for fp in &footprints {
+		let fill = group_of.get(fp.reference.as_str())
+			.map(|i| color(*i))
+			.unwrap_or_else(|| "none".into());
+
+		let body: Vec<_> = fp.graphics_on("Fab").collect();
+		let body = if body.is_empty() {
+			fp.graphics_on("CrtYd").collect()
+		} else {
+			body
+		};
+
+		// fab outlines are mostly drawn with single lines, to fill them
+		// they need to be joined
+		let lines: Vec<_> = body.iter()
+			.filter_map(|g| match g.shape {
+				Shape::Line { start, end } => Some((start, end)),
+				_ => None
+			})
+			.collect();
+		if let Some(outline) = join_lines(&lines) {
+			writeln!(
+				svg, "<path fill=\"{}\" d=\"{}\"/>",
+				fill, Shape::Poly(outline).svg_path()
+			).unwrap();
+		}
+
+		for g in &body {
+			let fill = match g.shape {
+				Shape::Poly(_) | Shape::Circle { .. } => fill.as_str(),
+				_ => "none"
+			};
+			writeln!(
+				svg, "<path class=\"body\" fill=\"{}\" d=\"{}\"/>",
+				fill, g.shape.svg_path()
+			).unwrap();
+		}
+
+		for pad in &fp.pads {
+			writeln!(svg, "<path class=\"pad\" d=\"{}\"/>", pad.svg_path())
+				.unwrap();
+		}
+
+		if let Some(pad) = polarity_pad(fp) {
+			let r = (pad.size.0.min(pad.size.1) / 3f64).clamp(0.1, 0.4);
+			let mark = Shape::Circle { center: pad.at, radius: r };
+			writeln!(svg, "<path class=\"pol\" d=\"{}\"/>", mark.svg_path())
+				.unwrap();
+		}
+	}
+
+	svg.push_str("</g>\n");
+
+	// text is drawn outside of the mirrored group to stay readable
+	for fp in &footprints {
+		if fp.reference.is_empty() {
+			continue
+		}
+
+		let points = fp.graphics.iter()
+			.flat_map(|g| g.shape.points())
+			.chain(fp.pads.iter().map(|p| p.at));
+		let size = bounds(points)
+			.map(|((a, b), (c, d))| (c - a).min(d - b))
+			.unwrap_or(1f64);
+		let font = (size * 0.35).clamp(0.3, 1.2);
+
+		writeln!(
+			svg,
+			"<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"{:.2}\" \
+			text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>",
+			mirror(fp.at.0), fp.at.1, font, escape(&fp.reference)
+		).unwrap();
+	}
+
+	let title = if back { "Bottom (mirrored)" } else { "Top" };
+	let mut y = y0;
+	writeln!(
+		svg,
+		"<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"1.2\" \
+		font-weight=\"bold\">{}</text>",
+		legend_x, y, title
+	).unwrap();
+	y += LEGEND_ROW * 1.5;
+
+	for (i, entry) in on_side {
+		let designators: Vec<_> = entry.designators.iter()
+			.filter(|d| footprints.iter().any(|f| &f.reference == *d))
+			.map(|d| d.as_str())
+			.collect();
+
+		writeln!(
+			svg,
+			"<rect x=\"{:.3}\" y=\"{:.3}\" width=\"1\" height=\"1\" \
+			fill=\"{}\" stroke=\"#333\" stroke-width=\"0.05\"/>\
+			<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"0.8\">{} {} {} {}</text>",
+			legend_x, y - 0.8, color(i),
+			legend_x + 1.5, y,
+			i + 1, escape(&entry.value), escape(&entry.jlcpcb_part),
+			escape(&designators.join(","))
+		).unwrap();
+		y += LEGEND_ROW;
+	}
+
+	svg.push_str("</svg>\n");
+	svg
+}
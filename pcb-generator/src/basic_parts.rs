@@ -0,0 +1,239 @@
+use crate::bom::{group_entries, parse_value, read_custom_entries};
+use crate::packages::normalize_package;
+use crate::partslist::{find_in_parts_list, Part};
+use crate::util::{create_build_dir, BUILD_DIR};
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use clap::Parser;
+
+const DIELECTRICS: &[&str] = &[
+	"C0G", "NP0", "X5R", "X6S", "X7R", "X7S", "X8R", "Y5V", "Z5U"
+];
+
+/// Lists Basic parts which could replace the Extended parts of the bom
+#[derive(Debug, Parser)]
+pub struct BasicParts {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// The fee jlcpcb charges per Extended part and order
+	#[clap(long, default_value_t = 3f64)]
+	fee: f64,
+	/// How many boards get ordered, used for the price difference
+	#[clap(long, default_value_t = 1)]
+	boards: usize,
+	/// How many alternatives get listed per part
+	#[clap(long, default_value_t = 3)]
+	limit: usize,
+	/// Writes build/bom-basic.csv with the best alternative of every part
+	#[clap(long)]
+	write_bom: bool
+}
+
+/// The parametric values found in a part description
+///
+/// `10kΩ ±1% 62.5mW 0402` has the value 10k, a tolerance of 1% and a
+/// power rating of 0.0625W.
+#[derive(Debug, Default, PartialEq)]
+struct Params {
+	/// Capacitance, resistance or inductance with their unit
+	values: Vec<(char, f64)>,
+	/// Voltage, power and current ratings, a replacement may be higher
+	ratings: Vec<(char, f64)>,
+	/// In percent, a replacement may be tighter
+	tolerance: Option<f64>,
+	dielectric: Option<String>
+}
+
+impl Params {
+	fn parse(desc: &str) -> Self {
+		let mut params = Self::default();
+
+		for token in desc.split_whitespace() {
+			let token = token.trim_start_matches('±');
+			let upper = token.to_uppercase();
+			if DIELECTRICS.contains(&upper.as_str()) {
+				params.dielectric = Some(upper);
+				continue
+			}
+
+			let Some(last) = token.chars().last() else { continue };
+			let num = &token[..token.len() - last.len_utf8()];
+			let unit = if last == '\u{2126}' { 'Ω' } else { last };
+
+			match unit {
+				'F' | 'H' | 'Ω' => {
+					if let Some(v) = parse_value(num) {
+						params.values.push((unit, v));
+					}
+				},
+				'V' | 'W' | 'A' => {
+					if let Some(v) = parse_value(num) {
+						params.ratings.push((unit, v));
+					}
+				},
+				'%' => {
+					params.tolerance = num.parse().ok();
+				},
+				_ => {}
+			}
+		}
+
+		params
+	}
+
+	/// Returns true if a part with these params can replace `orig`
+	fn can_replace(&self, orig: &Params) -> bool {
+		let same = |a: f64, b: f64| (a - b).abs() <= a.abs() * 1e-6;
+
+		let values_match = self.values.len() == orig.values.len() &&
+			orig.values.iter().all(|(u, v)| {
+				self.values.iter().any(|(u2, v2)| u == u2 && same(*v, *v2))
+			});
+
+		let ratings_match = orig.ratings.iter().all(|(u, v)| {
+			self.ratings.iter()
+				.any(|(u2, v2)| u == u2 && *v2 >= *v * (1f64 - 1e-6))
+		});
+
+		let tolerance_match = match (orig.tolerance, self.tolerance) {
+			(Some(a), Some(b)) => b <= a,
+			(Some(_), None) => false,
+			(None, _) => true
+		};
+
+		let dielectric_match = orig.dielectric.is_none() ||
+			orig.dielectric == self.dielectric;
+
+		values_match && ratings_match && tolerance_match && dielectric_match
+	}
+}
+
+fn is_extended(part: &Part) -> bool {
+	part.library_type.trim().eq_ignore_ascii_case("extended")
+}
+
+fn is_basic(part: &Part) -> bool {
+	part.library_type.trim().eq_ignore_ascii_case("basic")
+}
+
+/// Returns the Basic parts which can replace `orig`, the ones with the most
+/// stock first
+///
+/// Parts without a parametric value (like ICs) can only be replaced by the
+/// same manufacturer part.
+fn alternatives<'a>(orig: &Part, basic: &'a [Part]) -> Vec<&'a Part> {
+	let orig_params = Params::parse(&orig.desc);
+	let orig_package = normalize_package(&orig.package);
+	let orig_mfr = orig.mfr_part.trim().to_lowercase();
+
+	let mut list: Vec<_> = basic.iter()
+		.filter(|p| p.lcsc.trim() != orig.lcsc.trim())
+		.filter(|p| {
+			if !orig_mfr.is_empty() &&
+				p.mfr_part.trim().to_lowercase() == orig_mfr
+			{
+				return true
+			}
+
+			!orig_params.values.is_empty() &&
+				normalize_package(&p.package) == orig_package &&
+				p.first_cat == orig.first_cat &&
+				p.second_cat == orig.second_cat &&
+				Params::parse(&p.desc).can_replace(&orig_params)
+		})
+		.collect();
+	list.sort_by_key(|p| Reverse(p.stock));
+
+	list
+}
+
+pub fn basic_parts(args: BasicParts) {
+	let custom_entries = read_custom_entries("./bom.csv", args.uses_comma);
+	let groups = group_entries(&custom_entries);
+
+	let ids: Vec<_> = groups.iter().map(|(id, _)| id.to_string()).collect();
+	let parts = find_in_parts_list(|p| {
+		is_basic(p) || ids.iter().any(|i| i == p.lcsc.trim())
+	});
+
+	let by_id: HashMap<_, _> = parts.iter()
+		.map(|p| (p.lcsc.trim(), p))
+		.collect();
+	let basic: Vec<_> = parts.iter()
+		.filter(|p| is_basic(p))
+		.cloned()
+		.collect();
+
+	let mut substitutions = HashMap::new();
+	let mut extended = 0;
+	let mut total_fee = 0f64;
+	let mut total_price = 0f64;
+
+	for (jlcpcb_part, designators) in &groups {
+		let Some(part) = by_id.get(jlcpcb_part) else {
+			eprintln!("warning: {} not found in the parts list", jlcpcb_part);
+			continue
+		};
+		if !is_extended(part) {
+			continue
+		}
+		extended += 1;
+
+		println!(
+			"{} {} {:?} {}",
+			jlcpcb_part, part.package, part.desc, designators.join(",")
+		);
+
+		let alternatives = alternatives(part, &basic);
+		if alternatives.is_empty() {
+			println!("    no basic alternative");
+			continue
+		}
+
+		let qty = designators.len() * args.boards;
+		let price = part.unit_price(qty).map(|p| p * qty as f64);
+
+		for alt in alternatives.iter().take(args.limit) {
+			let alt_price = alt.unit_price(qty).map(|p| p * qty as f64);
+			let price_diff = match (price, alt_price) {
+				(Some(a), Some(b)) => format!("{:+.4}", b - a),
+				_ => "?".into()
+			};
+			println!(
+				"    {:<10} {:<16} stock {:>8}  fee saved {:.2}  price {}",
+				alt.lcsc.trim(), alt.mfr_part, alt.stock, args.fee, price_diff
+			);
+		}
+
+		let best = alternatives[0];
+		if let (Some(a), Some(b)) = (price, best.unit_price(qty)) {
+			total_price += b * qty as f64 - a;
+		}
+		total_fee += args.fee;
+		substitutions.insert(*jlcpcb_part, best.lcsc.trim().to_string());
+	}
+
+	println!(
+		"{} of {} extended parts can be replaced, saving {:.2} in fees \
+		({:+.4} part price for {} boards)",
+		substitutions.len(), extended, total_fee, total_price, args.boards
+	);
+
+	if args.write_bom {
+		create_build_dir();
+		let path = format!("{}/bom-basic.csv", BUILD_DIR);
+		let mut w = csv::Writer::from_path(&path).unwrap();
+		w.write_record(["Designator", "JLCPCB Part"]).unwrap();
+		for (jlcpcb_part, designators) in &groups {
+			let part = substitutions.get(jlcpcb_part)
+				.map(|s| s.as_str())
+				.unwrap_or(jlcpcb_part);
+			w.write_record([designators.join(",").as_str(), part]).unwrap();
+		}
+		w.flush().unwrap();
+
+		println!("substituted bom written to {:?}", path);
+	}
+}
@@ -0,0 +1,406 @@
+//! Board geometry read from a .kicad_pcb (or .kicad_mod) file
+//!
+//! All coordinates are in mm, in kicad coordinates (y pointing down).
+
+use crate::sexpr::Sexpr;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub type Point = (f64, f64);
+
+#[derive(Debug, Clone)]
+pub enum Shape {
+	Line { start: Point, end: Point },
+	Circle { center: Point, radius: f64 },
+	Arc { start: Point, mid: Point, end: Point },
+	/// closed polygon, rectangles are stored as polygons
+	Poly(Vec<Point>)
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Graphic {
+	pub layer: String,
+	pub width: f64,
+	pub shape: Shape
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Pad {
+	pub number: String,
+	/// smd, thru_hole, np_thru_hole, ...
+	pub kind: String,
+	/// rect, roundrect, circle, oval, custom, ...
+	pub shape: String,
+	pub at: Point,
+	pub size: (f64, f64),
+	/// in deg, absolute
+	pub rotation: f64,
+	pub layers: Vec<String>
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Footprint {
+	/// library:name
+	pub lib_id: String,
+	pub reference: String,
+	pub value: String,
+	pub at: Point,
+	/// in deg
+	pub rotation: f64,
+	/// F.Cu or B.Cu
+	pub layer: String,
+	/// smd, through_hole, exclude_from_bom, ...
+	pub attrs: Vec<String>,
+	/// in board coordinates
+	pub graphics: Vec<Graphic>,
+	/// in board coordinates
+	pub pads: Vec<Pad>
+}
+
+#[derive(Debug, Clone)]
+pub struct Board {
+	/// Everything on the Edge.Cuts layer
+	pub outline: Vec<Graphic>,
+	pub footprints: Vec<Footprint>
+}
+
+impl Board {
+	pub fn read(path: impl AsRef<Path>) -> Self {
+		let path = path.as_ref();
+		let raw = fs::read_to_string(path)
+			.unwrap_or_else(|e| panic!("could not read {:?} {:?}", path, e));
+		let sexpr = Sexpr::parse(&raw)
+			.unwrap_or_else(|e| panic!("could not parse {:?} {}", path, e));
+
+		Self::from_sexpr(&sexpr)
+	}
+
+	pub fn from_sexpr(sexpr: &Sexpr) -> Self {
+		let outline = sexpr.as_list().iter()
+			.filter_map(|s| parse_graphic(s, "gr_", &|p| p))
+			.filter(|g| g.layer == "Edge.Cuts")
+			.collect();
+
+		let footprints = sexpr.as_list().iter()
+			.filter(|s| matches!(s.name(), Some("footprint" | "module")))
+			.map(Footprint::from_sexpr)
+			.collect();
+
+		Self { outline, footprints }
+	}
+
+	pub fn footprint(&self, reference: &str) -> Option<&Footprint> {
+		self.footprints.iter().find(|f| f.reference == reference)
+	}
+
+	/// Returns (min, max) of the outline, or of all footprints if there is
+	/// no outline
+	pub fn bounds(&self) -> Option<(Point, Point)> {
+		let graphics: Vec<_> = if self.outline.is_empty() {
+			self.footprints.iter()
+				.flat_map(|f| f.graphics.iter())
+				.collect()
+		} else {
+			self.outline.iter().collect()
+		};
+
+		bounds(graphics.iter().flat_map(|g| g.shape.points()))
+	}
+}
+
+impl Footprint {
+	pub fn from_sexpr(sexpr: &Sexpr) -> Self {
+		let at = sexpr.child("at");
+		let pos = at.map(|a| (
+			a.num(0).unwrap_or(0f64),
+			a.num(1).unwrap_or(0f64)
+		)).unwrap_or((0f64, 0f64));
+		let rotation = at.and_then(|a| a.num(2)).unwrap_or(0f64);
+
+		let mut fp = Self {
+			lib_id: sexpr.atom(0).unwrap_or("").into(),
+			reference: String::new(),
+			value: String::new(),
+			at: pos,
+			rotation,
+			layer: sexpr.child("layer")
+				.and_then(|l| l.atom(0))
+				.unwrap_or("F.Cu").into(),
+			attrs: sexpr.child("attr")
+				.map(|a| a.as_list()[1..].iter()
+					.filter_map(|s| s.as_atom())
+					.map(Into::into)
+					.collect())
+				.unwrap_or_default(),
+			graphics: vec![],
+			pads: vec![]
+		};
+
+		// kicad 6 uses fp_text, kicad 7 properties
+		let texts = sexpr.children("fp_text")
+			.chain(sexpr.children("property"));
+		for text in texts {
+			match (text.atom(0), text.atom(1)) {
+				(Some("reference" | "Reference"), Some(r)) => {
+					fp.reference = r.into();
+				},
+				(Some("value" | "Value"), Some(v)) => fp.value = v.into(),
+				_ => {}
+			}
+		}
+
+		let transform = |p: Point| fp.transform(p);
+		let graphics = sexpr.as_list().iter()
+			.filter_map(|s| parse_graphic(s, "fp_", &transform))
+			.collect();
+
+		let pads = sexpr.children("pad")
+			.map(|p| {
+				let at = p.child("at");
+				let local = at.map(|a| (
+					a.num(0).unwrap_or(0f64),
+					a.num(1).unwrap_or(0f64)
+				)).unwrap_or((0f64, 0f64));
+				let size = p.child("size")
+					.map(|s| (
+					s.num(0).unwrap_or(0f64),
+					s.num(1).unwrap_or(0f64)
+				))
+					.unwrap_or((0f64, 0f64));
+
+				Pad {
+					number: p.atom(0).unwrap_or("").into(),
+					kind: p.atom(1).unwrap_or("").into(),
+					shape: p.atom(2).unwrap_or("").into(),
+					at: fp.transform(local),
+					size,
+					rotation: at.and_then(|a| a.num(2)).unwrap_or(0f64),
+					layers: p.child("layers")
+						.map(|l| l.as_list()[1..].iter()
+							.filter_map(|s| s.as_atom())
+							.map(Into::into)
+							.collect())
+						.unwrap_or_default()
+				}
+			})
+			.collect();
+
+		fp.graphics = graphics;
+		fp.pads = pads;
+
+		fp
+	}
+
+	pub fn is_back(&self) -> bool {
+		self.layer.starts_with("B.")
+	}
+
+	/// Converts a point relative to the footprint to board coordinates
+	pub fn transform(&self, (x, y): Point) -> Point {
+		let (sin, cos) = self.rotation.to_radians().sin_cos();
+		(
+			self.at.0 + x * cos + y * sin,
+			self.at.1 - x * sin + y * cos
+		)
+	}
+
+	/// Graphics on the given layer without the side prefix, for example
+	/// `CrtYd` or `Fab`
+	pub fn graphics_on<'a>(
+		&'a self,
+		layer: &'a str
+	) -> impl Iterator<Item=&'a Graphic> + 'a {
+		self.graphics.iter()
+			.filter(move |g| {
+				g.layer.split_once('.').map(|(_, l)| l) == Some(layer)
+			})
+	}
+}
+
+fn parse_graphic(
+	sexpr: &Sexpr,
+	prefix: &str,
+	transform: &dyn Fn(Point) -> Point
+) -> Option<Graphic> {
+	let kind = sexpr.name()?.strip_prefix(prefix)?;
+	let point = |name: &str| -> Option<Point> {
+		let p = sexpr.child(name)?;
+		Some(transform((p.num(0)?, p.num(1)?)))
+	};
+
+	let shape = match kind {
+		"line" => Shape::Line {
+			start: point("start")?,
+			end: point("end")?
+		},
+		"rect" => {
+			let s = sexpr.child("start")?;
+			let e = sexpr.child("end")?;
+			let (x0, y0) = (s.num(0)?, s.num(1)?);
+			let (x1, y1) = (e.num(0)?, e.num(1)?);
+			Shape::Poly(
+				[(x0, y0), (x1, y0), (x1, y1), (x0, y1)].into_iter()
+					.map(transform)
+					.collect()
+			)
+		},
+		"circle" => {
+			let center = point("center")?;
+			let end = point("end")?;
+			Shape::Circle {
+				center,
+				radius: distance(center, end)
+			}
+		},
+		"arc" => Shape::Arc {
+			start: point("start")?,
+			mid: point("mid")?,
+			end: point("end")?
+		},
+		"poly" => Shape::Poly(
+			sexpr.child("pts")?
+				.children("xy")
+				.filter_map(|xy| {
+					Some(transform((xy.num(0)?, xy.num(1)?)))
+				})
+				.collect()
+		),
+		_ => return None
+	};
+
+	let width = sexpr.child("width")
+		.or_else(|| sexpr.child("stroke").and_then(|s| s.child("width")))
+		.and_then(|w| w.num(0))
+		.unwrap_or(0f64);
+
+	Some(Graphic {
+		layer: sexpr.child("layer")?.atom(0)?.into(),
+		width,
+		shape
+	})
+}
+
+impl Shape {
+	/// Returns the points needed to calculate the bounds
+	pub fn points(&self) -> Vec<Point> {
+		match self {
+			Self::Line { start, end } => vec![*start, *end],
+			Self::Circle { center: (x, y), radius: r } => {
+				vec![(x - r, y - r), (x + r, y + r)]
+			},
+			Self::Arc { start, mid, end } => vec![*start, *mid, *end],
+			Self::Poly(pts) => pts.clone()
+		}
+	}
+
+	/// Returns the svg path data
+	pub fn svg_path(&self) -> String {
+		match self {
+			Self::Line { start, end } => format!(
+				"M{:.4} {:.4}L{:.4} {:.4}", start.0, start.1, end.0, end.1
+			),
+			Self::Circle { center: (x, y), radius: r } => format!(
+				"M{:.4} {:.4}a{r:.4} {r:.4} 0 1 0 {d:.4} 0\
+				a{r:.4} {r:.4} 0 1 0 {nd:.4} 0",
+				x - r, y, r = r, d = 2f64 * r, nd = -2f64 * r
+			),
+			Self::Arc { start, mid, end } => {
+				let Some(center) = circumcenter(*start, *mid, *end) else {
+					return Self::Line { start: *start, end: *end }.svg_path()
+				};
+				let r = distance(center, *start);
+				let chord = cross(sub(*end, *start), sub(*mid, *start));
+				let side = cross(sub(*end, *start), sub(center, *start));
+				let large = (chord > 0f64) == (side > 0f64);
+				let sweep = cross(sub(*mid, *start), sub(*end, *mid)) > 0f64;
+				format!(
+					"M{:.4} {:.4}A{r:.4} {r:.4} 0 {} {} {:.4} {:.4}",
+					start.0, start.1, large as u8, sweep as u8, end.0, end.1,
+					r = r
+				)
+			},
+			Self::Poly(pts) => {
+				let mut d = String::new();
+				for (i, (x, y)) in pts.iter().enumerate() {
+					let cmd = if i == 0 { 'M' } else { 'L' };
+					d.push_str(&format!("{}{:.4} {:.4}", cmd, x, y));
+				}
+				d.push('Z');
+				d
+			}
+		}
+	}
+}
+
+impl Pad {
+	/// Returns the svg path data of the pad outline
+	pub fn svg_path(&self) -> String {
+		let (w, h) = (self.size.0 / 2f64, self.size.1 / 2f64);
+		if matches!(self.shape.as_str(), "circle") {
+			return Shape::Circle { center: self.at, radius: w }.svg_path()
+		}
+
+		let (sin, cos) = self.rotation.to_radians().sin_cos();
+		let pts = [(-w, -h), (w, -h), (w, h), (-w, h)].into_iter()
+			.map(|(x, y)| (
+				self.at.0 + x * cos + y * sin,
+				self.at.1 - x * sin + y * cos
+			))
+			.collect();
+
+		Shape::Poly(pts).svg_path()
+	}
+}
+
+pub fn bounds(points: impl Iterator<Item=Point>) -> Option<(Point, Point)> {
+	points.fold(None, |b, (x, y)| match b {
+		None => Some(((x, y), (x, y))),
+		Some(((x0, y0), (x1, y1))) => {
+			Some(((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y))))
+		}
+	})
+}
+
+fn sub(a: Point, b: Point) -> Point {
+	(a.0 - b.0, a.1 - b.1)
+}
+
+fn cross(a: Point, b: Point) -> f64 {
+	a.0 * b.1 - a.1 * b.0
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+	((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+fn circumcenter(a: Point, b: Point, c: Point) -> Option<Point> {
+	let d = 2f64 *
+		(a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+	if d.abs() < 1e-12 {
+		return None
+	}
+
+	let a2 = a.0 * a.0 + a.1 * a.1;
+	let b2 = b.0 * b.0 + b.1 * b.1;
+	let c2 = c.0 * c.0 + c.1 * c.1;
+
+	Some((
+		(a2 * (b.1 - c.1) + b2 * (c.1 - a.1) + c2 * (a.1 - b.1)) / d,
+		(a2 * (c.0 - b.0) + b2 * (a.0 - c.0) + c2 * (b.0 - a.0)) / d
+	))
+}
+
+/// Returns the first .kicad_pcb file in the directory
+pub fn find_board_file(dir: impl AsRef<Path>) -> Option<PathBuf> {
+	let mut files: Vec<_> = fs::read_dir(dir).ok()?
+		.filter_map(|e| e.ok())
+		.map(|e| e.path())
+		.filter(|p| p.extension().is_some_and(|e| e == "kicad_pcb"))
+		.collect();
+	files.sort();
+	files.into_iter().next()
+}
@@ -1,8 +1,12 @@
-
-use crate::util::{BUILD_DIR, create_build_dir};
-use crate::partslist::find_in_parts_list;
+use crate::util::{BUILD_DIR, create_build_dir, natural_cmp};
+use crate::partslist::{find_in_parts_list, Part};
+use crate::cpl::read_kicad_entries;
+use crate::packages::PackageAliases;
 
 use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process;
 
 use clap::Parser;
 
@@ -17,22 +21,7 @@ pub struct Bom {
 pub fn bom(args: Bom) {
 	create_build_dir();
 
-	let raw_csv = fs::read_to_string("./bom.csv").expect("failed to read csv");
-
-	let delimiter = if args.uses_comma {
-		b','
-	} else {
-		b';'
-	};
-
-	let reader = csv::ReaderBuilder::new()
-		.flexible(true)
-		.delimiter(delimiter)
-		.from_reader(raw_csv.trim().as_bytes());
-
-	let custom_entries: Vec<CustomEntry> = reader.into_deserialize()
-		.collect::<Result<_, _>>()
-		.expect("failed to deserialize bom");
+	let custom_entries = read_custom_entries("./bom.csv", args.uses_comma);
 
 	let bom_path = format!("{}/bom.csv", BUILD_DIR);
 	let mut w = csv::Writer::from_path(&bom_path).unwrap();
@@ -43,19 +32,53 @@ pub fn bom(args: Bom) {
 
 	let parts = find_in_parts_list(|p| ids.iter().any(|i| i == p.lcsc.trim()));
 
+	let kicad_entries: HashMap<_, _> = read_kicad_entries(args.uses_comma)
+		.unwrap_or_default()
+		.into_iter()
+		.map(|e| (e.designator.clone(), e))
+		.collect();
+	if kicad_entries.is_empty() {
+		eprintln!("no position file found, skipping value checks");
+	}
+
+	let aliases = PackageAliases::read();
+
+	let groups = group_entries(&custom_entries);
+
 	// convert to JlcpcbEntry
-	for entry in custom_entries {
+	for (jlcpcb_part, designators) in groups {
 		let Some(part) = parts.iter()
-			.find(|p| p.lcsc.trim() == entry.jlcpcb_part) else
+			.find(|p| p.lcsc.trim() == jlcpcb_part) else
 		{
-			panic!("could not find {:?}", entry);
+			panic!("could not find {:?} {:?}", jlcpcb_part, designators);
 		};
 
+		for designator in &designators {
+			let Some(kicad) = kicad_entries.get(*designator) else {
+				continue
+			};
+
+			if !value_matches(&kicad.value, part) {
+				eprintln!(
+					"warning: {} value {:?} does not match {} {:?}",
+					designator, kicad.value, jlcpcb_part, part.desc
+				);
+			}
+
+			if !aliases.matches(&kicad.package, &part.package) {
+				eprintln!(
+					"warning: {} footprint {:?} does not match {} {:?}",
+					designator, kicad.package, jlcpcb_part, part.package
+				);
+			}
+		}
+
 		w.serialize(JlcpcbEntry {
 			comment: part.desc.clone(),
-			designators: entry.designators,
+			designators: designators.join(","),
 			footprint: part.package.clone(),
-			jlcpcb_part: entry.jlcpcb_part
+			jlcpcb_part: jlcpcb_part.into(),
+			quantity: designators.len()
 		}).unwrap();
 	}
 	w.flush().unwrap();
@@ -63,24 +86,165 @@ pub fn bom(args: Bom) {
 	println!("written to {:?}", bom_path);
 }
 
+/// Merges entries with the same part and sorts the designators
+///
+/// The groups are sorted by their first designator. Exits if a designator
+/// is listed twice.
+pub fn group_entries(entries: &[CustomEntry]) -> Vec<(&str, Vec<&str>)> {
+	match try_group_entries(entries) {
+		Ok(groups) => groups,
+		Err(designator) => {
+			eprintln!("{} is listed twice in the bom", designator);
+			process::exit(1);
+		}
+	}
+}
+
+/// Like `group_entries` but returns the designator which is listed twice
+pub fn try_group_entries(
+	entries: &[CustomEntry]
+) -> Result<Vec<(&str, Vec<&str>)>, String> {
+	let mut groups: HashMap<&str, Vec<&str>> = HashMap::new();
+	let mut seen = HashSet::new();
+
+	for entry in entries {
+		let group = groups.entry(entry.jlcpcb_part.trim()).or_default();
+		for designator in split_designators(&entry.designators) {
+			if !seen.insert(designator) {
+				return Err(designator.into())
+			}
+			group.push(designator);
+		}
+	}
+
+	let mut groups: Vec<_> = groups.into_iter()
+		.filter(|(_, designators)| !designators.is_empty())
+		.collect();
+	for (_, designators) in &mut groups {
+		designators.sort_by(|a, b| natural_cmp(a, b));
+	}
+	groups.sort_by(|(_, a), (_, b)| natural_cmp(a[0], b[0]));
+
+	Ok(groups)
+}
+
+/// Checks if the kicad value can be found in the part description
+///
+/// Values like `100n` or `4k7` are compared as numbers, everything else
+/// needs to be contained in the description or the mfr part.
+pub fn value_matches(value: &str, part: &Part) -> bool {
+	let value = value.trim();
+	if value.is_empty() || value == "~" {
+		return true
+	}
+
+	if let Some(num) = parse_value(value) {
+		return part.desc.split_whitespace()
+			.filter_map(parse_value)
+			.any(|n| (n - num).abs() <= num.abs() * 1e-6)
+	}
+
+	let value = value.to_lowercase();
+	part.desc.to_lowercase().contains(&value) ||
+		part.mfr_part.to_lowercase().contains(&value)
+}
+
+/// Parses values like `100nF`, `4.7u`, `4k7`, `10kΩ` or `0R`
+pub fn parse_value(s: &str) -> Option<f64> {
+	let s = s.trim()
+		.trim_end_matches(['F', 'H', 'Ω', '\u{2126}'])
+		.trim_end_matches("ohm");
+
+	let pos = s.find(|c: char| !c.is_ascii_digit() && c != '.')
+		.unwrap_or(s.len());
+	let (num, rest) = s.split_at(pos);
+	if num.is_empty() {
+		return None
+	}
+
+	let mut rest = rest.chars();
+	let (mult, decimals) = match rest.next() {
+		None => (1f64, ""),
+		Some(c) => {
+			let mult = match c {
+				'p' => 1e-12,
+				'n' => 1e-9,
+				'u' | 'µ' | 'μ' => 1e-6,
+				'm' => 1e-3,
+				'R' | 'r' => 1f64,
+				'k' | 'K' => 1e3,
+				'M' => 1e6,
+				_ => return None
+			};
+			(mult, rest.as_str())
+		}
+	};
+
+	// 4k7
+	if !decimals.is_empty() && !decimals.chars().all(|c| c.is_ascii_digit()) {
+		return None
+	}
+
+	let num: f64 = if decimals.is_empty() {
+		num.parse().ok()?
+	} else {
+		format!("{}.{}", num, decimals).parse().ok()?
+	};
+
+	Some(num * mult)
+}
+
+/// Reads a bom file as written by hand (usually ./bom.csv)
+pub fn read_custom_entries(
+	path: impl AsRef<Path>,
+	uses_comma: bool
+) -> Vec<CustomEntry> {
+	let raw_csv = fs::read_to_string(path).expect("failed to read csv");
+
+	let delimiter = if uses_comma {
+		b','
+	} else {
+		b';'
+	};
+
+	let reader = csv::ReaderBuilder::new()
+		.flexible(true)
+		.delimiter(delimiter)
+		.from_reader(raw_csv.trim().as_bytes());
+
+	reader.into_deserialize()
+		.collect::<Result<_, _>>()
+		.expect("failed to deserialize bom")
+}
+
 #[derive(Debug, Deserialize)]
-struct CustomEntry {
+pub struct CustomEntry {
 	/// Comma separated designator list
 	#[serde(rename = "Designator")]
-	designators: String,
+	pub designators: String,
 	#[serde(rename = "JLCPCB Part")]
-	jlcpcb_part: String
+	pub jlcpcb_part: String
 }
 
-#[derive(Debug, Serialize)]
-struct JlcpcbEntry {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JlcpcbEntry {
 	#[serde(rename = "Comment")]
-	comment: String,
+	pub comment: String,
 	/// Comma separated designator list
 	#[serde(rename = "Designator")]
-	designators: String,
+	pub designators: String,
 	#[serde(rename = "Footprint")]
-	footprint: String,
+	pub footprint: String,
 	#[serde(rename = "JLCPCB Part")]
-	jlcpcb_part: String
+	pub jlcpcb_part: String,
+	// older boms don't contain the quantity
+	#[serde(rename = "Quantity", default)]
+	pub quantity: usize
+}
+
+/// Splits a comma separated designator list
+pub fn split_designators(list: &str) -> impl Iterator<Item=&str> {
+	list.split(',')
+		.map(|d| d.trim())
+		.filter(|d| !d.is_empty())
 }
\ No newline at end of file
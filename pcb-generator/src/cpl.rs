@@ -1,4 +1,3 @@
-
 use crate::util::{create_build_dir, BUILD_DIR};
 
 use std::fs;
@@ -16,12 +15,53 @@ pub struct Cpl {
 }
 
 pub fn cpl(args: Cpl) {
-	let output = "./output";
 	create_build_dir();
 
+	let kicad_entries = read_kicad_entries(args.uses_comma)
+		.expect("did not find *top-pos.csv file");
+
+	let rotation_table = read_rotation_table();
+
+	let jlcpcb_entries: Vec<_> = kicad_entries.into_iter()
+		.map(|e| {
+			let entry = rotation_table.get(&e.designator)
+				.unwrap_or(RotationEntry::DEFAULT);
+
+			JlcpcbEntry {
+				designator: e.designator,
+				mid_x: format!("{}mm", e.pos_x + entry.pos_x),
+				mid_y: format!("{}mm", e.pos_y + entry.pos_y),
+				layer: "Top".into(),
+				rotation: e.rotation + entry.rotation
+			}
+		})
+		.collect();
+
+	let cpl_path = format!("{}/cpl.csv", BUILD_DIR);
+	let mut w = csv::Writer::from_path(&cpl_path).unwrap();
+	for entry in jlcpcb_entries {
+		w.serialize(entry).unwrap();
+	}
+	w.flush().unwrap();
+
+	println!("created {}", cpl_path);
+}
+
+/// Reads the position file exported by kicad from ./output
+///
+/// Returns None if no *top-pos.csv file exists.
+pub fn read_kicad_entries(uses_comma: bool) -> Option<Vec<KicadEntry>> {
+	read_kicad_entries_in(Path::new("./output"), uses_comma)
+}
+
+/// Like `read_kicad_entries` but from the given output dir
+pub fn read_kicad_entries_in(
+	output: &Path,
+	uses_comma: bool
+) -> Option<Vec<KicadEntry>> {
 	let mut entry_csv = None;
 
-	let read_dir = fs::read_dir(output).expect("could not read output");
+	let read_dir = fs::read_dir(output).ok()?;
 	for entry in read_dir {
 		let entry = entry.unwrap();
 		let name = entry.file_name().into_string().expect("entry not utf8");
@@ -34,9 +74,9 @@ pub fn cpl(args: Cpl) {
 		}
 	}
 
-	let raw_csv = entry_csv.expect("did not find *top-pos.csv file");
+	let raw_csv = entry_csv?;
 
-	let delimiter = if args.uses_comma {
+	let delimiter = if uses_comma {
 		b','
 	} else {
 		b';'
@@ -47,77 +87,61 @@ pub fn cpl(args: Cpl) {
 		.delimiter(delimiter)
 		.from_reader(raw_csv.trim().as_bytes());
 
-	let kicad_entries: Vec<KicadEntry> = reader.into_deserialize()
+	let kicad_entries = reader.into_deserialize()
 		.collect::<Result<_, _>>()
 		.expect("failed to deserialize cpl");
 
-	let rotation_table = read_rotation_table();
-
-	let jlcpcb_entries: Vec<_> = kicad_entries.into_iter()
-		.map(|e| {
-			let entry = rotation_table.get(&e.designator)
-				.unwrap_or(RotationEntry::DEFAULT);
-
-			JlcpcbEntry {
-				designator: e.designator,
-				mid_x: format!("{}mm", e.pos_x + entry.pos_x),
-				mid_y: format!("{}mm", e.pos_y + entry.pos_y),
-				layer: format!("Top"),
-				rotation: e.rotation + entry.rotation
-			}
-		})
-		.collect();
-
-	let cpl_path = format!("{}/cpl.csv", BUILD_DIR);
-	let mut w = csv::Writer::from_path(&cpl_path).unwrap();
-	for entry in jlcpcb_entries {
-		w.serialize(entry).unwrap();
-	}
-	w.flush().unwrap();
-
-	println!("created {}", cpl_path);
+	Some(kicad_entries)
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
-struct KicadEntry {
+pub struct KicadEntry {
 	#[serde(rename = "Ref")]
-	designator: String,
-	/// Comma separated designator list
+	pub designator: String,
 	#[serde(rename = "Val")]
-	#[allow(dead_code)]
-	value: String,
+	pub value: String,
 	#[serde(rename = "Package")]
-	package: String,
+	pub package: String,
 	// in mm
 	#[serde(rename = "PosX")]
-	pos_x: f32,
+	pub pos_x: f32,
 	// in mm
 	#[serde(rename = "PosY")]
-	pos_y: f32,
+	pub pos_y: f32,
 	// in deg
 	#[serde(rename = "Rot")]
-	rotation: f32,
+	pub rotation: f32,
 	#[serde(rename = "Side")]
-	side: String
+	pub side: String
 }
 
-#[derive(Debug, Serialize)]
-struct JlcpcbEntry {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JlcpcbEntry {
 	#[serde(rename = "Designator")]
-	designator: String,
+	pub designator: String,
 	/// Comma separated designator list
 	#[serde(rename = "Mid X")]
-	mid_x: String,
+	pub mid_x: String,
 	#[serde(rename = "Mid Y")]
-	mid_y: String,
+	pub mid_y: String,
 	#[serde(rename = "Layer")]
-	layer: String,
+	pub layer: String,
 	#[serde(rename = "Rotation")]
-	rotation: f32
+	pub rotation: f32
+}
+
+impl JlcpcbEntry {
+	/// Returns the position in mm
+	pub fn position(&self) -> (f32, f32) {
+		let parse = |s: &str| s.trim().trim_end_matches("mm").parse()
+			.expect("position not a number");
+
+		(parse(&self.mid_x), parse(&self.mid_y))
+	}
 }
 
-fn read_rotation_table() -> HashMap<String, RotationEntry> {
+pub fn read_rotation_table() -> HashMap<String, RotationEntry> {
 	let rotation_table_path = "./rotation-table.csv";
 	if !Path::new(rotation_table_path).is_file() {
 		return HashMap::new()
@@ -140,7 +164,7 @@ fn read_rotation_table() -> HashMap<String, RotationEntry> {
 }
 
 #[derive(Debug, Default, Deserialize)]
-struct RotationEntry {
+pub struct RotationEntry {
 	#[serde(rename = "Designator")]
 	designator: String,
 	#[serde(rename = "Rotation")]
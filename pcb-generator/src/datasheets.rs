@@ -0,0 +1,274 @@
+use crate::bom::{read_custom_entries, split_designators};
+use crate::partslist::find_in_parts_list;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::Parser;
+
+use serde::Serialize;
+
+use reqwest::blocking::Client;
+
+#[derive(Debug, Parser)]
+pub struct Datasheets {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// Where the datasheets get stored
+	#[clap(long, default_value = "../docs")]
+	docs_dir: String
+}
+
+pub fn datasheets(args: Datasheets) {
+	let custom_entries = read_custom_entries("./bom.csv", args.uses_comma);
+
+	fs::create_dir_all(&args.docs_dir).expect("could not create docs dir");
+
+	let ids: Vec<_> = custom_entries.iter()
+		.map(|e| e.jlcpcb_part.clone())
+		.collect();
+
+	let parts = find_in_parts_list(|p| ids.iter().any(|i| i == p.lcsc.trim()));
+
+	let sources: Vec<_> = custom_entries.into_iter()
+		.map(|entry| {
+			let Some(part) = parts.iter()
+				.find(|p| p.lcsc.trim() == entry.jlcpcb_part) else
+			{
+				panic!("could not find {:?}", entry);
+			};
+
+			Source {
+				lcsc: entry.jlcpcb_part,
+				mfr_part: part.mfr_part.trim().into(),
+				url: part.datasheet.trim().into(),
+				designators: entry.designators
+			}
+		})
+		.collect();
+
+	let fetched = fetch(&Client::new(), &sources, Path::new(&args.docs_dir));
+
+	let index_path = Path::new(&args.docs_dir).join("index.csv");
+	let mut w = csv::Writer::from_path(&index_path).unwrap();
+	for entry in &fetched.index {
+		w.serialize(entry).unwrap();
+	}
+	w.flush().unwrap();
+
+	println!("index written to {:?}", index_path);
+
+	for lcsc in &fetched.no_datasheet {
+		println!("{} has no datasheet", lcsc);
+	}
+	if !fetched.failed.is_empty() {
+		eprintln!(
+			"{} datasheets could not be downloaded",
+			fetched.failed.len()
+		);
+		for failure in &fetched.failed {
+			eprintln!("  {}", failure);
+		}
+		process::exit(1);
+	}
+}
+
+/// A bom line and where its datasheet can be downloaded
+#[derive(Debug, Clone)]
+struct Source {
+	lcsc: String,
+	mfr_part: String,
+	url: String,
+	designators: String
+}
+
+#[derive(Debug, Default)]
+struct Fetched {
+	index: Vec<IndexEntry>,
+	/// Parts without a datasheet url
+	no_datasheet: Vec<String>,
+	/// A line per datasheet which could not be downloaded
+	failed: Vec<String>
+}
+
+/// Downloads the datasheets which are not in `docs_dir` yet
+fn fetch(client: &Client, sources: &[Source], docs_dir: &Path) -> Fetched {
+	let mut fetched = Fetched::default();
+
+	for source in sources {
+		if source.url.is_empty() {
+			fetched.no_datasheet.push(source.lcsc.clone());
+			continue
+		}
+
+		let file = datasheet_file_name(&source.lcsc, &source.mfr_part);
+		let path = docs_dir.join(&file);
+
+		if path.is_file() {
+			println!("{} already exists", file);
+		} else {
+			let resp = client.get(&source.url)
+				.send()
+				.and_then(|r| r.error_for_status())
+				.and_then(|r| r.bytes());
+
+			match resp {
+				Ok(bytes) => {
+					fs::write(&path, bytes)
+						.expect("could not write datasheet");
+					println!("downloaded {}", file);
+				},
+				Err(e) => {
+					fetched.failed.push(format!(
+						"{} {}: {}", source.lcsc, source.url, e
+					));
+					continue
+				}
+			}
+		}
+
+		for designator in split_designators(&source.designators) {
+			fetched.index.push(IndexEntry {
+				designator: designator.into(),
+				jlcpcb_part: source.lcsc.clone(),
+				mfr_part: source.mfr_part.clone(),
+				file: file.clone()
+			});
+		}
+	}
+
+	fetched
+}
+
+/// Returns for example `C7214_CL21A106KOQNNNE.pdf`
+fn datasheet_file_name(lcsc: &str, mfr_part: &str) -> String {
+	let mfr_part: String = mfr_part.trim().chars()
+		.map(|c| match c {
+			'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+			_ => '_'
+		})
+		.collect();
+
+	format!("{}_{}.pdf", lcsc.trim(), mfr_part)
+}
+
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+	#[serde(rename = "Designator")]
+	designator: String,
+	#[serde(rename = "JLCPCB Part")]
+	jlcpcb_part: String,
+	#[serde(rename = "MFR.Part")]
+	mfr_part: String,
+	#[serde(rename = "File")]
+	file: String
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	const PDF: &[u8] = b"%PDF-1.4 stand-in";
+
+	/// Serves `/ok.pdf` and answers everything else with a 404
+	fn stand_in() -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let mut request = String::new();
+				let mut reader = BufReader::new(&stream);
+				reader.read_line(&mut request).unwrap();
+				// skip the headers
+				let mut line = String::new();
+				while reader.read_line(&mut line).unwrap() > 2 {
+					line.clear();
+				}
+
+				let (status, body) = if request.starts_with("GET /ok.pdf ") {
+					("200 OK", PDF)
+				} else {
+					("404 Not Found", &b"not found"[..])
+				};
+				write!(
+					stream,
+					"HTTP/1.1 {}\r\nContent-Length: {}\r\n\
+					Connection: close\r\n\r\n",
+					status, body.len()
+				).unwrap();
+				stream.write_all(body).unwrap();
+			}
+		});
+
+		format!("http://{}", addr)
+	}
+
+	fn source(lcsc: &str, url: String, designators: &str) -> Source {
+		Source {
+			lcsc: lcsc.into(),
+			mfr_part: "MFR/1".into(),
+			url,
+			designators: designators.into()
+		}
+	}
+
+	fn temp_dir(name: &str) -> std::path::PathBuf {
+		let dir = std::env::temp_dir()
+			.join(format!("pcb-generator-{}-{}", name, process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn downloads_and_reports_failures() {
+		let base = stand_in();
+		let dir = temp_dir("datasheets");
+		let sources = [
+			source("C1", format!("{}/ok.pdf", base), "C3, C4"),
+			source("C2", format!("{}/missing.pdf", base), "R1"),
+			source("C3", String::new(), "U1")
+		];
+
+		let fetched = fetch(&Client::new(), &sources, &dir);
+
+		let file = dir.join("C1_MFR_1.pdf");
+		assert_eq!(fs::read(&file).unwrap(), PDF);
+		let designators: Vec<_> = fetched.index.iter()
+			.map(|e| (e.designator.as_str(), e.file.as_str()))
+			.collect();
+		assert_eq!(
+			designators,
+			[("C3", "C1_MFR_1.pdf"), ("C4", "C1_MFR_1.pdf")]
+		);
+		assert_eq!(fetched.no_datasheet, ["C3"]);
+		assert_eq!(fetched.failed.len(), 1);
+		assert!(fetched.failed[0].starts_with("C2 "));
+		assert!(!dir.join("C2_MFR_1.pdf").exists());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn skips_existing_files() {
+		let dir = temp_dir("datasheets-existing");
+		fs::write(dir.join("C1_MFR_1.pdf"), "old").unwrap();
+		// nothing listens on this url, so it would fail if it was fetched
+		let sources = [source("C1", "http://127.0.0.1:1/x.pdf".into(), "C1")];
+
+		let fetched = fetch(&Client::new(), &sources, &dir);
+
+		assert!(fetched.failed.is_empty());
+		assert_eq!(fetched.index.len(), 1);
+		assert_eq!(fs::read(dir.join("C1_MFR_1.pdf")).unwrap(), b"old");
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
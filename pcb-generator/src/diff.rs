@@ -0,0 +1,281 @@
+use crate::bom::{self, read_custom_entries, split_designators};
+use crate::cpl::{self, read_kicad_entries_in};
+use crate::partslist::{find_in_parts_list, Part};
+use crate::util::{create_build_dir, extract_zip, extracted_root, BUILD_DIR};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+/// Compares two board revisions
+///
+/// A revision is either a project directory (containing `bom.csv` and
+/// optionally the generated `build` dir) or a release directory or zip
+/// which contains the generated `bom.csv` and `cpl.csv`.
+///
+/// Values are compared with the `Val` column of the kicad position file in
+/// `output`, so they are only known for project directories.
+#[derive(Debug, Parser)]
+pub struct Diff {
+	old: PathBuf,
+	new: PathBuf,
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// How many boards get ordered, used for the cost calculation
+	#[clap(long, default_value_t = 1)]
+	boards: usize,
+	/// Movements smaller than this (in mm) are ignored
+	#[clap(long, default_value_t = 0.001)]
+	tolerance: f32,
+	/// Rotations smaller than this (in deg) are ignored
+	#[clap(long, default_value_t = 0.01)]
+	rotation_tolerance: f32
+}
+
+/// Everything we know about a single designator
+#[derive(Debug, Default)]
+struct Placement {
+	jlcpcb_part: String,
+	/// The value from kicad
+	value: Option<String>,
+	footprint: Option<String>,
+	position: Option<(f32, f32)>,
+	rotation: Option<f32>
+}
+
+#[derive(Debug)]
+struct Revision {
+	placements: BTreeMap<String, Placement>
+}
+
+impl Revision {
+	fn read(dir: &Path, uses_comma: bool) -> Self {
+		let (source, generated, output) = if dir.join("cpl.csv").is_file() {
+			(None, dir.to_path_buf(), None)
+		} else {
+			(
+				Some(dir.join("bom.csv")),
+				dir.join("build"),
+				Some(dir.join("output"))
+			)
+		};
+
+		let mut placements: BTreeMap<String, Placement> = BTreeMap::new();
+
+		if let Some(source) = source.filter(|s| s.is_file()) {
+			for entry in read_custom_entries(source, uses_comma) {
+				for designator in split_designators(&entry.designators) {
+					placements.entry(designator.into()).or_default()
+						.jlcpcb_part = entry.jlcpcb_part.trim().into();
+				}
+			}
+		}
+
+		let generated_bom = generated.join("bom.csv");
+		if generated_bom.is_file() {
+			let reader = csv::Reader::from_path(&generated_bom)
+				.expect("could not read generated bom");
+			for entry in reader.into_deserialize() {
+				let entry: bom::JlcpcbEntry = entry
+					.expect("failed to deserialize generated bom");
+
+				for designator in split_designators(&entry.designators) {
+					let placement = placements.entry(designator.into())
+						.or_default();
+					// the source bom has priority
+					if placement.jlcpcb_part.is_empty() {
+						placement.jlcpcb_part = entry.jlcpcb_part.trim().into();
+					}
+					placement.footprint = Some(entry.footprint.clone());
+				}
+			}
+		}
+
+		let generated_cpl = generated.join("cpl.csv");
+		if generated_cpl.is_file() {
+			let reader = csv::Reader::from_path(&generated_cpl)
+				.expect("could not read generated cpl");
+			for entry in reader.into_deserialize() {
+				let entry: cpl::JlcpcbEntry = entry
+					.expect("failed to deserialize generated cpl");
+
+				let placement = placements.entry(entry.designator.clone())
+					.or_default();
+				placement.position = Some(entry.position());
+				placement.rotation = Some(entry.rotation);
+			}
+		}
+
+		let kicad_entries = output
+			.and_then(|o| read_kicad_entries_in(&o, uses_comma))
+			.unwrap_or_default();
+		for entry in kicad_entries {
+			// only parts which are in a bom get a placement
+			if let Some(placement) = placements.get_mut(&entry.designator) {
+				placement.value = Some(entry.value);
+			}
+		}
+
+		// designators which are only in the cpl don't get assembled
+		placements.retain(|_, p| !p.jlcpcb_part.is_empty());
+		assert!(!placements.is_empty(), "nothing found in {:?}", dir);
+
+		Self { placements }
+	}
+
+	fn fill_from_parts(&mut self, parts: &HashMap<String, Part>) {
+		for placement in self.placements.values_mut() {
+			let Some(part) = parts.get(&placement.jlcpcb_part) else {
+				continue
+			};
+
+			placement.footprint.get_or_insert_with(|| part.package.clone());
+		}
+	}
+
+	/// Returns None if a part could not be found or has no price
+	fn cost(
+		&self,
+		parts: &HashMap<String, Part>,
+		boards: usize
+	) -> Option<f64> {
+		let mut quantities: HashMap<&str, usize> = HashMap::new();
+		for placement in self.placements.values() {
+			*quantities.entry(&placement.jlcpcb_part).or_default() += boards;
+		}
+
+		quantities.into_iter()
+			.map(|(id, qty)| {
+				let price = parts.get(id)?.unit_price(qty)?;
+				Some(price * qty as f64)
+			})
+			.sum()
+	}
+}
+
+/// Extracts a release zip, directories are returned as they are
+fn unpack(path: &Path, name: &str) -> PathBuf {
+	if !path.is_file() {
+		return path.to_path_buf()
+	}
+
+	create_build_dir();
+	let dir = format!("{}/diff/{}", BUILD_DIR, name);
+	if Path::new(&dir).is_dir() {
+		fs::remove_dir_all(&dir).expect("could not clean diff dir");
+	}
+	fs::create_dir_all(&dir).expect("could not create diff dir");
+
+	extract_zip(path.to_str().expect("path not utf8"), &dir);
+	extracted_root(Path::new(&dir))
+}
+
+pub fn diff(args: Diff) {
+	let old_dir = unpack(&args.old, "old");
+	let new_dir = unpack(&args.new, "new");
+	let mut old = Revision::read(&old_dir, args.uses_comma);
+	let mut new = Revision::read(&new_dir, args.uses_comma);
+
+	let ids: BTreeSet<_> = old.placements.values()
+		.chain(new.placements.values())
+		.map(|p| p.jlcpcb_part.clone())
+		.collect();
+	let parts: HashMap<_, _> = find_in_parts_list(|p| {
+		ids.contains(p.lcsc.trim())
+	})
+		.into_iter()
+		.map(|p| (p.lcsc.trim().to_string(), p))
+		.collect();
+
+	old.fill_from_parts(&parts);
+	new.fill_from_parts(&parts);
+
+	let mut added = vec![];
+	let mut removed = vec![];
+	let mut resourced = vec![];
+	let mut value_changed = vec![];
+	let mut package_changed = vec![];
+	let mut moved = vec![];
+	let mut rotated = vec![];
+
+	for (designator, o) in &old.placements {
+		let Some(n) = new.placements.get(designator) else {
+			removed.push(format!("{} {}", designator, o.jlcpcb_part));
+			continue
+		};
+
+		if o.jlcpcb_part != n.jlcpcb_part {
+			resourced.push(format!(
+				"{} {} -> {}", designator, o.jlcpcb_part, n.jlcpcb_part
+			));
+		}
+
+		if let (Some(a), Some(b)) = (&o.value, &n.value) {
+			if a.trim() != b.trim() {
+				value_changed.push(format!(
+					"{} {:?} -> {:?}", designator, a, b
+				));
+			}
+		}
+
+		if let (Some(a), Some(b)) = (&o.footprint, &n.footprint) {
+			if a.trim() != b.trim() {
+				package_changed.push(format!(
+					"{} {:?} -> {:?}", designator, a, b
+				));
+			}
+		}
+
+		if let (Some(a), Some(b)) = (o.position, n.position) {
+			if (a.0 - b.0).abs() > args.tolerance ||
+				(a.1 - b.1).abs() > args.tolerance
+			{
+				moved.push(format!(
+					"{} ({}, {}) -> ({}, {})", designator, a.0, a.1, b.0, b.1
+				));
+			}
+		}
+
+		if let (Some(a), Some(b)) = (o.rotation, n.rotation) {
+			let d = (a - b).rem_euclid(360f32);
+			if d.min(360f32 - d) > args.rotation_tolerance {
+				rotated.push(format!("{} {} -> {}", designator, a, b));
+			}
+		}
+	}
+
+	for (designator, n) in &new.placements {
+		if !old.placements.contains_key(designator) {
+			added.push(format!("{} {}", designator, n.jlcpcb_part));
+		}
+	}
+
+	print_section("added", &added);
+	print_section("removed", &removed);
+	print_section("re-sourced", &resourced);
+	print_section("value changed", &value_changed);
+	print_section("package changed", &package_changed);
+	print_section("moved", &moved);
+	print_section("rotated", &rotated);
+
+	match (old.cost(&parts, args.boards), new.cost(&parts, args.boards)) {
+		(Some(o), Some(n)) => println!(
+			"cost for {} boards: {:.4} -> {:.4} ({:+.4})",
+			args.boards, o, n, n - o
+		),
+		_ => println!("cost: unknown, not every part has a price")
+	}
+}
+
+fn print_section(name: &str, lines: &[String]) {
+	if lines.is_empty() {
+		return
+	}
+
+	println!("{} ({}):", name, lines.len());
+	for line in lines {
+		println!("  {}", line);
+	}
+}
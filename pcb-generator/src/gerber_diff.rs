@@ -0,0 +1,236 @@
+use crate::gerber_image::{
+	parse_excellon, parse_gerber, rasterize, Bitmap, Frame, Image
+};
+use crate::util::{create_build_dir, extract_zip, extracted_root, BUILD_DIR};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Parser;
+
+const UNCHANGED: [u8; 3] = [0x90, 0x90, 0x90];
+const ADDED: [u8; 3] = [0x20, 0xd0, 0x20];
+const REMOVED: [u8; 3] = [0xe0, 0x20, 0x20];
+const BACKGROUND: [u8; 3] = [0x10, 0x10, 0x10];
+
+/// Rasterizes every layer of two gerber sets and writes an image per
+/// layer with added copper in green and removed copper in red
+#[derive(Debug, Parser)]
+pub struct GerberDiff {
+	/// Directory or zip with the old gerber and drill files
+	old: PathBuf,
+	/// Directory or zip with the new gerber and drill files
+	new: PathBuf,
+	/// Pixels per mm
+	#[clap(long, default_value_t = 20f64)]
+	resolution: f64,
+	/// Layers which are expected to change, for example F_Cu or PTH
+	#[clap(long)]
+	expect: Vec<String>,
+	/// A layer with fewer changed pixels counts as unchanged
+	#[clap(long, default_value_t = 0)]
+	threshold: usize
+}
+
+pub fn gerber_diff(args: GerberDiff) {
+	create_build_dir();
+	let out_dir = format!("{}/gerber-diff", BUILD_DIR);
+	if Path::new(&out_dir).is_dir() {
+		fs::remove_dir_all(&out_dir).expect("could not clean gerber-diff dir");
+	}
+	fs::create_dir_all(&out_dir).expect("could not create gerber-diff dir");
+
+	let old = read_layers(&args.old, &format!("{}/old", out_dir));
+	let new = read_layers(&args.new, &format!("{}/new", out_dir));
+
+	let keys: BTreeSet<_> = old.keys().chain(new.keys()).cloned().collect();
+	let mut unexpected = vec![];
+
+	println!("{:<16} {:>10} {:>10} {:>10}", "layer", "added", "removed", "mm2");
+
+	for key in keys {
+		let empty = Image::default();
+		let a = old.get(&key).unwrap_or(&empty);
+		let b = new.get(&key).unwrap_or(&empty);
+
+		let bounds = [a.bounds(), b.bounds()].into_iter()
+			.flatten()
+			.reduce(|(a0, a1), (b0, b1)| (
+				(a0.0.min(b0.0), a0.1.min(b0.1)),
+				(a1.0.max(b1.0), a1.1.max(b1.1))
+			));
+		let Some((min, max)) = bounds else { continue };
+
+		let margin = 1f64;
+		let frame = Frame {
+			min: (min.0 - margin, min.1 - margin),
+			max: (max.0 + margin, max.1 + margin),
+			resolution: args.resolution
+		};
+
+		let a = rasterize(a, frame);
+		let b = rasterize(b, frame);
+		let image_path = format!("{}/{}.png", out_dir, key);
+		let (added, removed) = write_diff(&a, &b, &image_path);
+
+		let changed = added + removed;
+		let area = changed as f64 / (args.resolution * args.resolution);
+		let expected = args.expect.contains(&key);
+		let status = match (changed > args.threshold, expected) {
+			(false, _) => "",
+			(true, true) => "expected",
+			(true, false) => {
+				unexpected.push(key.clone());
+				"UNEXPECTED"
+			}
+		};
+
+		let missing = match (old.contains_key(&key), new.contains_key(&key)) {
+			(false, _) => " (only new)",
+			(_, false) => " (only old)",
+			_ => ""
+		};
+
+		println!(
+			"{:<16} {:>10} {:>10} {:>10.3} {}{}",
+			key, added, removed, area, status, missing
+		);
+	}
+
+	println!("images written to {}", out_dir);
+
+	if !unexpected.is_empty() {
+		eprintln!("unexpected changes in {}", unexpected.join(", "));
+		process::exit(1);
+	}
+}
+
+/// Reads every gerber and drill file, keyed by what follows the project
+/// name (`jag-v1-F_Cu.gbr` becomes `F_Cu`, `jag-v1-PTH-drl_map.gbr`
+/// becomes `PTH-drl_map`) or by the extension for protel names
+/// (`jag-v1.gtl` becomes `GTL`)
+fn read_layers(path: &Path, tmp_dir: &str) -> BTreeMap<String, Image> {
+	let dir = if path.is_file() {
+		extract_zip(path.to_str().expect("path not utf8"), tmp_dir);
+		extracted_root(Path::new(tmp_dir))
+	} else {
+		path.to_path_buf()
+	};
+
+	let read_dir = fs::read_dir(&dir)
+		.unwrap_or_else(|e| panic!("could not read {:?} {:?}", dir, e));
+	let mut files = vec![];
+	for entry in read_dir {
+		let path = entry.unwrap().path();
+		let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+			continue
+		};
+		let ext = ext.to_lowercase();
+		let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+		files.push((path, ext, stem));
+	}
+
+	let prefix = project_prefix(files.iter()
+		.filter(|(_, ext, _)| matches!(ext.as_str(), "drl" | "xln" | "gbr"))
+		.map(|(_, _, stem)| stem.as_str()));
+
+	let mut layers = BTreeMap::new();
+	let mut sources: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+	for (path, ext, stem) in files {
+		let layer_key = stem.strip_prefix(&prefix).unwrap_or(&stem).to_string();
+
+		let (key, image) = match ext.as_str() {
+			"drl" | "xln" => (
+				layer_key,
+				parse_excellon(&fs::read_to_string(&path).expect("read drill"))
+			),
+			"gbr" => (
+				layer_key,
+				parse_gerber(&fs::read_to_string(&path).expect("read gerber"))
+			),
+			// the extension already names the layer
+			"gtl" | "gbl" | "gts" | "gbs" | "gto" | "gbo" | "gtp" | "gbp" |
+			"gko" | "gm1" | "g1" | "g2" | "g3" | "g4" => (
+				ext.to_uppercase(),
+				parse_gerber(&fs::read_to_string(&path).expect("read gerber"))
+			),
+			_ => continue
+		};
+
+		if let Some(other) = sources.get(&key) {
+			eprintln!("{:?} and {:?} are both layer {}", other, path, key);
+			process::exit(1);
+		}
+		sources.insert(key.clone(), path);
+		layers.insert(key, image);
+	}
+
+	assert!(!layers.is_empty(), "no gerber files found in {:?}", dir);
+
+	layers
+}
+
+/// The common start of the file names up to and including the last `-`,
+/// kicad names every file `<project>-<layer>`
+fn project_prefix<'a>(stems: impl Iterator<Item = &'a str>) -> String {
+	let mut prefix: Option<&str> = None;
+	for stem in stems {
+		prefix = Some(match prefix {
+			None => stem,
+			Some(p) => {
+				let len = p.char_indices().zip(stem.chars())
+					.find(|((_, a), b)| a != b)
+					.map(|((i, _), _)| i)
+					.unwrap_or(p.len().min(stem.len()));
+				&p[..len]
+			}
+		});
+	}
+
+	let prefix = prefix.unwrap_or("");
+	match prefix.rfind('-') {
+		Some(i) => prefix[..=i].to_string(),
+		None => String::new()
+	}
+}
+
+/// Returns (added, removed) pixels
+fn write_diff(a: &Bitmap, b: &Bitmap, path: &str) -> (usize, usize) {
+	let mut added = 0;
+	let mut removed = 0;
+	let mut rgb = Vec::with_capacity(a.data.len() * 3);
+
+	for (a, b) in a.data.iter().zip(&b.data) {
+		let color = match (a, b) {
+			(true, true) => UNCHANGED,
+			(false, true) => {
+				added += 1;
+				ADDED
+			},
+			(true, false) => {
+				removed += 1;
+				REMOVED
+			},
+			(false, false) => BACKGROUND
+		};
+		rgb.extend_from_slice(&color);
+	}
+
+	let file = File::create(path).expect("could not create image");
+	let mut encoder = png::Encoder::new(
+		BufWriter::new(file),
+		a.width as u32,
+		a.height as u32
+	);
+	encoder.set_color(png::ColorType::Rgb);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.write_header()
+		.and_then(|mut w| w.write_image_data(&rgb))
+		.expect("could not write image");
+
+	(added, removed)
+}
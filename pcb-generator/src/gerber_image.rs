@@ -0,0 +1,810 @@
+//! Parses gerber (RS-274X) and excellon drill files into polygons and
+//! rasterizes them
+//!
+//! Only the subset kicad writes is supported, everything is converted
+//! into polygons in mm (y pointing up).
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+pub type Point = (f64, f64);
+
+/// How many segments are used to approximate a full circle
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Polygon {
+	pub points: Vec<Point>,
+	/// false if the polygon clears what was drawn before
+	pub dark: bool
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Image {
+	/// in drawing order
+	pub polygons: Vec<Polygon>
+}
+
+impl Image {
+	pub fn bounds(&self) -> Option<(Point, Point)> {
+		self.polygons.iter()
+			.flat_map(|p| p.points.iter())
+			.fold(None, |b, &(x, y)| match b {
+				None => Some(((x, y), (x, y))),
+				Some(((x0, y0), (x1, y1))) => {
+					Some(((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y))))
+				}
+			})
+	}
+
+	fn push(&mut self, points: Vec<Point>, dark: bool) {
+		if points.len() >= 3 {
+			self.polygons.push(Polygon { points, dark });
+		}
+	}
+
+	/// Draws a line with a round aperture
+	fn stroke(&mut self, a: Point, b: Point, d: f64, dark: bool) {
+		let r = d / 2f64;
+		let angle = (b.1 - a.1).atan2(b.0 - a.0);
+
+		// two half circles connected
+		let half = CIRCLE_SEGMENTS / 2;
+		let mut points = Vec::with_capacity(half * 2 + 2);
+		for i in 0..=half {
+			let t = angle + PI / 2f64 + PI * i as f64 / half as f64;
+			points.push((a.0 + r * t.cos(), a.1 + r * t.sin()));
+		}
+		for i in 0..=half {
+			let t = angle - PI / 2f64 + PI * i as f64 / half as f64;
+			points.push((b.0 + r * t.cos(), b.1 + r * t.sin()));
+		}
+
+		self.push(points, dark);
+	}
+
+	/// Draws a line with a polygon aperture (the convex hull)
+	fn stroke_polygon(
+		&mut self,
+		a: Point,
+		b: Point,
+		shape: &[Point],
+		dark: bool
+	) {
+		let points: Vec<_> = shape.iter()
+			.map(|p| (a.0 + p.0, a.1 + p.1))
+			.chain(shape.iter().map(|p| (b.0 + p.0, b.1 + p.1)))
+			.collect();
+
+		self.push(convex_hull(points), dark);
+	}
+}
+
+fn circle(center: Point, d: f64, segments: usize) -> Vec<Point> {
+	let r = d / 2f64;
+	(0..segments)
+		.map(|i| {
+			let t = 2f64 * PI * i as f64 / segments as f64;
+			(center.0 + r * t.cos(), center.1 + r * t.sin())
+		})
+		.collect()
+}
+
+fn rect(center: Point, w: f64, h: f64) -> Vec<Point> {
+	let (x, y) = center;
+	let (w, h) = (w / 2f64, h / 2f64);
+	vec![(x - w, y - h), (x + w, y - h), (x + w, y + h), (x - w, y + h)]
+}
+
+fn obround(center: Point, w: f64, h: f64) -> Vec<Point> {
+	let d = w.min(h);
+	let offset = (w.max(h) - d) / 2f64;
+	let (a, b) = if w > h {
+		((center.0 - offset, center.1), (center.0 + offset, center.1))
+	} else {
+		((center.0, center.1 - offset), (center.0, center.1 + offset))
+	};
+
+	let mut img = Image::default();
+	img.stroke(a, b, d, true);
+	img.polygons.pop().map(|p| p.points).unwrap_or_default()
+}
+
+fn rotate((x, y): Point, deg: f64) -> Point {
+	let (sin, cos) = deg.to_radians().sin_cos();
+	(x * cos - y * sin, x * sin + y * cos)
+}
+
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+	points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	points.dedup();
+	if points.len() < 3 {
+		return points
+	}
+
+	let cross = |o: Point, a: Point, b: Point| {
+		(a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+	};
+
+	let mut hull: Vec<Point> = vec![];
+	for pass in 0..2 {
+		let start = hull.len();
+		let iter: Box<dyn Iterator<Item=&Point>> = if pass == 0 {
+			Box::new(points.iter())
+		} else {
+			Box::new(points.iter().rev())
+		};
+		for &p in iter {
+			while hull.len() >= start + 2 &&
+				cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0f64
+			{
+				hull.pop();
+			}
+			hull.push(p);
+		}
+		hull.pop();
+	}
+
+	hull
+}
+
+/// Samples an arc from a to b around center
+fn arc_points(
+	a: Point,
+	b: Point,
+	center: Point,
+	clockwise: bool
+) -> Vec<Point> {
+	let r = ((a.0 - center.0).powi(2) + (a.1 - center.1).powi(2)).sqrt();
+	let start = (a.1 - center.1).atan2(a.0 - center.0);
+	let end = (b.1 - center.1).atan2(b.0 - center.0);
+
+	let mut sweep = end - start;
+	if clockwise {
+		if sweep >= 0f64 {
+			sweep -= 2f64 * PI;
+		}
+	} else if sweep <= 0f64 {
+		sweep += 2f64 * PI;
+	}
+
+	let steps = ((sweep.abs() / (2f64 * PI)) * CIRCLE_SEGMENTS as f64)
+		.ceil()
+		.max(1f64) as usize;
+
+	(1..=steps)
+		.map(|i| {
+			let t = start + sweep * i as f64 / steps as f64;
+			(center.0 + r * t.cos(), center.1 + r * t.sin())
+		})
+		.collect()
+}
+
+#[derive(Debug, Clone)]
+enum Aperture {
+	Circle(f64),
+	/// a polygon relative to the center
+	Shape(Vec<Point>),
+	/// macros can contain multiple polygons
+	Macro(Vec<Polygon>)
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+	/// the body of the macro without the name
+	statements: Vec<String>
+}
+
+impl Macro {
+	fn instantiate(&self, params: &[f64]) -> Vec<Polygon> {
+		let mut vars: HashMap<usize, f64> = params.iter()
+			.enumerate()
+			.map(|(i, v)| (i + 1, *v))
+			.collect();
+		let mut polygons = vec![];
+
+		for statement in &self.statements {
+			let statement = statement.trim();
+			// comment
+			if statement.starts_with('0') && !statement.contains(',') {
+				continue
+			}
+
+			if let Some(rest) = statement.strip_prefix('$') {
+				if let Some((var, expr)) = rest.split_once('=') {
+					if let Ok(var) = var.trim().parse() {
+						vars.insert(var, eval_expr(expr, &vars));
+					}
+				}
+				continue
+			}
+
+			let mut parts = statement.split(',');
+			let Some(code) = parts.next() else { continue };
+			let args: Vec<f64> = parts.map(|p| eval_expr(p, &vars)).collect();
+			let arg = |i: usize| args.get(i).copied().unwrap_or(0f64);
+			let dark = arg(0) != 0f64;
+
+			let (points, rotation) = match code.trim() {
+				"1" => (
+					circle((arg(2), arg(3)), arg(1), CIRCLE_SEGMENTS),
+					arg(4)
+				),
+				"20" => {
+					let (s, e) = ((arg(2), arg(3)), (arg(4), arg(5)));
+					let w = arg(1) / 2f64;
+					let angle = (e.1 - s.1).atan2(e.0 - s.0);
+					let (sin, cos) = angle.sin_cos();
+					let n = (-sin * w, cos * w);
+					(vec![
+						(s.0 + n.0, s.1 + n.1),
+						(e.0 + n.0, e.1 + n.1),
+						(e.0 - n.0, e.1 - n.1),
+						(s.0 - n.0, s.1 - n.1)
+					], arg(6))
+				},
+				"21" => (rect((arg(3), arg(4)), arg(1), arg(2)), arg(5)),
+				"4" => {
+					let n = arg(1) as usize;
+					let points = (0..=n)
+						.map(|i| (arg(2 + i * 2), arg(3 + i * 2)))
+						.collect();
+					(points, arg(4 + n * 2))
+				},
+				"5" => {
+					let n = arg(1) as usize;
+					(circle((arg(2), arg(3)), arg(4), n.max(3)), arg(5))
+				},
+				_ => continue
+			};
+
+			polygons.push(Polygon {
+				points: points.into_iter()
+					.map(|p| rotate(p, rotation))
+					.collect(),
+				dark
+			});
+		}
+
+		polygons
+	}
+}
+
+/// Evaluates macro expressions like `$1+$1` or `$2x0.5`
+fn eval_expr(expr: &str, vars: &HashMap<usize, f64>) -> f64 {
+	fn parse_sum(
+		s: &[char],
+		i: &mut usize,
+		vars: &HashMap<usize, f64>
+	) -> f64 {
+		let mut v = parse_product(s, i, vars);
+		while *i < s.len() {
+			match s[*i] {
+				'+' => { *i += 1; v += parse_product(s, i, vars); },
+				'-' => { *i += 1; v -= parse_product(s, i, vars); },
+				_ => break
+			}
+		}
+		v
+	}
+
+	fn parse_product(
+		s: &[char],
+		i: &mut usize,
+		vars: &HashMap<usize, f64>
+	) -> f64 {
+		let mut v = parse_atom(s, i, vars);
+		while *i < s.len() {
+			match s[*i] {
+				'x' | 'X' => { *i += 1; v *= parse_atom(s, i, vars); },
+				'/' => { *i += 1; v /= parse_atom(s, i, vars); },
+				_ => break
+			}
+		}
+		v
+	}
+
+	fn parse_atom(
+		s: &[char],
+		i: &mut usize,
+		vars: &HashMap<usize, f64>
+	) -> f64 {
+		match s.get(*i) {
+			Some('(') => {
+				*i += 1;
+				let v = parse_sum(s, i, vars);
+				*i += 1;
+				v
+			},
+			Some('-') => {
+				*i += 1;
+				-parse_atom(s, i, vars)
+			},
+			Some('+') => {
+				*i += 1;
+				parse_atom(s, i, vars)
+			},
+			Some('$') => {
+				*i += 1;
+				let start = *i;
+				while *i < s.len() && s[*i].is_ascii_digit() {
+					*i += 1;
+				}
+				let n: String = s[start..*i].iter().collect();
+				n.parse().ok()
+					.and_then(|n| vars.get(&n).copied())
+					.unwrap_or(0f64)
+			},
+			_ => {
+				let start = *i;
+				while *i < s.len() && (s[*i].is_ascii_digit() || s[*i] == '.') {
+					*i += 1;
+				}
+				let n: String = s[start..*i].iter().collect();
+				n.parse().unwrap_or(0f64)
+			}
+		}
+	}
+
+	let chars: Vec<char> = expr.chars()
+		.filter(|c| !c.is_whitespace())
+		.collect();
+	parse_sum(&chars, &mut 0, vars)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interpolation {
+	Linear,
+	Clockwise,
+	CounterClockwise
+}
+
+struct GerberState {
+	image: Image,
+	apertures: HashMap<u32, Aperture>,
+	macros: HashMap<String, Macro>,
+	aperture: Option<u32>,
+	pos: Point,
+	interpolation: Interpolation,
+	dark: bool,
+	region: Option<Vec<Vec<Point>>>,
+	/// number of decimals of the x and y coordinates
+	decimals: (i32, i32),
+	/// mm per unit
+	unit: f64
+}
+
+pub fn parse_gerber(raw: &str) -> Image {
+	let mut state = GerberState {
+		image: Image::default(),
+		apertures: HashMap::new(),
+		macros: HashMap::new(),
+		aperture: None,
+		pos: (0f64, 0f64),
+		interpolation: Interpolation::Linear,
+		dark: true,
+		region: None,
+		decimals: (6, 6),
+		unit: 1f64
+	};
+
+	let mut rest = raw;
+	while let Some(c) = rest.chars().next() {
+		if c == '%' {
+			let end = rest[1..].find('%').map(|e| e + 1).unwrap_or(rest.len());
+			state.extended(&rest[1..end]);
+			rest = rest.get(end + 1..).unwrap_or("");
+		} else {
+			let end = rest.find(['*', '%']).unwrap_or(rest.len());
+			let word = rest[..end].trim();
+			if !word.is_empty() {
+				state.operation(word);
+			}
+			rest = if rest[end..].starts_with('*') {
+				&rest[end + 1..]
+			} else {
+				&rest[end..]
+			};
+		}
+	}
+
+	state.image
+}
+
+impl GerberState {
+	fn extended(&mut self, block: &str) {
+		let statements: Vec<_> = block.split('*')
+			.map(|s| s.trim())
+			.filter(|s| !s.is_empty())
+			.collect();
+		let Some(first) = statements.first() else { return };
+
+		if let Some(fs) = first.strip_prefix("FS") {
+			// FSLAX46Y46
+			let digits = |axis: char| fs.find(axis)
+				.and_then(|i| fs[i + 2..].chars().next())
+				.and_then(|c| c.to_digit(10))
+				.map(|d| d as i32);
+			self.decimals = (
+				digits('X').unwrap_or(6),
+				digits('Y').unwrap_or(6)
+			);
+		} else if let Some(mo) = first.strip_prefix("MO") {
+			self.unit = if mo.starts_with("IN") { 25.4 } else { 1f64 };
+		} else if let Some(lp) = first.strip_prefix("LP") {
+			self.dark = lp.starts_with('D');
+		} else if let Some(name) = first.strip_prefix("AM") {
+			self.macros.insert(name.into(), Macro {
+				statements: statements[1..].iter()
+					.map(|s| s.to_string())
+					.collect()
+			});
+		} else if let Some(ad) = first.strip_prefix("ADD") {
+			self.define_aperture(ad);
+		}
+	}
+
+	fn define_aperture(&mut self, ad: &str) {
+		let num_end = ad.find(|c: char| !c.is_ascii_digit())
+			.unwrap_or(ad.len());
+		let Ok(num) = ad[..num_end].parse::<u32>() else { return };
+		let def = &ad[num_end..];
+		let (name, params) = def.split_once(',').unwrap_or((def, ""));
+		let params: Vec<f64> = params.split('X')
+			.filter_map(|p| p.trim().parse().ok())
+			.collect();
+		let unit = self.unit;
+		let param = |i: usize| params.get(i).copied().unwrap_or(0f64) * unit;
+
+		let aperture = match name {
+			"C" => Aperture::Circle(param(0)),
+			"R" => Aperture::Shape(rect((0f64, 0f64), param(0), param(1))),
+			"O" => Aperture::Shape(obround((0f64, 0f64), param(0), param(1))),
+			"P" => {
+				let n = params.get(1).copied().unwrap_or(3f64) as usize;
+				let rotation = params.get(2).copied().unwrap_or(0f64);
+				Aperture::Shape(
+					circle((0f64, 0f64), param(0), n.max(3)).into_iter()
+						.map(|p| rotate(p, rotation))
+						.collect()
+				)
+			},
+			name => {
+				let Some(m) = self.macros.get(name) else { return };
+				let polygons = m.instantiate(&params)
+					.into_iter()
+					.map(|p| Polygon {
+						points: p.points.into_iter()
+							.map(|(x, y)| (x * unit, y * unit))
+							.collect(),
+						dark: p.dark
+					})
+					.collect();
+				Aperture::Macro(polygons)
+			}
+		};
+
+		self.apertures.insert(num, aperture);
+	}
+
+	fn coord(&self, word: &str, letter: char, decimals: i32) -> Option<f64> {
+		let start = word.find(letter)? + 1;
+		let end = word[start..]
+			.find(|c: char| {
+				!(c.is_ascii_digit() || matches!(c, '-' | '+' | '.'))
+			})
+			.map(|e| start + e)
+			.unwrap_or(word.len());
+		let raw = &word[start..end];
+		let v: f64 = raw.parse().ok()?;
+		let v = if raw.contains('.') { v } else { v / 10f64.powi(decimals) };
+		Some(v * self.unit)
+	}
+
+	fn operation(&mut self, word: &str) {
+		if word.starts_with("G04") || word.starts_with("M0") {
+			return
+		}
+
+		let mut word = word;
+		// G codes at the start
+		while let Some(rest) = word.strip_prefix('G') {
+			let end = rest.find(|c: char| !c.is_ascii_digit())
+				.unwrap_or(rest.len());
+			match &rest[..end] {
+				"01" | "1" => self.interpolation = Interpolation::Linear,
+				"02" | "2" => self.interpolation = Interpolation::Clockwise,
+				"03" | "3" => {
+					self.interpolation = Interpolation::CounterClockwise
+				},
+				"36" => self.region = Some(vec![]),
+				"37" => self.end_region(),
+				_ => {}
+			}
+			word = &rest[end..];
+		}
+
+		if word.is_empty() {
+			return
+		}
+
+		let d_code = word.rfind('D')
+			.and_then(|i| word[i + 1..].parse::<u32>().ok());
+
+		// aperture selection
+		if word.starts_with('D') {
+			if let Some(d) = d_code.filter(|d| *d >= 10) {
+				self.aperture = Some(d);
+				return
+			}
+		}
+
+		let x = self.coord(word, 'X', self.decimals.0).unwrap_or(self.pos.0);
+		let y = self.coord(word, 'Y', self.decimals.1).unwrap_or(self.pos.1);
+		let i = self.coord(word, 'I', self.decimals.0).unwrap_or(0f64);
+		let j = self.coord(word, 'J', self.decimals.1).unwrap_or(0f64);
+		let target = (x, y);
+
+		match d_code {
+			Some(1) => self.interpolate(target, (i, j)),
+			Some(2) => {
+				if let Some(region) = &mut self.region {
+					region.push(vec![target]);
+				}
+			},
+			Some(3) => self.flash(target),
+			_ => {}
+		}
+
+		self.pos = target;
+	}
+
+	fn interpolate(&mut self, target: Point, offset: Point) {
+		let points = match self.interpolation {
+			Interpolation::Linear => vec![target],
+			dir => {
+				let center = (self.pos.0 + offset.0, self.pos.1 + offset.1);
+				arc_points(
+					self.pos, target, center,
+					dir == Interpolation::Clockwise
+				)
+			}
+		};
+
+		if let Some(region) = &mut self.region {
+			if region.is_empty() {
+				region.push(vec![self.pos]);
+			}
+			region.last_mut().unwrap().extend(points);
+			return
+		}
+
+		let Some(aperture) = self.aperture.and_then(|a| self.apertures.get(&a))
+		else {
+			return
+		};
+
+		let mut prev = self.pos;
+		for p in points {
+			match aperture {
+				Aperture::Circle(d) => {
+					self.image.stroke(prev, p, *d, self.dark)
+				},
+				Aperture::Shape(shape) => {
+					let shape = shape.clone();
+					self.image.stroke_polygon(prev, p, &shape, self.dark);
+				},
+				// kicad doesn't draw with macros
+				Aperture::Macro(_) => {}
+			}
+			prev = p;
+		}
+	}
+
+	fn flash(&mut self, at: Point) {
+		let Some(aperture) = self.aperture.and_then(|a| self.apertures.get(&a))
+		else {
+			return
+		};
+
+		let offset = |points: &[Point]| -> Vec<Point> {
+			points.iter().map(|p| (at.0 + p.0, at.1 + p.1)).collect()
+		};
+
+		let polygons = match aperture {
+			Aperture::Circle(d) => {
+				vec![(circle(at, *d, CIRCLE_SEGMENTS), true)]
+			},
+			Aperture::Shape(shape) => vec![(offset(shape), true)],
+			Aperture::Macro(polygons) => polygons.iter()
+				.map(|p| (offset(&p.points), p.dark))
+				.collect()
+		};
+
+		for (points, dark) in polygons {
+			// clear inside a dark aperture stays clear
+			self.image.push(points, dark == self.dark);
+		}
+	}
+
+	fn end_region(&mut self) {
+		let Some(region) = self.region.take() else { return };
+		for contour in region {
+			self.image.push(contour, self.dark);
+		}
+	}
+}
+
+/// Parses an excellon drill file, every hole becomes a dark circle and
+/// every slot (`G85` or routed) a dark stroke
+pub fn parse_excellon(raw: &str) -> Image {
+	let mut image = Image::default();
+	let mut tools: HashMap<String, f64> = HashMap::new();
+	let mut tool = None;
+	let mut unit = 1f64;
+	let mut pos = (0f64, 0f64);
+	let mut header = true;
+	// routed slots: G00 moves, M15 plunges, G01 mills until M16 or M17
+	let mut routing = false;
+	let mut plunged = false;
+
+	let coord = |s: &str| -> Option<f64> {
+		let v: f64 = s.parse().ok()?;
+		// without a decimal point kicad uses 3.3 for metric
+		Some(if s.contains('.') { v } else { v / 1000f64 })
+	};
+
+	let parse = |s: &str, prev: Point, unit: f64| -> Point {
+		let xi = s.find('X');
+		let yi = s.find('Y');
+		let x = xi.and_then(|i| coord(&s[i + 1..yi.filter(|y| *y > i)
+			.unwrap_or(s.len())]));
+		let y = yi.and_then(|i| coord(&s[i + 1..]));
+		(
+			x.map(|x| x * unit).unwrap_or(prev.0),
+			y.map(|y| y * unit).unwrap_or(prev.1)
+		)
+	};
+
+	for line in raw.lines() {
+		let line = line.trim();
+		if line.starts_with(';') || line.is_empty() {
+			continue
+		}
+
+		if line.starts_with("INCH") {
+			unit = 25.4;
+		} else if line.starts_with("METRIC") {
+			unit = 1f64;
+		} else if line == "%" {
+			header = false;
+		} else if line.starts_with('T') {
+			let (name, rest) = match line.find('C') {
+				Some(i) => (&line[..i], Some(&line[i + 1..])),
+				None => (line, None)
+			};
+			match rest {
+				Some(d) if header || !tools.contains_key(name) => {
+					if let Ok(d) = d.parse::<f64>() {
+						tools.insert(name.into(), d * unit);
+					}
+				},
+				_ => tool = tools.get(name).copied()
+			}
+		} else if line == "G05" {
+			routing = false;
+			plunged = false;
+		} else if line == "M15" {
+			plunged = true;
+		} else if line == "M16" || line == "M17" {
+			plunged = false;
+		} else if let Some(rest) = line.strip_prefix("G00") {
+			routing = true;
+			pos = parse(rest, pos, unit);
+		} else if let Some(rest) = line.strip_prefix("G01") {
+			routing = true;
+			let end = parse(rest, pos, unit);
+			if let (true, Some(d)) = (plunged, tool) {
+				image.stroke(pos, end, d, true);
+			}
+			pos = end;
+		} else if routing && (line.starts_with('X') || line.starts_with('Y')) {
+			// G01 stays active until the tool is lifted
+			let end = parse(line, pos, unit);
+			if let (true, Some(d)) = (plunged, tool) {
+				image.stroke(pos, end, d, true);
+			}
+			pos = end;
+		} else if line.starts_with('X') || line.starts_with('Y') {
+			let (start, end) = match line.split_once("G85") {
+				Some((a, b)) => (a, Some(b)),
+				None => (line, None)
+			};
+
+			pos = parse(start, pos, unit);
+			let Some(d) = tool else { continue };
+
+			match end {
+				Some(end) => {
+					let end = parse(end, pos, unit);
+					image.stroke(pos, end, d, true);
+				},
+				None => image.push(circle(pos, d, CIRCLE_SEGMENTS), true)
+			}
+		}
+	}
+
+	image
+}
+
+/// A monochrome raster, row 0 is at the top
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+	pub width: usize,
+	pub height: usize,
+	pub data: Vec<bool>
+}
+
+/// The area which gets rasterized
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+	pub min: Point,
+	pub max: Point,
+	/// pixels per mm
+	pub resolution: f64
+}
+
+impl Frame {
+	pub fn width(&self) -> usize {
+		((self.max.0 - self.min.0) * self.resolution).ceil().max(1f64) as usize
+	}
+
+	pub fn height(&self) -> usize {
+		((self.max.1 - self.min.1) * self.resolution).ceil().max(1f64) as usize
+	}
+}
+
+pub fn rasterize(image: &Image, frame: Frame) -> Bitmap {
+	let width = frame.width();
+	let height = frame.height();
+	let mut data = vec![false; width * height];
+
+	for polygon in &image.polygons {
+		let n = polygon.points.len();
+		let (min_y, max_y) = polygon.points.iter()
+			.fold((f64::MAX, f64::MIN), |(a, b), p| (a.min(p.1), b.max(p.1)));
+
+		// rows are counted from the top
+		let row_of = |y: f64| (frame.max.1 - y) * frame.resolution;
+		let first = row_of(max_y).floor().max(0f64) as usize;
+		let last = (row_of(min_y).ceil() as usize).min(height);
+
+		let mut xs = vec![];
+		for row in first..last {
+			let y = frame.max.1 - (row as f64 + 0.5) / frame.resolution;
+
+			xs.clear();
+			for i in 0..n {
+				let a = polygon.points[i];
+				let b = polygon.points[(i + 1) % n];
+				if (a.1 <= y) != (b.1 <= y) {
+					xs.push(a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0));
+				}
+			}
+			xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+			for pair in xs.chunks(2) {
+				let [x0, x1] = pair else { continue };
+				let col = |x: f64| (x - frame.min.0) * frame.resolution - 0.5;
+				let c0 = col(*x0).ceil().max(0f64) as usize;
+				let c1 = (col(*x1).floor() + 1f64).max(0f64) as usize;
+				let c1 = c1.min(width);
+				for c in c0..c1 {
+					data[row * width + c] = polygon.dark;
+				}
+			}
+		}
+	}
+
+	Bitmap { width, height, data }
+}
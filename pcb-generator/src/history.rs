@@ -0,0 +1,289 @@
+use crate::bom::{group_entries, read_custom_entries};
+use crate::partslist::{find_in_parts_list, unit_price};
+use crate::util::{config_dir, format_date, parse_date, today};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use serde::{Deserialize, Serialize};
+
+/// Contains a `YYYY-MM-DD.csv` snapshot per download
+const HISTORY_DIR: &str = "parts-history";
+
+fn history_dir() -> String {
+	format!("{}/{}", config_dir(), HISTORY_DIR)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotEntry {
+	#[serde(rename = "LCSC Part")]
+	lcsc: String,
+	#[serde(rename = "Price")]
+	price: String,
+	#[serde(rename = "Stock")]
+	stock: usize
+}
+
+impl SnapshotEntry {
+	fn unit_price(&self) -> Option<f64> {
+		unit_price(&self.price, 1)
+	}
+}
+
+struct Snapshot {
+	/// Days since 1970-01-01
+	day: i64,
+	parts: HashMap<String, SnapshotEntry>
+}
+
+/// Returns every snapshot, the oldest first
+fn read_history() -> Vec<Snapshot> {
+	let Ok(read_dir) = fs::read_dir(history_dir()) else {
+		return vec![]
+	};
+
+	let mut snapshots: Vec<_> = read_dir
+		.filter_map(|e| e.ok())
+		.map(|e| e.path())
+		.filter_map(|path| {
+			if path.extension()? != "csv" {
+				return None
+			}
+			let day = parse_date(path.file_stem()?.to_str()?)?;
+
+			let reader = csv::Reader::from_path(&path)
+				.expect("could not open snapshot");
+			let parts = reader.into_deserialize()
+				.map(|e| {
+					let e: SnapshotEntry = e.expect("failed to deserialize");
+					(e.lcsc.clone(), e)
+				})
+				.collect();
+
+			Some(Snapshot { day, parts })
+		})
+		.collect();
+	snapshots.sort_by_key(|s| s.day);
+
+	snapshots
+}
+
+/// Reads the part ids of the given boms, missing files are ignored
+fn bom_part_ids(boms: &[PathBuf], uses_comma: bool) -> BTreeSet<String> {
+	boms.iter()
+		.filter(|p| p.is_file())
+		.flat_map(|p| read_custom_entries(p, uses_comma))
+		.map(|e| e.jlcpcb_part.trim().to_string())
+		.filter(|id| !id.is_empty())
+		.collect()
+}
+
+/// Stores the price and stock of the parts used in the boms and of every
+/// part which was already tracked
+///
+/// A second download on the same day replaces that days snapshot.
+pub fn write_snapshot(boms: &[PathBuf], uses_comma: bool) {
+	let mut ids = bom_part_ids(boms, uses_comma);
+	if let Some(last) = read_history().last() {
+		ids.extend(last.parts.keys().cloned());
+	}
+
+	if ids.is_empty() {
+		println!("no parts to track, skipping the history snapshot");
+		return
+	}
+
+	let parts = find_in_parts_list(|p| ids.contains(p.lcsc.trim()));
+
+	let dir = history_dir();
+	fs::create_dir_all(&dir).expect("could not create history dir");
+	let path = format!("{}/{}.csv", dir, today());
+
+	let mut w = csv::Writer::from_path(&path).unwrap();
+	for part in &parts {
+		w.serialize(SnapshotEntry {
+			lcsc: part.lcsc.trim().into(),
+			price: part.price.clone(),
+			stock: part.stock
+		}).unwrap();
+	}
+	w.flush().unwrap();
+
+	println!("snapshot of {} parts written to {:?}", parts.len(), path);
+}
+
+/// Returns the stock change per day using a least squares fit
+fn stock_trend(points: &[(i64, usize)]) -> Option<f64> {
+	if points.len() < 2 {
+		return None
+	}
+
+	let n = points.len() as f64;
+	let mean_x = points.iter().map(|(x, _)| *x as f64).sum::<f64>() / n;
+	let mean_y = points.iter().map(|(_, y)| *y as f64).sum::<f64>() / n;
+
+	let (mut num, mut den) = (0f64, 0f64);
+	for (x, y) in points {
+		let dx = *x as f64 - mean_x;
+		num += dx * (*y as f64 - mean_y);
+		den += dx * dx;
+	}
+
+	if den == 0f64 {
+		return None
+	}
+
+	Some(num / den)
+}
+
+/// Returns the estimated days until the stock runs out
+fn days_left(points: &[(i64, usize)]) -> Option<f64> {
+	let trend = stock_trend(points)?;
+	let (_, stock) = points.last()?;
+	if trend >= 0f64 {
+		return None
+	}
+
+	Some(*stock as f64 / -trend)
+}
+
+fn stock_points(history: &[Snapshot], id: &str) -> Vec<(i64, usize)> {
+	history.iter()
+		.filter_map(|s| Some((s.day, s.parts.get(id)?.stock)))
+		.collect()
+}
+
+/// Prints a warning for every bom part which is out of stock or is
+/// expected to run out within `warn_days`
+pub fn warn_low_stock(boms: &[PathBuf], uses_comma: bool, warn_days: f64) {
+	let history = read_history();
+
+	for id in bom_part_ids(boms, uses_comma) {
+		let points = stock_points(&history, &id);
+		let Some((day, stock)) = points.last() else { continue };
+
+		if *stock == 0 {
+			eprintln!("warning: {} is out of stock", id);
+		} else if let Some(days) = days_left(&points) {
+			if days < warn_days {
+				eprintln!(
+					"warning: {} has {} left and might run out around {}",
+					id, stock, format_date(day + days as i64)
+				);
+			}
+		}
+	}
+}
+
+/// Shows how the price and stock of parts changed over time
+///
+/// Without `--id` every part of the bom is summarized.
+#[derive(Debug, Parser)]
+pub struct History {
+	#[clap(long)]
+	id: Option<String>,
+	/// The boms whose parts get summarized
+	#[clap(long, default_value = "./bom.csv")]
+	bom: Vec<PathBuf>,
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// Warn if a part might run out within this many days
+	#[clap(long, default_value_t = 60f64)]
+	warn_days: f64
+}
+
+pub fn history(args: History) {
+	let history = read_history();
+	if history.is_empty() {
+		println!("no snapshots in {}, run download-parts-list", history_dir());
+		return
+	}
+
+	match &args.id {
+		Some(id) => print_part(&history, id.trim()),
+		None => print_bom(&history, &args)
+	}
+}
+
+fn print_part(history: &[Snapshot], id: &str) {
+	println!("{:<12} {:>10} {:>10} {:>10}", "date", "stock", "change", "price");
+
+	let mut prev: Option<&SnapshotEntry> = None;
+	for snapshot in history {
+		let Some(entry) = snapshot.parts.get(id) else { continue };
+		let change = prev
+			.map(|p| format!("{:+}", entry.stock as i64 - p.stock as i64))
+			.unwrap_or_default();
+		let price = entry.unit_price()
+			.map(|p| format!("{:.4}", p))
+			.unwrap_or_else(|| "?".into());
+		println!(
+			"{:<12} {:>10} {:>10} {:>10}",
+			format_date(snapshot.day), entry.stock, change, price
+		);
+		prev = Some(entry);
+	}
+
+	let Some(prev) = prev else {
+		println!("{} is not in any snapshot", id);
+		return
+	};
+
+	let points = stock_points(history, id);
+	match (stock_trend(&points), days_left(&points)) {
+		(Some(trend), Some(days)) => println!(
+			"stock changes by {:.1} per day, empty around {}",
+			trend, format_date(points.last().unwrap().0 + days as i64)
+		),
+		(Some(trend), None) => println!(
+			"stock changes by {:+.1} per day", trend
+		),
+		_ => println!("{} in stock", prev.stock)
+	}
+}
+
+fn print_bom(history: &[Snapshot], args: &History) {
+	let existing: Vec<_> = args.bom.iter()
+		.filter(|p| Path::new(p).is_file())
+		.cloned()
+		.collect();
+	assert!(!existing.is_empty(), "no bom found at {:?}", args.bom);
+
+	println!(
+		"{:<10} {:>6} {:>10} {:>10} {:>10} {:>12}",
+		"part", "qty", "stock", "per day", "price", "empty"
+	);
+
+	for bom in &existing {
+		let entries = read_custom_entries(bom, args.uses_comma);
+		for (id, designators) in group_entries(&entries) {
+			let points = stock_points(history, id);
+			let last = history.iter().rev().find_map(|s| s.parts.get(id));
+			let Some(last) = last else {
+				println!(
+					"{:<10} {:>6} not in any snapshot", id, designators.len()
+				);
+				continue
+			};
+
+			let trend = stock_trend(&points)
+				.map(|t| format!("{:+.1}", t))
+				.unwrap_or_default();
+			let price = last.unit_price()
+				.map(|p| format!("{:.4}", p))
+				.unwrap_or_default();
+			let empty = days_left(&points)
+				.map(|d| format_date(points.last().unwrap().0 + d as i64))
+				.unwrap_or_default();
+			println!(
+				"{:<10} {:>6} {:>10} {:>10} {:>10} {:>12}",
+				id, designators.len(), last.stock, trend, price, empty
+			);
+		}
+	}
+
+	warn_low_stock(&existing, args.uses_comma, args.warn_days);
+}
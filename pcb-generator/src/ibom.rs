@@ -0,0 +1,226 @@
+use crate::board::{find_board_file, Board, Footprint, Graphic, Shape};
+use crate::bom::{group_entries, read_custom_entries};
+use crate::cpl::read_kicad_entries;
+use crate::partslist::find_in_parts_list;
+use crate::util::{create_build_dir, escape, BUILD_DIR};
+
+use std::fmt::Write;
+use std::fs;
+
+use clap::Parser;
+
+/// Writes a self contained html file with the board and a clickable bom
+#[derive(Debug, Parser)]
+pub struct Ibom {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool
+}
+
+pub fn ibom(args: Ibom) {
+	create_build_dir();
+
+	let board = match find_board_file(".") {
+		Some(path) => Board::read(path),
+		None => {
+			eprintln!("no .kicad_pcb found, using the position file");
+			board_from_positions(args.uses_comma)
+		}
+	};
+
+	let custom_entries = read_custom_entries("./bom.csv", args.uses_comma);
+	let groups = group_entries(&custom_entries);
+
+	let ids: Vec<_> = groups.iter().map(|(id, _)| id.to_string()).collect();
+	let parts = find_in_parts_list(|p| ids.iter().any(|i| i == p.lcsc.trim()));
+
+	let mut rows = String::new();
+	for (i, (jlcpcb_part, designators)) in groups.iter().enumerate() {
+		let desc = parts.iter()
+			.find(|p| p.lcsc.trim() == *jlcpcb_part)
+			.map(|p| p.desc.as_str())
+			.unwrap_or("");
+		let value = board.footprint(designators[0])
+			.map(|f| f.value.as_str())
+			.unwrap_or("");
+		writeln!(
+			rows,
+			"<tr data-refs=\"{refs}\">\
+			<td><input type=\"checkbox\"></td><td>{}</td><td>{}</td>\
+			<td>{}</td><td>{}</td><td>{refs}</td><td>{}</td></tr>",
+			i + 1, designators.len(), escape(value), escape(jlcpcb_part),
+			escape(desc),
+			refs = escape(&designators.join(","))
+		).unwrap();
+	}
+
+	let html = TEMPLATE
+		.replace("{front}", &render_side(&board, false))
+		.replace("{back}", &render_side(&board, true))
+		.replace("{rows}", &rows);
+
+	let path = format!("{}/ibom.html", BUILD_DIR);
+	fs::write(&path, html).expect("could not write ibom");
+
+	println!("created {}", path);
+}
+
+/// Without a board file every part is drawn as a small square
+fn board_from_positions(uses_comma: bool) -> Board {
+	let kicad_entries = read_kicad_entries(uses_comma)
+		.expect("did not find a .kicad_pcb or *top-pos.csv file");
+
+	let footprints = kicad_entries.into_iter()
+		.map(|e| {
+			let mut fp = Footprint {
+				lib_id: e.package.clone(),
+				reference: e.designator,
+				value: e.value,
+				// the position file has the y axis pointing up
+				at: (e.pos_x as f64, -e.pos_y as f64),
+				rotation: e.rotation as f64,
+				layer: if e.side == "bottom" { "B.Cu" } else { "F.Cu" }.into(),
+				attrs: vec![],
+				graphics: vec![],
+				pads: vec![]
+			};
+			let pts = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+			fp.graphics.push(Graphic {
+				layer: format!("{}.CrtYd", &fp.layer[..1]),
+				width: 0.05,
+				shape: Shape::Poly(
+					pts.into_iter().map(|p| fp.transform(p)).collect()
+				)
+			});
+			fp
+		})
+		.collect();
+
+	Board { outline: vec![], footprints }
+}
+
+fn render_side(board: &Board, back: bool) -> String {
+	let ((x0, y0), (x1, y1)) = board.bounds()
+		.unwrap_or(((0f64, 0f64), (1f64, 1f64)));
+	let margin = 2f64;
+
+	let mut svg = format!(
+		"<svg xmlns=\"http://www.w3.org/2000/svg\" \
+		viewBox=\"{} {} {} {}\">",
+		x0 - margin, y0 - margin,
+		x1 - x0 + margin * 2f64, y1 - y0 + margin * 2f64
+	);
+
+	// the back side is viewed mirrored
+	if back {
+		write!(
+			svg, "<g transform=\"translate({} 0) scale(-1 1)\">", x0 + x1
+		).unwrap();
+	} else {
+		svg.push_str("<g>");
+	}
+
+	for g in &board.outline {
+		write!(svg, "<path class=\"edge\" d=\"{}\"/>", g.shape.svg_path())
+			.unwrap();
+	}
+
+	for fp in board.footprints.iter().filter(|f| f.is_back() == back) {
+		write!(svg, "<g class=\"fp\" data-ref=\"{}\">", escape(&fp.reference))
+			.unwrap();
+
+		let body: Vec<_> = fp.graphics_on("Fab").collect();
+		let body = if body.is_empty() {
+			fp.graphics_on("CrtYd").collect()
+		} else {
+			body
+		};
+		for g in body {
+			write!(svg, "<path class=\"body\" d=\"{}\"/>", g.shape.svg_path())
+				.unwrap();
+		}
+
+		for pad in &fp.pads {
+			let pin1 = if pad.number == "1" { " pin1" } else { "" };
+			write!(
+				svg, "<path class=\"pad{}\" d=\"{}\"/>", pin1, pad.svg_path()
+			).unwrap();
+		}
+
+		svg.push_str("</g>");
+	}
+
+	svg.push_str("</g></svg>");
+	svg
+}
+
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Interactive BOM</title>
+<style>
+body { font-family: sans-serif; margin: 0; display: flex; height: 100vh; }
+#bom { width: 45%; overflow: auto; }
+#boards { flex: 1; display: flex; flex-direction: column; background: #222; }
+#boards div { flex: 1; min-height: 0; padding: 8px; }
+#boards h3 { color: #ccc; margin: 0; font-size: 14px; }
+svg { width: 100%; height: calc(100% - 20px); }
+.edge { fill: none; stroke: #ff0; stroke-width: 0.2; }
+.body { fill: none; stroke: #888; stroke-width: 0.1; }
+.pad { fill: #a0a0a0; }
+.pin1 { fill: #d0a040; }
+.fp.hl .pad { fill: #f33; }
+.fp.hl .body { stroke: #f33; stroke-width: 0.2; }
+table { border-collapse: collapse; width: 100%; font-size: 13px; }
+td, th { border: 1px solid #ccc; padding: 2px 6px; text-align: left; }
+tr { cursor: pointer; }
+tr.hl { background: #fdd; }
+tr.placed { color: #999; }
+</style>
+</head>
+<body>
+<div id="bom">
+<table>
+<tr><th>Placed</th><th>#</th><th>Qty</th><th>Value</th><th>LCSC</th>
+<th>Designators</th><th>Description</th></tr>
+{rows}
+</table>
+</div>
+<div id="boards">
+<div><h3>Front</h3>{front}</div>
+<div><h3>Back</h3>{back}</div>
+</div>
+<script>
+const rows = [...document.querySelectorAll('tr[data-refs]')];
+const fps = [...document.querySelectorAll('.fp')];
+
+function highlight(row) {
+	rows.forEach(r => r.classList.toggle('hl', r === row));
+	const refs = row ? row.dataset.refs.split(',') : [];
+	fps.forEach(f => f.classList.toggle('hl', refs.includes(f.dataset.ref)));
+}
+
+rows.forEach(row => {
+	row.addEventListener('click', () => highlight(row));
+	const check = row.querySelector('input');
+	const key = 'ibom-placed-' + row.dataset.refs;
+	check.checked = localStorage.getItem(key) === '1';
+	row.classList.toggle('placed', check.checked);
+	check.addEventListener('change', () => {
+		localStorage.setItem(key, check.checked ? '1' : '0');
+		row.classList.toggle('placed', check.checked);
+	});
+});
+
+fps.forEach(fp => fp.addEventListener('click', () => {
+	const row = rows.find(r => r.dataset.refs.split(',')
+		.includes(fp.dataset.ref));
+	if (row) {
+		highlight(row);
+		row.scrollIntoView({ block: 'center' });
+	}
+}));
+</script>
+</body>
+</html>
+"#;
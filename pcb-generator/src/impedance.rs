@@ -0,0 +1,291 @@
+use std::f64::consts::{E, PI};
+
+use clap::Parser;
+
+/// All values in mm
+#[derive(Debug)]
+pub struct Stackup {
+	pub name: &'static str,
+	pub desc: &'static str,
+	/// copper thickness of each layer, top to bottom
+	pub copper: &'static [f64],
+	/// dielectric between copper layer n and n + 1
+	pub dielectrics: &'static [Dielectric]
+}
+
+#[derive(Debug)]
+pub struct Dielectric {
+	pub thickness: f64,
+	pub er: f64
+}
+
+const OUTER_1OZ: f64 = 0.035;
+const INNER_05OZ: f64 = 0.0152;
+
+const fn dielectric(thickness: f64, er: f64) -> Dielectric {
+	Dielectric { thickness, er }
+}
+
+// values as published by jlcpcb for 1.6mm boards,
+// double check them before ordering
+pub const STACKUPS: &[Stackup] = &[
+	Stackup {
+		name: "JLC7628",
+		desc: "4 layer 1.6mm, 7628 prepreg",
+		copper: &[OUTER_1OZ, INNER_05OZ, INNER_05OZ, OUTER_1OZ],
+		dielectrics: &[
+			dielectric(0.2104, 4.4),
+			dielectric(1.065, 4.6),
+			dielectric(0.2104, 4.4)
+		]
+	},
+	Stackup {
+		name: "JLC3313",
+		desc: "4 layer 1.6mm, 3313 prepreg",
+		copper: &[OUTER_1OZ, INNER_05OZ, INNER_05OZ, OUTER_1OZ],
+		dielectrics: &[
+			dielectric(0.0994, 4.1),
+			dielectric(1.265, 4.6),
+			dielectric(0.0994, 4.1)
+		]
+	},
+	Stackup {
+		name: "JLC2313",
+		desc: "4 layer 1.6mm, 2313 prepreg",
+		copper: &[OUTER_1OZ, INNER_05OZ, INNER_05OZ, OUTER_1OZ],
+		dielectrics: &[
+			dielectric(0.1, 4.05),
+			dielectric(1.265, 4.6),
+			dielectric(0.1, 4.05)
+		]
+	},
+	Stackup {
+		name: "JLC2116",
+		desc: "4 layer 1.6mm, 2116 prepreg",
+		copper: &[OUTER_1OZ, INNER_05OZ, INNER_05OZ, OUTER_1OZ],
+		dielectrics: &[
+			dielectric(0.1164, 4.16),
+			dielectric(1.24, 4.6),
+			dielectric(0.1164, 4.16)
+		]
+	},
+	Stackup {
+		name: "JLC2L",
+		desc: "2 layer 1.6mm FR-4",
+		copper: &[OUTER_1OZ, OUTER_1OZ],
+		dielectrics: &[dielectric(1.51, 4.5)]
+	}
+];
+
+pub fn find_stackup(name: &str) -> Option<&'static Stackup> {
+	STACKUPS.iter()
+		.find(|s| s.name.eq_ignore_ascii_case(name.trim()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+	Microstrip,
+	Stripline
+}
+
+/// The geometry of a trace on a given layer of a stackup
+#[derive(Debug, Clone, Copy)]
+pub struct Trace {
+	pub kind: TraceKind,
+	/// copper thickness
+	pub t: f64,
+	/// distance to the (nearer) reference plane
+	pub h1: f64,
+	/// distance to the other reference plane, only used by striplines
+	pub h2: f64,
+	pub er: f64
+}
+
+impl Stackup {
+	pub fn layers(&self) -> usize {
+		self.copper.len()
+	}
+
+	/// layer starts at 1 (top)
+	pub fn trace(&self, layer: usize) -> Option<Trace> {
+		if layer == 0 || layer > self.layers() {
+			return None
+		}
+
+		let i = layer - 1;
+		let t = self.copper[i];
+
+		if i == 0 || i == self.layers() - 1 {
+			let d = &self.dielectrics[i.min(self.dielectrics.len() - 1)];
+			return Some(Trace {
+				kind: TraceKind::Microstrip,
+				t,
+				h1: d.thickness,
+				h2: 0f64,
+				er: d.er
+			})
+		}
+
+		let above = &self.dielectrics[i - 1];
+		let below = &self.dielectrics[i];
+		let (near, far) = if above.thickness <= below.thickness {
+			(above, below)
+		} else {
+			(below, above)
+		};
+
+		let er = (above.er * above.thickness + below.er * below.thickness) /
+			(above.thickness + below.thickness);
+
+		Some(Trace {
+			kind: TraceKind::Stripline,
+			t,
+			h1: near.thickness,
+			h2: far.thickness,
+			er
+		})
+	}
+}
+
+impl Trace {
+	/// single ended impedance in ohm
+	pub fn impedance(&self, w: f64) -> f64 {
+		match self.kind {
+			TraceKind::Microstrip => microstrip(w, self.t, self.h1, self.er),
+			TraceKind::Stripline => {
+				stripline(w, self.t, self.h1, self.h2, self.er)
+			}
+		}
+	}
+
+	/// differential impedance in ohm
+	pub fn diff_impedance(&self, w: f64, s: f64) -> f64 {
+		let z0 = self.impedance(w);
+		match self.kind {
+			TraceKind::Microstrip => {
+				2f64 * z0 * (1f64 - 0.48 * E.powf(-0.96 * s / self.h1))
+			},
+			TraceKind::Stripline => {
+				let b = self.h1 + self.h2 + self.t;
+				2f64 * z0 * (1f64 - 0.347 * E.powf(-2.9 * s / b))
+			}
+		}
+	}
+
+	/// Returns the width needed to reach the target impedance
+	///
+	/// If a gap is given the differential impedance is targeted.
+	pub fn solve_width(&self, target: f64, gap: Option<f64>) -> Option<f64> {
+		let z = |w: f64| match gap {
+			Some(s) => self.diff_impedance(w, s),
+			None => self.impedance(w)
+		};
+
+		// the impedance falls with a wider trace
+		let (mut lo, mut hi) = (0.01f64, 10f64);
+		if z(lo) < target || z(hi) > target {
+			return None
+		}
+
+		for _ in 0..100 {
+			let mid = (lo + hi) / 2f64;
+			if z(mid) > target {
+				lo = mid;
+			} else {
+				hi = mid;
+			}
+		}
+
+		Some((lo + hi) / 2f64)
+	}
+}
+
+/// Hammerstad and Jensen with a thickness correction for the width
+fn microstrip(w: f64, t: f64, h: f64, er: f64) -> f64 {
+	let w = w + t / PI * (1f64 + (2f64 * h / t).ln());
+	let u = w / h;
+
+	let f = 6f64 + (2f64 * PI - 6f64) * E.powf(-(30.666 / u).powf(0.7528));
+	let z_air = 60f64 * (f / u + (1f64 + 4f64 / (u * u)).sqrt()).ln();
+
+	let a = 1f64 +
+		((u.powi(4) + (u / 52f64).powi(2)) / (u.powi(4) + 0.432)).ln() / 49f64 +
+		(1f64 + (u / 18.1).powi(3)).ln() / 18.7;
+	let b = 0.564 * ((er - 0.9) / (er + 3f64)).powf(0.053);
+	let er_eff = (er + 1f64) / 2f64 +
+		(er - 1f64) / 2f64 * (1f64 + 10f64 / u).powf(-a * b);
+
+	z_air / er_eff.sqrt()
+}
+
+/// IPC-2141 (asymmetric) stripline
+fn stripline(w: f64, t: f64, h1: f64, h2: f64, er: f64) -> f64 {
+	80f64 / er.sqrt() *
+		(1.9 * (2f64 * h1 + t) / (0.8 * w + t)).ln() *
+		(1f64 - h1 / (4f64 * (h1 + h2 + t)))
+}
+
+#[derive(Debug, Parser)]
+pub struct Impedance {
+	/// The fab stackup, see --list-stackups
+	#[clap(long, default_value = "JLC7628")]
+	stackup: String,
+	/// The copper layer starting with 1 at the top
+	#[clap(long, default_value_t = 1)]
+	layer: usize,
+	/// Trace width in mm
+	#[clap(long)]
+	width: Option<f64>,
+	/// Gap between the two traces of a differential pair in mm
+	#[clap(long)]
+	gap: Option<f64>,
+	/// Target impedance in ohm, solves for the trace width
+	#[clap(long)]
+	target: Option<f64>,
+	#[clap(long)]
+	list_stackups: bool
+}
+
+pub fn impedance(args: Impedance) {
+	if args.list_stackups {
+		for stackup in STACKUPS {
+			println!("{}: {}", stackup.name, stackup.desc);
+		}
+		return
+	}
+
+	let stackup = find_stackup(&args.stackup)
+		.unwrap_or_else(|| panic!("unknown stackup {:?}", args.stackup));
+	let trace = stackup.trace(args.layer)
+		.unwrap_or_else(|| panic!(
+			"{} has only {} layers", stackup.name, stackup.layers()
+		));
+
+	println!(
+		"{} layer {} {:?} (h {}mm, t {}mm, er {:.2})",
+		stackup.name, args.layer, trace.kind, trace.h1, trace.t, trace.er
+	);
+
+	match (args.target, args.width) {
+		(Some(target), _) => {
+			let Some(w) = trace.solve_width(target, args.gap) else {
+				panic!("{}ohm is not reachable on this layer", target);
+			};
+
+			match args.gap {
+				Some(s) => println!(
+					"width {:.4}mm with gap {}mm for {}ohm differential",
+					w, s, target
+				),
+				None => println!("width {:.4}mm for {}ohm", w, target)
+			}
+		},
+		(None, Some(w)) => {
+			println!("single ended {:.2}ohm", trace.impedance(w));
+			if let Some(s) = args.gap {
+				println!("differential {:.2}ohm", trace.diff_impedance(w, s));
+			}
+		},
+		(None, None) => panic!("either --width or --target is required")
+	}
+}
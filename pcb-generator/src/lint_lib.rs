@@ -0,0 +1,415 @@
+use crate::board::Footprint;
+use crate::sexpr::Sexpr;
+use crate::util::natural_cmp;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Parser;
+
+/// Checks the project symbol and footprint libraries
+#[derive(Debug, Parser)]
+pub struct LintLib {
+	/// A global fp-lib-table used to resolve the kicad libraries
+	#[clap(long)]
+	global_fp_lib_table: Option<PathBuf>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+	Warning,
+	Error
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+	pub level: Level,
+	pub message: String
+}
+
+/// nickname -> directory or file
+type LibTable = HashMap<String, Option<PathBuf>>;
+
+fn read_sexpr(path: &Path) -> Sexpr {
+	let raw = fs::read_to_string(path)
+		.unwrap_or_else(|e| panic!("could not read {:?} {:?}", path, e));
+	Sexpr::parse(&raw)
+		.unwrap_or_else(|e| panic!("could not parse {:?} {}", path, e))
+}
+
+/// Reads a fp-lib-table or sym-lib-table
+///
+/// Uris with unknown variables are stored as None.
+fn read_lib_table(path: &Path, project_dir: &Path) -> LibTable {
+	if !path.is_file() {
+		return HashMap::new()
+	}
+
+	read_sexpr(path).children("lib")
+		.filter_map(|lib| {
+			let name = lib.child("name")?.atom(0)?;
+			let uri = lib.child("uri")?.atom(0)?;
+			Some((name.to_string(), expand_uri(uri, project_dir)))
+		})
+		.collect()
+}
+
+/// Replaces ${KIPRJMOD} and environment variables
+fn expand_uri(uri: &str, project_dir: &Path) -> Option<PathBuf> {
+	let mut out = String::new();
+	let mut rest = uri;
+
+	while let Some(start) = rest.find("${") {
+		out.push_str(&rest[..start]);
+		let end = rest[start..].find('}')? + start;
+		let var = &rest[start + 2..end];
+		if var == "KIPRJMOD" {
+			out.push_str(project_dir.to_str()?);
+		} else {
+			out.push_str(&env::var(var).ok()?);
+		}
+		rest = &rest[end + 1..];
+	}
+	out.push_str(rest);
+
+	Some(PathBuf::from(out))
+}
+
+/// Returns the pin numbers of a symbol (of all units)
+fn symbol_pins(symbol: &Sexpr) -> BTreeSet<String> {
+	let mut pins = BTreeSet::new();
+	for unit in symbol.children("symbol").chain([symbol]) {
+		for pin in unit.children("pin") {
+			if let Some(number) = pin.child("number").and_then(|n| n.atom(0)) {
+				pins.insert(number.to_string());
+			}
+		}
+	}
+	pins
+}
+
+fn footprint_pads(footprint: &Footprint) -> BTreeSet<String> {
+	footprint.pads.iter()
+		.map(|p| p.number.clone())
+		.filter(|n| !n.is_empty())
+		.collect()
+}
+
+fn join_sorted(set: &BTreeSet<&String>) -> String {
+	let mut v: Vec<_> = set.iter().map(|s| s.as_str()).collect();
+	v.sort_by(|a, b| natural_cmp(a, b));
+	v.join(",")
+}
+
+struct Linter {
+	project_dir: PathBuf,
+	fp_table: LibTable,
+	project_fp_libs: BTreeSet<String>,
+	footprints: HashMap<String, Option<Footprint>>,
+	findings: Vec<Finding>
+}
+
+impl Linter {
+	fn error(&mut self, message: String) {
+		self.findings.push(Finding { level: Level::Error, message });
+	}
+
+	fn warning(&mut self, message: String) {
+		self.findings.push(Finding { level: Level::Warning, message });
+	}
+
+	/// Resolves `lib:name` to a footprint
+	///
+	/// Returns None if the library exists but cannot be read (for
+	/// example because of an unknown path variable).
+	fn footprint(&mut self, id: &str, context: &str) -> Option<Footprint> {
+		if let Some(fp) = self.footprints.get(id) {
+			return fp.clone()
+		}
+
+		let fp = self.resolve_footprint(id, context);
+		self.footprints.insert(id.into(), fp.clone());
+		fp
+	}
+
+	fn resolve_footprint(&mut self, id: &str, context: &str) -> Option<Footprint> {
+		let Some((lib, name)) = id.split_once(':') else {
+			self.error(format!("{}: footprint {:?} has no library", context, id));
+			return None
+		};
+
+		let dir = match self.fp_table.get(lib) {
+			Some(Some(dir)) => dir.clone(),
+			// unknown variable
+			Some(None) => return None,
+			None => {
+				// the kicad libraries are only known with a global table
+				if !self.project_fp_libs.is_empty() &&
+					self.fp_table.len() == self.project_fp_libs.len()
+				{
+					self.warning(format!(
+						"{}: {:?} is not in the project fp-lib-table, \
+						pass --global-fp-lib-table to check it",
+						context, id
+					));
+				} else {
+					self.error(format!(
+						"{}: library {:?} of {:?} is not in any fp-lib-table",
+						context, lib, id
+					));
+				}
+				return None
+			}
+		};
+
+		let path = dir.join(format!("{}.kicad_mod", name));
+		if !path.is_file() {
+			self.error(format!("{}: {:?} does not exist", context, path));
+			return None
+		}
+
+		Some(Footprint::from_sexpr(&read_sexpr(&path)))
+	}
+
+	fn check_pins(
+		&mut self,
+		context: &str,
+		footprint_id: &str,
+		pins: &BTreeSet<String>,
+		footprint: &Footprint
+	) {
+		let pads = footprint_pads(footprint);
+
+		let missing_pads: BTreeSet<_> = pins.difference(&pads).collect();
+		if !missing_pads.is_empty() {
+			self.error(format!(
+				"{}: pins {} have no pad in {}",
+				context, join_sorted(&missing_pads), footprint_id
+			));
+		}
+
+		let missing_pins: BTreeSet<_> = pads.difference(pins).collect();
+		if !missing_pins.is_empty() {
+			self.error(format!(
+				"{}: pads {} of {} have no pin",
+				context, join_sorted(&missing_pins), footprint_id
+			));
+		}
+	}
+
+	/// Every footprint in the project libraries needs a courtyard and a fab
+	/// layer
+	fn check_project_footprints(&mut self) {
+		let libs: Vec<_> = self.project_fp_libs.iter()
+			.filter_map(|lib| Some((lib.clone(), self.fp_table.get(lib)?.clone()?)))
+			.collect();
+
+		for (lib, dir) in libs {
+			let Ok(read_dir) = fs::read_dir(&dir) else {
+				self.error(format!("footprint library {:?} not found", dir));
+				continue
+			};
+
+			let mut paths: Vec<_> = read_dir
+				.filter_map(|e| e.ok())
+				.map(|e| e.path())
+				.filter(|p| p.extension().is_some_and(|e| e == "kicad_mod"))
+				.collect();
+			paths.sort();
+
+			for path in paths {
+				let name = path.file_stem().unwrap().to_string_lossy();
+				let fp = Footprint::from_sexpr(&read_sexpr(&path));
+				let id = format!("{}:{}", lib, name);
+
+				if fp.graphics_on("CrtYd").next().is_none() {
+					self.error(format!("{}: has no courtyard", id));
+				}
+
+				if fp.graphics_on("Fab").next().is_none() {
+					self.warning(format!("{}: has no fab layer", id));
+				}
+
+				if footprint_pads(&fp).is_empty() {
+					self.warning(format!("{}: has no numbered pads", id));
+				}
+			}
+		}
+	}
+}
+
+/// Runs every check in the current directory
+pub fn lint_library(global_fp_lib_table: Option<&Path>) -> Vec<Finding> {
+	let project_dir = env::current_dir().expect("no current dir");
+
+	let project_fp_table = read_lib_table(
+		&project_dir.join("fp-lib-table"),
+		&project_dir
+	);
+	let sym_table = read_lib_table(
+		&project_dir.join("sym-lib-table"),
+		&project_dir
+	);
+
+	let mut fp_table = global_fp_lib_table
+		.map(|p| read_lib_table(p, &project_dir))
+		.unwrap_or_default();
+	let project_fp_libs = project_fp_table.keys().cloned().collect();
+	// the project table has priority
+	fp_table.extend(project_fp_table);
+
+	let mut linter = Linter {
+		project_dir,
+		fp_table,
+		project_fp_libs,
+		footprints: HashMap::new(),
+		findings: vec![]
+	};
+
+	// the symbols of the project symbol libraries
+	let mut project_symbols = BTreeMap::new();
+	for (lib, path) in &sym_table {
+		let Some(path) = path else { continue };
+		if !path.is_file() {
+			linter.error(format!("symbol library {:?} not found", path));
+			continue
+		}
+
+		for symbol in read_sexpr(path).children("symbol") {
+			let Some(name) = symbol.atom(0) else { continue };
+			project_symbols.insert(format!("{}:{}", lib, name), symbol.clone());
+		}
+	}
+
+	for (id, symbol) in &project_symbols {
+		let footprint = symbol.children("property")
+			.find(|p| p.atom(0) == Some("Footprint"))
+			.and_then(|p| p.atom(1))
+			.unwrap_or("");
+
+		if footprint.is_empty() {
+			continue
+		}
+
+		let context = format!("symbol {}", id);
+		if let Some(fp) = linter.footprint(footprint, &context) {
+			linter.check_pins(&context, footprint, &symbol_pins(symbol), &fp);
+		}
+	}
+
+	// every placed symbol which uses a project symbol or footprint
+	let mut schematics: Vec<_> = fs::read_dir(&linter.project_dir)
+		.expect("could not read project dir")
+		.filter_map(|e| e.ok())
+		.map(|e| e.path())
+		.filter(|p| p.extension().is_some_and(|e| e == "kicad_sch"))
+		.collect();
+	schematics.sort();
+
+	for path in schematics {
+		let sch = read_sexpr(&path);
+
+		let lib_symbols: HashMap<_, _> = sch.child("lib_symbols")
+			.map(|l| l.children("symbol")
+				.filter_map(|s| Some((s.atom(0)?.to_string(), s)))
+				.collect())
+			.unwrap_or_default();
+
+		// kicad 6 also stores the footprint per instance path, which is set
+		// when the property was left empty
+		let instance_footprints: HashMap<_, _> = sch.child("symbol_instances")
+			.map(|l| l.children("path")
+				.filter_map(|p| Some((
+					p.child("reference")?.atom(0)?.to_string(),
+					p.child("footprint")?.atom(0)?.to_string()
+				)))
+				.collect())
+			.unwrap_or_default();
+
+		for instance in sch.children("symbol") {
+			let Some(lib_id) = instance.child("lib_id").and_then(|l| l.atom(0))
+			else {
+				continue
+			};
+			let property = |name: &str| instance.children("property")
+				.find(|p| p.atom(0) == Some(name))
+				.and_then(|p| p.atom(1))
+				.unwrap_or("")
+				.to_string();
+			let reference = property("Reference");
+			let mut footprint = property("Footprint");
+			if footprint.is_empty() {
+				if let Some(fp) = instance_footprints.get(&reference) {
+					footprint = fp.clone();
+				}
+			}
+
+			// power symbols and similar
+			if reference.starts_with('#') {
+				continue
+			}
+
+			let fp_lib = footprint.split_once(':').map(|(l, _)| l);
+			let is_project = project_symbols.contains_key(lib_id) ||
+				fp_lib.is_some_and(|l| linter.project_fp_libs.contains(l));
+
+			let context = format!("{} ({})", reference, lib_id);
+
+			if footprint.is_empty() {
+				if project_symbols.contains_key(lib_id) {
+					linter.error(format!("{}: has no footprint", context));
+				}
+				continue
+			}
+
+			if !is_project && !linter.fp_table.contains_key(fp_lib.unwrap_or("")) {
+				continue
+			}
+
+			let Some(fp) = linter.footprint(&footprint, &context) else {
+				continue
+			};
+
+			let pins = match lib_symbols.get(lib_id) {
+				Some(symbol) => symbol_pins(symbol),
+				None => match project_symbols.get(lib_id) {
+					Some(symbol) => symbol_pins(symbol),
+					None => continue
+				}
+			};
+
+			linter.check_pins(&context, &footprint, &pins, &fp);
+		}
+	}
+
+	linter.check_project_footprints();
+
+	linter.findings
+}
+
+pub fn lint_lib(args: LintLib) {
+	let findings = lint_library(args.global_fp_lib_table.as_deref());
+
+	for finding in &findings {
+		let level = match finding.level {
+			Level::Warning => "warning",
+			Level::Error => "error"
+		};
+		println!("{}: {}", level, finding.message);
+	}
+
+	let errors = findings.iter()
+		.filter(|f| f.level == Level::Error)
+		.count();
+	println!(
+		"{} errors, {} warnings",
+		errors, findings.len() - errors
+	);
+
+	if errors > 0 {
+		process::exit(1);
+	}
+}
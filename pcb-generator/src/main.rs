@@ -4,6 +4,23 @@ mod gerber;
 mod bom;
 mod cpl;
 mod partslist;
+mod sexpr;
+mod board;
+mod gerber_image;
+mod impedance;
+mod datasheets;
+mod diff;
+mod packages;
+mod ibom;
+mod gerber_diff;
+mod lint_lib;
+mod basic_parts;
+mod history;
+mod workspace;
+mod watch;
+mod assembly_drawing;
+mod pin_map;
+mod preflight;
 
 use clap::Parser;
 
@@ -19,7 +36,21 @@ enum SubCommand {
 	Bom(bom::Bom),
 	Cpl(cpl::Cpl),
 	DownloadPartsList(partslist::DownloadPartsList),
-	SearchPartsList(partslist::SearchPartsList)
+	SearchPartsList(partslist::SearchPartsList),
+	Impedance(impedance::Impedance),
+	Datasheets(datasheets::Datasheets),
+	Diff(diff::Diff),
+	CheckPackages(packages::CheckPackages),
+	Ibom(ibom::Ibom),
+	GerberDiff(gerber_diff::GerberDiff),
+	LintLib(lint_lib::LintLib),
+	BasicParts(basic_parts::BasicParts),
+	History(history::History),
+	Workspace(workspace::Workspace),
+	Watch(watch::Watch),
+	AssemblyDrawing(assembly_drawing::AssemblyDrawing),
+	PinMap(pin_map::PinMap),
+	Preflight(preflight::Preflight)
 }
 
 
@@ -42,6 +73,48 @@ fn main() {
 		},
 		SubCommand::SearchPartsList(args) => {
 			partslist::search_parts_list(args);
+		},
+		SubCommand::Impedance(args) => {
+			impedance::impedance(args);
+		},
+		SubCommand::Datasheets(args) => {
+			datasheets::datasheets(args);
+		},
+		SubCommand::Diff(args) => {
+			diff::diff(args);
+		},
+		SubCommand::CheckPackages(args) => {
+			packages::check_packages(args);
+		},
+		SubCommand::Ibom(args) => {
+			ibom::ibom(args);
+		},
+		SubCommand::GerberDiff(args) => {
+			gerber_diff::gerber_diff(args);
+		},
+		SubCommand::LintLib(args) => {
+			lint_lib::lint_lib(args);
+		},
+		SubCommand::BasicParts(args) => {
+			basic_parts::basic_parts(args);
+		},
+		SubCommand::History(args) => {
+			history::history(args);
+		},
+		SubCommand::Workspace(args) => {
+			workspace::workspace(args);
+		},
+		SubCommand::Watch(args) => {
+			watch::watch(args);
+		},
+		SubCommand::AssemblyDrawing(args) => {
+			assembly_drawing::assembly_drawing(args);
+		},
+		SubCommand::PinMap(args) => {
+			pin_map::pin_map(args);
+		},
+		SubCommand::Preflight(args) => {
+			preflight::preflight(args);
 		}
 	}
 }
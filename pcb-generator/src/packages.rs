@@ -0,0 +1,199 @@
+use crate::bom::{read_custom_entries, split_designators};
+use crate::cpl::read_kicad_entries;
+use crate::partslist::find_in_parts_list;
+use crate::util::natural_cmp;
+
+use std::path::Path;
+use std::process;
+
+use clap::Parser;
+
+use serde::Deserialize;
+
+/// Equivalent package names which are not covered by `normalize_package`
+const DEFAULT_ALIASES: &[(&str, &str)] = &[
+	("SOT-23-3", "SOT-23"),
+	("SOT-223-3", "SOT-223"),
+	("SOT-89-3", "SOT-89"),
+	("SC-70-5", "SOT-353"),
+	("SC-70-6", "SOT-363"),
+	("SMA", "DO-214AC"),
+	("SMB", "DO-214AA"),
+	("SMC", "DO-214AB"),
+	("D_SMA", "SMA"),
+	("D_SOD-123", "SOD-123")
+];
+
+/// Reads ./package-aliases.csv with the columns `Footprint,Package`
+///
+/// Both columns get normalized before they are compared.
+#[derive(Debug)]
+pub struct PackageAliases {
+	inner: Vec<(String, String)>
+}
+
+impl PackageAliases {
+	pub fn read() -> Self {
+		let mut inner: Vec<_> = DEFAULT_ALIASES.iter()
+			.map(|(a, b)| (normalize_package(a), normalize_package(b)))
+			.collect();
+
+		let path = "./package-aliases.csv";
+		if !Path::new(path).is_file() {
+			return Self { inner }
+		}
+
+		let reader = csv::ReaderBuilder::new()
+			.flexible(true)
+			.from_path(path)
+			.expect("cannot read package aliases");
+
+		for entry in reader.into_deserialize() {
+			let entry: AliasEntry = entry.expect("failed to deserialize");
+			inner.push((
+				normalize_package(&entry.footprint),
+				normalize_package(&entry.package)
+			));
+		}
+
+		Self { inner }
+	}
+
+	/// Returns true if the kicad footprint plausibly matches the package
+	/// of the part
+	pub fn matches(&self, footprint: &str, package: &str) -> bool {
+		let footprint = normalize_package(footprint);
+		let package = normalize_package(package);
+
+		footprint == package || self.inner.iter().any(|(a, b)| {
+			(*a == footprint && *b == package) ||
+				(*a == package && *b == footprint)
+		})
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct AliasEntry {
+	#[serde(rename = "Footprint")]
+	footprint: String,
+	#[serde(rename = "Package")]
+	package: String
+}
+
+/// Brings kicad footprint names and jlcpcb package names into the same
+/// format
+///
+/// - `Capacitor_SMD:C_0402_1005Metric` and `C0402` become `0402`
+/// - `LQFP-48_7x7mm_P0.5mm` and `LQFP-48(7x7)` become `LQFP-48`
+/// - `SOT-23-5L` becomes `SOT-23-5`
+pub fn normalize_package(name: &str) -> String {
+	let name = name.trim();
+	let name = name.rsplit(':').next().unwrap_or(name);
+	let name = name.split('(').next().unwrap_or(name).trim();
+	let name = name.to_uppercase();
+
+	// imperial size codes like 0402
+	let size = name.split(['_', '-', ' '])
+		.filter(|p| !p.ends_with("METRIC"))
+		.map(|p| p.trim_start_matches(['C', 'R', 'L', 'D']))
+		.find(|p| p.len() == 4 && p.chars().all(|c| c.is_ascii_digit()));
+	if let Some(size) = size {
+		return size.into()
+	}
+
+	let mut name = name.split('_').next().unwrap_or(&name).to_string();
+
+	// SOT-23-5L
+	if name.ends_with('L') &&
+		name[..name.len() - 1].ends_with(|c: char| c.is_ascii_digit())
+	{
+		name.pop();
+	}
+
+	name
+}
+
+#[derive(Debug)]
+pub struct PackageMismatch {
+	pub designator: String,
+	pub footprint: String,
+	pub jlcpcb_part: String,
+	pub package: String
+}
+
+/// Compares every placed designator with the package of its part
+///
+/// Returns None if there is no kicad position file.
+pub fn find_package_mismatches(
+	uses_comma: bool
+) -> Option<Vec<PackageMismatch>> {
+	let kicad_entries = read_kicad_entries(uses_comma)?;
+	let custom_entries = read_custom_entries("./bom.csv", uses_comma);
+	let aliases = PackageAliases::read();
+
+	let ids: Vec<_> = custom_entries.iter()
+		.map(|e| e.jlcpcb_part.trim().to_string())
+		.collect();
+	let parts = find_in_parts_list(|p| ids.iter().any(|i| i == p.lcsc.trim()));
+
+	let mut mismatches = vec![];
+
+	for entry in &custom_entries {
+		let Some(part) = parts.iter()
+			.find(|p| p.lcsc.trim() == entry.jlcpcb_part.trim()) else
+		{
+			panic!("could not find {:?}", entry);
+		};
+
+		for designator in split_designators(&entry.designators) {
+			let Some(kicad) = kicad_entries.iter()
+				.find(|e| e.designator == designator) else
+			{
+				continue
+			};
+
+			if !aliases.matches(&kicad.package, &part.package) {
+				mismatches.push(PackageMismatch {
+					designator: designator.into(),
+					footprint: kicad.package.clone(),
+					jlcpcb_part: part.lcsc.trim().into(),
+					package: part.package.clone()
+				});
+			}
+		}
+	}
+
+	mismatches.sort_by(|a, b| natural_cmp(&a.designator, &b.designator));
+
+	Some(mismatches)
+}
+
+#[derive(Debug, Parser)]
+pub struct CheckPackages {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool
+}
+
+pub fn check_packages(args: CheckPackages) {
+	let mismatches = find_package_mismatches(args.uses_comma)
+		.expect("did not find *top-pos.csv file");
+
+	for m in &mismatches {
+		println!(
+			"{}: footprint {:?} ({}) does not match {} {:?} ({})",
+			m.designator, m.footprint, normalize_package(&m.footprint),
+			m.jlcpcb_part, m.package, normalize_package(&m.package)
+		);
+	}
+
+	if !mismatches.is_empty() {
+		println!(
+			"{} mismatches, add aliases to ./package-aliases.csv \
+			if they are correct",
+			mismatches.len()
+		);
+		process::exit(1);
+	}
+
+	println!("all footprints match");
+}
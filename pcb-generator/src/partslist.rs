@@ -1,7 +1,9 @@
+use crate::history;
 use crate::util::{config_dir, create_config_dir};
 
 use std::io;
 use std::fs::{File};
+use std::path::PathBuf;
 
 use clap::Parser;
 
@@ -13,15 +15,24 @@ use reqwest::blocking::{Client};
 
 const PARTS_LIST_FILE: &str = "jlcpcb-parts-list.csv";
 
-fn parts_list_path() -> String {
+pub fn parts_list_path() -> String {
 	format!("{}/{}", config_dir(), PARTS_LIST_FILE)
 }
 
 
 #[derive(Debug, Parser)]
-pub struct DownloadPartsList {}
+pub struct DownloadPartsList {
+	/// The boms whose parts get added to the price and stock history
+	#[clap(long, default_value = "./bom.csv")]
+	bom: Vec<PathBuf>,
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// Warn if a bom part might run out within this many days
+	#[clap(long, default_value_t = 60f64)]
+	warn_days: f64
+}
 
-pub fn download_parts_list(_args: DownloadPartsList) {
+pub fn download_parts_list(args: DownloadPartsList) {
 	let client = Client::new();
 	let resp = client
 		.get("https://jlcpcb.com/componentSearch/uploadComponentInfo")
@@ -44,6 +55,9 @@ pub fn download_parts_list(_args: DownloadPartsList) {
 	io::copy(&mut reader, &mut csv_file).expect("could not write parts list");
 
 	println!("parts list written to {:?}", parts_list_path);
+
+	history::write_snapshot(&args.bom, args.uses_comma);
+	history::warn_low_stock(&args.bom, args.uses_comma, args.warn_days);
 }
 
 #[derive(Debug, Parser)]
@@ -143,8 +157,10 @@ pub struct Part {
 	pub mfr_part: String,
 	#[serde(rename = "Package")]
 	pub package: String,
+	#[allow(dead_code)]
 	#[serde(rename = "Solder Joint")]
 	pub solder_joint: String,
+	#[allow(dead_code)]
 	#[serde(rename = "Manufacturer")]
 	pub manufacturer: String,
 	#[serde(rename = "Library Type")]
@@ -157,4 +173,36 @@ pub struct Part {
 	pub price: String,
 	#[serde(rename = "Stock")]
 	pub stock: usize
-}
\ No newline at end of file
+}
+
+impl Part {
+	/// Returns the price per unit when ordering `qty` units
+	pub fn unit_price(&self, qty: usize) -> Option<f64> {
+		unit_price(&self.price, qty)
+	}
+}
+
+/// Parses a price column like `1-9:0.0123,10-99:0.0098,100-:0.0071` and
+/// returns the price per unit when ordering `qty` units
+pub fn unit_price(price_tiers: &str, qty: usize) -> Option<f64> {
+	let mut price = None;
+
+	for tier in price_tiers.split(',') {
+		let Some((range, tier_price)) = tier.split_once(':') else {
+			continue
+		};
+		let Ok(tier_price) = tier_price.trim().parse::<f64>() else {
+			continue
+		};
+		let from = range.split('-').next()
+			.and_then(|f| f.trim().parse::<usize>().ok())
+			.unwrap_or(0);
+
+		// below the first tier we still need to pay the first tier
+		if price.is_none() || qty >= from {
+			price = Some(tier_price);
+		}
+	}
+
+	price
+}
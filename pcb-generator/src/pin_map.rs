@@ -0,0 +1,386 @@
+use crate::sexpr::Sexpr;
+use crate::util::natural_cmp;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Parser;
+
+/// Generates a rust module with a type alias per net label connected to a
+/// pin of the microcontroller
+///
+/// Only flat (single sheet) schematics are supported.
+#[derive(Debug, Parser)]
+pub struct PinMap {
+	/// The schematic, defaults to the only .kicad_sch in the current dir
+	#[clap(long)]
+	schematic: Option<PathBuf>,
+	/// Reference of the microcontroller, defaults to the first STM32
+	#[clap(long)]
+	mcu: Option<String>,
+	/// Where the module gets written to
+	#[clap(long, default_value = "../os/src/board.rs")]
+	out: PathBuf,
+	/// Fail if the module is not up to date instead of writing it
+	#[clap(long)]
+	check: bool
+}
+
+type Point = (f64, f64);
+/// A point rounded to 1/1000 mm, used to find connected items
+type Key = (i64, i64);
+
+fn key((x, y): Point) -> Key {
+	((x * 1000f64).round() as i64, (y * 1000f64).round() as i64)
+}
+
+/// Union find over the connection points
+#[derive(Default)]
+struct Nets {
+	ids: HashMap<Key, usize>,
+	parent: Vec<usize>
+}
+
+impl Nets {
+	fn id(&mut self, p: Point) -> usize {
+		let next = self.parent.len();
+		let id = *self.ids.entry(key(p)).or_insert(next);
+		if id == next {
+			self.parent.push(id);
+		}
+		id
+	}
+
+	fn find(&mut self, mut id: usize) -> usize {
+		while self.parent[id] != id {
+			self.parent[id] = self.parent[self.parent[id]];
+			id = self.parent[id];
+		}
+		id
+	}
+
+	fn union(&mut self, a: Point, b: Point) {
+		let a = self.id(a);
+		let b = self.id(b);
+		let (a, b) = (self.find(a), self.find(b));
+		self.parent[a] = b;
+	}
+
+	fn net(&mut self, p: Point) -> usize {
+		let id = self.id(p);
+		self.find(id)
+	}
+}
+
+/// Returns true if `p` lies on the segment between `a` and `b`
+fn on_segment(p: Point, a: Point, b: Point) -> bool {
+	let eps = 1e-3;
+	let cross = (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0);
+	let len = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+	if len < eps || cross.abs() > eps * len {
+		return false
+	}
+
+	p.0 >= a.0.min(b.0) - eps && p.0 <= a.0.max(b.0) + eps &&
+		p.1 >= a.1.min(b.1) - eps && p.1 <= a.1.max(b.1) + eps
+}
+
+fn at(sexpr: &Sexpr) -> Option<(Point, f64)> {
+	let at = sexpr.child("at")?;
+	Some(((at.num(0)?, at.num(1)?), at.num(2).unwrap_or(0f64)))
+}
+
+/// A pin of a placed symbol
+struct Pin {
+	name: String,
+	number: String,
+	pos: Point
+}
+
+/// Returns the pins of a placed symbol in schematic coordinates
+fn symbol_pins(instance: &Sexpr, lib_symbol: &Sexpr) -> Vec<Pin> {
+	let Some(((x, y), rotation)) = at(instance) else { return vec![] };
+	let unit = instance.child("unit").and_then(|u| u.num(0)).unwrap_or(1f64);
+	let mirror = instance.child("mirror").and_then(|m| m.atom(0));
+	let lib_name = lib_symbol.atom(0).unwrap_or("");
+	let base_name = lib_name.rsplit(':').next().unwrap_or(lib_name);
+
+	// units are stored as `Name_Unit_Style`, unit 0 is shared by all units
+	let units = lib_symbol.children("symbol").filter(|s| {
+		let name = s.atom(0).unwrap_or("");
+		let unit_nr = name.strip_prefix(base_name)
+			.and_then(|r| r.strip_prefix('_'))
+			.and_then(|r| r.split('_').next())
+			.and_then(|u| u.parse::<f64>().ok());
+		matches!(unit_nr, Some(u) if u == 0f64 || u == unit)
+	});
+
+	let (sin, cos) = rotation.to_radians().sin_cos();
+
+	units.flat_map(|u| u.children("pin"))
+		.filter_map(|pin| {
+			let ((px, py), _) = at(pin)?;
+			// the library uses y pointing up
+			let (px, py) = (px, -py);
+			let (mut dx, mut dy) = (px * cos + py * sin, -px * sin + py * cos);
+			match mirror {
+				Some("x") => dy = -dy,
+				Some("y") => dx = -dx,
+				_ => {}
+			}
+
+			Some(Pin {
+				name: pin.child("name")?.atom(0)?.into(),
+				number: pin.child("number")?.atom(0)?.into(),
+				pos: (x + dx, y + dy)
+			})
+		})
+		.collect()
+}
+
+/// Returns `(port, number)` for pin names like `PB10`
+fn gpio(name: &str) -> Option<(char, u8)> {
+	let rest = name.strip_prefix('P')?;
+	let mut chars = rest.chars();
+	let port = chars.next().filter(|c| ('A'..='K').contains(c))?;
+	let number = chars.as_str().parse().ok()?;
+	Some((port, number))
+}
+
+/// Converts `TIM2_PWM1` to `Tim2Pwm1`
+fn type_name(label: &str) -> String {
+	let mut name = String::new();
+	for part in label.split(|c: char| !c.is_ascii_alphanumeric()) {
+		let mut chars = part.chars();
+		if let Some(first) = chars.next() {
+			name.push(first.to_ascii_uppercase());
+			name.push_str(&chars.as_str().to_ascii_lowercase());
+		}
+	}
+	if name.starts_with(|c: char| c.is_ascii_digit()) {
+		name.insert_str(0, "Pin");
+	}
+	name
+}
+
+struct Mapping {
+	label: String,
+	pin_name: String,
+	pin_number: String,
+	port: char,
+	number: u8
+}
+
+/// Returns every label connected to a gpio of the microcontroller
+fn read_mappings(sch: &Sexpr, mcu: Option<&str>) -> (String, Vec<Mapping>) {
+	let lib_symbols: HashMap<_, _> = sch.child("lib_symbols")
+		.map(|l| l.children("symbol")
+			.filter_map(|s| Some((s.atom(0)?.to_string(), s)))
+			.collect())
+		.unwrap_or_default();
+
+	let reference = |s: &Sexpr| s.children("property")
+		.find(|p| p.atom(0) == Some("Reference"))
+		.and_then(|p| p.atom(1))
+		.unwrap_or("")
+		.to_string();
+	let lib_id = |s: &Sexpr| s.child("lib_id")
+		.and_then(|l| l.atom(0))
+		.unwrap_or("")
+		.to_string();
+
+	let instances: Vec<_> = sch.children("symbol").collect();
+	let mcu_instances: Vec<_> = instances.iter()
+		.filter(|s| match mcu {
+			Some(mcu) => reference(s) == mcu,
+			None => lib_id(s).contains("STM32")
+		})
+		.collect();
+	let Some(first) = mcu_instances.first() else {
+		panic!("microcontroller {:?} not found", mcu.unwrap_or("STM32"));
+	};
+	let mcu_ref = reference(first);
+
+	let mut nets = Nets::default();
+
+	let wires: Vec<(Point, Point)> = sch.children("wire")
+		.filter_map(|w| {
+			let mut pts = w.child("pts")?.children("xy");
+			let a = pts.next()?;
+			let b = pts.next()?;
+			Some(((a.num(0)?, a.num(1)?), (b.num(0)?, b.num(1)?)))
+		})
+		.collect();
+	for (a, b) in &wires {
+		nets.union(*a, *b);
+	}
+
+	let labels: Vec<(String, Point)> = sch.as_list().iter()
+		.filter(|s| matches!(
+			s.name(),
+			Some("label" | "global_label" | "hierarchical_label")
+		))
+		.filter_map(|s| Some((s.atom(0)?.to_string(), at(s)?.0)))
+		.collect();
+
+	let pins: Vec<Pin> = mcu_instances.iter()
+		.filter_map(|s| Some(symbol_pins(s, lib_symbols.get(&lib_id(s))?)))
+		.flatten()
+		.collect();
+
+	let junctions: Vec<Point> = sch.children("junction")
+		.filter_map(|j| Some(at(j)?.0))
+		.collect();
+
+	// points which touch a wire are connected to it, also in the middle
+	let points = wires.iter().flat_map(|(a, b)| [*a, *b])
+		.chain(labels.iter().map(|(_, p)| *p))
+		.chain(pins.iter().map(|p| p.pos))
+		.chain(junctions);
+	for p in points.collect::<Vec<_>>() {
+		for (a, b) in &wires {
+			if on_segment(p, *a, *b) {
+				nets.union(p, *a);
+			}
+		}
+	}
+
+	// labels with the same name are connected
+	let mut by_name: HashMap<&str, Point> = HashMap::new();
+	for (name, p) in &labels {
+		match by_name.get(name.as_str()) {
+			Some(other) => nets.union(*p, *other),
+			None => {
+				by_name.insert(name, *p);
+			}
+		}
+	}
+
+	let mut net_labels: BTreeMap<usize, BTreeSet<&str>> = BTreeMap::new();
+	for (name, p) in &labels {
+		let net = nets.net(*p);
+		net_labels.entry(net).or_default().insert(name);
+	}
+
+	let mut mappings = vec![];
+	for pin in &pins {
+		let Some((port, number)) = gpio(&pin.name) else { continue };
+		let net = nets.net(pin.pos);
+		for label in net_labels.get(&net).into_iter().flatten() {
+			mappings.push(Mapping {
+				label: label.to_string(),
+				pin_name: pin.name.clone(),
+				pin_number: pin.number.clone(),
+				port,
+				number
+			});
+		}
+	}
+	mappings.sort_by(|a, b| natural_cmp(&a.label, &b.label));
+
+	(mcu_ref, mappings)
+}
+
+fn render(source: &str, mcu: &str, mappings: &[Mapping]) -> String {
+	let mut out = String::new();
+	writeln!(out, "//! Pins of {} as labeled in {}", mcu, source).unwrap();
+	out.push_str("//!\n");
+	out.push_str(
+		"//! Generated by `pcb-generator pin-map`, do not edit by hand.\n"
+	);
+	out.push('\n');
+	out.push_str("#![allow(dead_code)]\n\n");
+
+	let ports: BTreeSet<_> = mappings.iter().map(|m| m.port).collect();
+	let ports: Vec<_> = ports.iter()
+		.map(|p| format!("gpio{}", p.to_ascii_lowercase()))
+		.collect();
+	match ports.len() {
+		0 => {},
+		1 => writeln!(out, "use hal::gpio::{};\n", ports[0]).unwrap(),
+		_ => writeln!(out, "use hal::gpio::{{{}}};\n", ports.join(", ")).unwrap()
+	}
+
+	let mut first = true;
+	for m in mappings {
+		if !first {
+			out.push('\n');
+		}
+		first = false;
+
+		let port = m.port.to_ascii_lowercase();
+		writeln!(
+			out,
+			"/// {} on {} (pin {})\n\
+			pub type {}<MODE> = gpio{}::P{}{}<MODE>;",
+			m.label, m.pin_name, m.pin_number,
+			type_name(&m.label), port, m.port, m.number
+		).unwrap();
+	}
+
+	out
+}
+
+fn find_schematic() -> PathBuf {
+	let mut files: Vec<_> = fs::read_dir(".")
+		.expect("could not read current dir")
+		.filter_map(|e| e.ok())
+		.map(|e| e.path())
+		.filter(|p| p.extension().is_some_and(|e| e == "kicad_sch"))
+		.collect();
+	assert!(files.len() == 1, "expected one .kicad_sch found {:?}", files);
+	files.remove(0)
+}
+
+/// Returns the generated module and how many pins it maps
+fn generate(path: &Path, mcu: Option<&str>) -> (String, usize) {
+	let raw = fs::read_to_string(path)
+		.unwrap_or_else(|e| panic!("could not read {:?} {:?}", path, e));
+	let sch = Sexpr::parse(&raw)
+		.unwrap_or_else(|e| panic!("could not parse {:?} {}", path, e));
+
+	let (mcu, mappings) = read_mappings(&sch, mcu);
+
+	// two labels with the same type name would not compile
+	let mut names = HashMap::new();
+	for m in &mappings {
+		if let Some(other) = names.insert(type_name(&m.label), &m.label) {
+			panic!("{} and {} result in the same name", other, m.label);
+		}
+	}
+
+	let source = path.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	(render(&source, &mcu, &mappings), mappings.len())
+}
+
+/// Checks if `out` matches the module generated from the only schematic
+/// in the current dir
+pub fn pin_map_is_current(out: &Path) -> bool {
+	let (module, _) = generate(&find_schematic(), None);
+	fs::read_to_string(out).is_ok_and(|current| current == module)
+}
+
+pub fn pin_map(args: PinMap) {
+	let path = args.schematic.clone().unwrap_or_else(find_schematic);
+	let (module, pins) = generate(&path, args.mcu.as_deref());
+
+	if args.check {
+		let current = fs::read_to_string(&args.out).unwrap_or_default();
+		if current != module {
+			eprintln!(
+				"{:?} is out of date, run pcb-generator pin-map", args.out
+			);
+			process::exit(1);
+		}
+		println!("{:?} is up to date", args.out);
+		return
+	}
+
+	fs::write(&args.out, module).expect("could not write pin map");
+	println!("{} pins written to {:?}", pins, args.out);
+}
@@ -0,0 +1,536 @@
+use crate::board::{find_board_file, Board};
+use crate::bom::{
+	read_custom_entries, split_designators, try_group_entries, value_matches
+};
+use crate::cpl::{read_kicad_entries, read_rotation_table};
+use crate::gerber_image::parse_gerber;
+use crate::impedance::find_stackup;
+use crate::lint_lib::{lint_library, Level};
+use crate::packages::find_package_mismatches;
+use crate::partslist::{find_in_parts_list, parts_list_path};
+use crate::pin_map::pin_map_is_current;
+use crate::util::{create_build_dir, BUILD_DIR};
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use clap::Parser;
+
+const OUTPUT_DIR: &str = "./output";
+/// Layers jlcpcb needs for a two layer board (kicad 6 names)
+const REQUIRED_LAYERS: &[&str] = &[
+	"F_Cu", "B_Cu", "F_Mask", "B_Mask", "F_Silkscreen", "B_Silkscreen",
+	"Edge_Cuts"
+];
+
+/// Runs every check before an order and writes a markdown summary
+#[derive(Debug, Parser)]
+pub struct Preflight {
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool,
+	/// How many boards get ordered, used for the stock check
+	#[clap(long, default_value_t = 5)]
+	boards: usize,
+	/// The stackup which will be ordered, see `impedance --list-stackups`
+	#[clap(long)]
+	stackup: Option<String>,
+	/// The expected board size in mm, for example `95x56`
+	#[clap(long)]
+	board_size: Option<String>,
+	/// The parts list counts as outdated after this many days
+	#[clap(long, default_value_t = 7)]
+	max_parts_list_age: u64,
+	/// The module written by `pin-map`, skipped if it does not exist
+	#[clap(long, default_value = "../os/src/board.rs")]
+	pin_map: PathBuf
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+	Pass,
+	Warn,
+	Fail
+}
+
+impl Status {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Pass => "pass",
+			Self::Warn => "warn",
+			Self::Fail => "FAIL"
+		}
+	}
+}
+
+struct Check {
+	name: &'static str,
+	status: Status,
+	/// One line per finding
+	details: Vec<String>
+}
+
+/// Collects the findings of a check, the worst finding sets the status
+#[derive(Default)]
+struct Findings {
+	status: Option<Status>,
+	details: Vec<String>
+}
+
+impl Findings {
+	fn add(&mut self, status: Status, detail: impl Into<String>) {
+		self.status = self.status.max(Some(status));
+		self.details.push(detail.into());
+	}
+
+	fn pass(&mut self, detail: impl Into<String>) {
+		self.add(Status::Pass, detail);
+	}
+
+	fn warn(&mut self, detail: impl Into<String>) {
+		self.add(Status::Warn, detail);
+	}
+
+	fn fail(&mut self, detail: impl Into<String>) {
+		self.add(Status::Fail, detail);
+	}
+}
+
+/// Runs a check, a panic counts as a failure
+fn run_check(
+	name: &'static str,
+	f: impl FnOnce(&mut Findings)
+) -> Check {
+	let mut findings = Findings::default();
+	let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut findings)));
+
+	if let Err(e) = result {
+		let msg = e.downcast_ref::<String>().cloned()
+			.or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+			.unwrap_or_else(|| "unknown error".into());
+		findings.fail(msg);
+	}
+
+	Check {
+		name,
+		status: findings.status.unwrap_or(Status::Pass),
+		details: findings.details
+	}
+}
+
+fn modified(path: impl AsRef<Path>) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Returns the files in the kicad output dir
+fn output_files() -> Vec<PathBuf> {
+	let mut files: Vec<_> = fs::read_dir(OUTPUT_DIR)
+		.map(|r| r.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+		.unwrap_or_default();
+	files.sort();
+	files
+}
+
+/// `jag-v1-F_Cu.gbr` becomes `F_Cu`
+fn layer_key(path: &Path) -> String {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	stem.rsplit('-').next().unwrap_or(&stem).to_string()
+}
+
+fn gerber_files() -> Vec<PathBuf> {
+	output_files().into_iter()
+		.filter(|p| p.extension().is_some_and(|e| e == "gbr"))
+		.collect()
+}
+
+fn check_parts_list(args: &Preflight, f: &mut Findings) {
+	let path = parts_list_path();
+	let Some(modified) = modified(&path) else {
+		f.fail("no parts list, run download-parts-list");
+		return
+	};
+
+	let age = SystemTime::now().duration_since(modified)
+		.unwrap_or(Duration::ZERO);
+	let days = age.as_secs() / 86400;
+	if days > args.max_parts_list_age {
+		f.warn(format!(
+			"parts list is {} days old, run download-parts-list", days
+		));
+	} else {
+		f.pass(format!("parts list is {} days old", days));
+	}
+}
+
+fn check_stock(args: &Preflight, f: &mut Findings) {
+	let entries = read_custom_entries("./bom.csv", args.uses_comma);
+	let groups = match try_group_entries(&entries) {
+		Ok(groups) => groups,
+		Err(designator) => {
+			f.fail(format!("{} is listed twice in the bom", designator));
+			return
+		}
+	};
+	let ids: Vec<_> = groups.iter().map(|(id, _)| id.to_string()).collect();
+	let parts = find_in_parts_list(|p| {
+		ids.iter().any(|i| i == p.lcsc.trim())
+	});
+
+	for (id, designators) in &groups {
+		let needed = designators.len() * args.boards;
+		match parts.iter().find(|p| p.lcsc.trim() == *id) {
+			None => f.fail(format!("{} is not in the parts list", id)),
+			Some(p) if p.stock < needed => f.fail(format!(
+				"{} needs {} but only {} are in stock", id, needed, p.stock
+			)),
+			Some(_) => {}
+		}
+	}
+
+	if f.details.is_empty() {
+		f.pass(format!(
+			"{} parts in stock for {} boards", ids.len(), args.boards
+		));
+	}
+}
+
+fn check_values(args: &Preflight, f: &mut Findings) {
+	let Some(kicad_entries) = read_kicad_entries(args.uses_comma) else {
+		f.fail("no *top-pos.csv in ./output");
+		return
+	};
+	let entries = read_custom_entries("./bom.csv", args.uses_comma);
+	let ids: Vec<_> = entries.iter()
+		.map(|e| e.jlcpcb_part.trim().to_string())
+		.collect();
+	let parts = find_in_parts_list(|p| {
+		ids.iter().any(|i| i == p.lcsc.trim())
+	});
+
+	let mut checked = 0;
+	for entry in &entries {
+		let id = entry.jlcpcb_part.trim();
+		// missing parts are reported by the stock check
+		let Some(part) = parts.iter().find(|p| p.lcsc.trim() == id) else {
+			continue
+		};
+
+		for designator in split_designators(&entry.designators) {
+			let Some(kicad) = kicad_entries.iter()
+				.find(|e| e.designator == designator) else
+			{
+				continue
+			};
+
+			checked += 1;
+			if !value_matches(&kicad.value, part) {
+				f.warn(format!(
+					"{} value {:?} does not match {} {:?}",
+					designator, kicad.value, id, part.desc
+				));
+			}
+		}
+	}
+
+	if f.details.is_empty() {
+		f.pass(format!("{} values match the parts", checked));
+	}
+}
+
+fn check_pin_map(args: &Preflight, f: &mut Findings) {
+	if !args.pin_map.is_file() {
+		f.pass(format!("no pin map at {:?}", args.pin_map));
+	} else if pin_map_is_current(&args.pin_map) {
+		f.pass(format!("{:?} is up to date", args.pin_map));
+	} else {
+		f.fail(format!(
+			"{:?} is out of date, run pin-map", args.pin_map
+		));
+	}
+}
+
+fn check_bom_cpl(args: &Preflight, f: &mut Findings) {
+	let Some(kicad_entries) = read_kicad_entries(args.uses_comma) else {
+		f.fail("no *top-pos.csv in ./output");
+		return
+	};
+	let entries = read_custom_entries("./bom.csv", args.uses_comma);
+
+	let placed: BTreeSet<_> = kicad_entries.iter()
+		.map(|e| e.designator.as_str())
+		.collect();
+	let in_bom: BTreeSet<_> = entries.iter()
+		.filter(|e| !e.jlcpcb_part.trim().is_empty())
+		.flat_map(|e| split_designators(&e.designators))
+		.collect();
+
+	for d in in_bom.difference(&placed) {
+		f.fail(format!("{} is in the bom but not in the position file", d));
+	}
+	for d in placed.difference(&in_bom) {
+		f.warn(format!("{} is placed but not in the bom", d));
+	}
+
+	match find_package_mismatches(args.uses_comma) {
+		Some(mismatches) => for m in mismatches {
+			f.warn(format!(
+				"{} footprint {:?} does not match {} {:?}",
+				m.designator, m.footprint, m.jlcpcb_part, m.package
+			));
+		},
+		None => f.fail("no position file to compare the packages")
+	}
+
+	// the generated files need to be newer than their inputs
+	let inputs = ["./bom.csv", "./rotation-table.csv"].into_iter()
+		.map(PathBuf::from)
+		.chain(output_files().into_iter().filter(|p| {
+			p.to_string_lossy().ends_with("pos.csv")
+		}))
+		.filter_map(modified)
+		.max();
+	for out in ["bom.csv", "cpl.csv"] {
+		let path = format!("{}/{}", BUILD_DIR, out);
+		match (modified(&path), inputs) {
+			(None, _) => f.fail(format!("{} missing, run bom and cpl", path)),
+			(Some(a), Some(b)) if a < b => {
+				f.warn(format!("{} is older than its inputs", path))
+			},
+			_ => {}
+		}
+	}
+
+	if f.details.is_empty() {
+		f.pass(format!("{} placed parts match the bom", placed.len()));
+	}
+}
+
+fn check_rotations(args: &Preflight, f: &mut Findings) {
+	let table = read_rotation_table();
+	if table.is_empty() {
+		f.warn("no rotation-table.csv, rotations were never reviewed");
+		return
+	}
+
+	let Some(kicad_entries) = read_kicad_entries(args.uses_comma) else {
+		f.fail("no *top-pos.csv in ./output");
+		return
+	};
+	let placed: BTreeSet<_> = kicad_entries.into_iter()
+		.map(|e| e.designator)
+		.collect();
+	let mut stale: Vec<_> = table.keys()
+		.filter(|d| !placed.contains(*d))
+		.map(|d| d.as_str())
+		.collect();
+	stale.sort();
+	if !stale.is_empty() {
+		f.warn(format!(
+			"rotation-table.csv contains unplaced {}", stale.join(", ")
+		));
+	}
+
+	let positions = output_files().into_iter()
+		.filter(|p| p.to_string_lossy().ends_with("pos.csv"))
+		.filter_map(modified)
+		.max();
+	if let (Some(table), Some(positions)) =
+		(modified("./rotation-table.csv"), positions)
+	{
+		if table < positions {
+			f.warn(
+				"positions were exported after the rotation table changed, \
+				check the pick and place preview"
+			);
+		}
+	}
+
+	if f.details.is_empty() {
+		f.pass(format!("{} rotation corrections", table.len()));
+	}
+}
+
+fn check_gerbers(f: &mut Findings) {
+	let files = output_files();
+	let keys: BTreeSet<_> = files.iter()
+		.filter(|p| p.extension().is_some_and(|e| e == "gbr"))
+		.map(|p| layer_key(p))
+		.collect();
+
+	if keys.is_empty() {
+		f.fail("no gerber files in ./output");
+		return
+	}
+
+	for layer in REQUIRED_LAYERS {
+		if !keys.contains(*layer) {
+			f.fail(format!("{} layer missing", layer));
+		}
+	}
+
+	if !files.iter().any(|p| p.extension().is_some_and(|e| e == "drl")) {
+		f.fail("no drill file");
+	}
+
+	let newest = files.iter().filter_map(modified).max();
+	let zip = format!("{}/gerber.zip", BUILD_DIR);
+	match (modified(&zip), newest) {
+		(None, _) => f.fail(format!("{} missing, run gerber", zip)),
+		(Some(a), Some(b)) if a < b => {
+			f.warn(format!("{} is older than ./output", zip))
+		},
+		_ => {}
+	}
+
+	if f.details.is_empty() {
+		f.pass(format!("{} layers and drill file", keys.len()));
+	}
+}
+
+fn check_board_size(args: &Preflight, f: &mut Findings) {
+	let edge = gerber_files().into_iter()
+		.find(|p| layer_key(p) == "Edge_Cuts")
+		.and_then(|p| {
+			parse_gerber(&fs::read_to_string(p).expect("read gerber")).bounds()
+		});
+	let outline = find_board_file(".")
+		.and_then(|p| Board::read(p).bounds());
+
+	let size = |((x0, y0), (x1, y1)): ((f64, f64), (f64, f64))| {
+		(x1 - x0, y1 - y0)
+	};
+
+	let Some((w, h)) = edge.or(outline).map(size) else {
+		f.fail("no Edge_Cuts gerber and no .kicad_pcb found");
+		return
+	};
+
+	// the gerber includes the line width of the outline
+	let tolerance = 0.3;
+	if let (Some(_), Some(outline)) = (edge, outline) {
+		let (ow, oh) = size(outline);
+		if (w - ow).abs() > tolerance || (h - oh).abs() > tolerance {
+			f.fail(format!(
+				"gerber outline {:.2}x{:.2}mm does not match the board \
+				{:.2}x{:.2}mm, export the gerbers again",
+				w, h, ow, oh
+			));
+		}
+	}
+
+	match &args.board_size {
+		Some(expected) => {
+			let (ew, eh) = expected.split_once('x')
+				.and_then(|(a, b)| Some((
+					a.trim().parse::<f64>().ok()?,
+					b.trim().parse::<f64>().ok()?
+				)))
+				.expect("board size needs to look like 95x56");
+			if (w - ew).abs() > tolerance || (h - eh).abs() > tolerance {
+				f.fail(format!(
+					"board is {:.2}x{:.2}mm expected {}mm", w, h, expected
+				));
+			} else {
+				f.pass(format!("board is {:.2}x{:.2}mm", w, h));
+			}
+		},
+		None => f.warn(format!(
+			"board is {:.2}x{:.2}mm, pass --board-size to check it", w, h
+		))
+	}
+}
+
+fn check_stackup(args: &Preflight, f: &mut Findings) {
+	let Some(name) = &args.stackup else {
+		f.warn("no stackup chosen, pass --stackup");
+		return
+	};
+	let Some(stackup) = find_stackup(name) else {
+		f.fail(format!("unknown stackup {}", name));
+		return
+	};
+
+	let copper = gerber_files().iter()
+		.filter(|p| layer_key(p).ends_with("_Cu"))
+		.count();
+	if copper > 0 && copper != stackup.copper.len() {
+		f.fail(format!(
+			"{} has {} copper layers but the gerbers have {}",
+			stackup.name, stackup.copper.len(), copper
+		));
+	} else {
+		f.pass(format!("{} ({})", stackup.name, stackup.desc));
+	}
+}
+
+fn check_library(f: &mut Findings) {
+	for finding in lint_library(None) {
+		match finding.level {
+			Level::Error => f.fail(finding.message),
+			Level::Warning => f.warn(finding.message)
+		}
+	}
+
+	if f.details.is_empty() {
+		f.pass("no findings");
+	}
+}
+
+fn markdown(checks: &[Check]) -> String {
+	let mut md = String::from("## Preflight\n\n");
+	md.push_str("| Check | Status | Details |\n|---|---|---|\n");
+	for check in checks {
+		let details = check.details.join("<br>").replace('|', "\\|");
+		writeln!(
+			md, "| {} | {} | {} |",
+			check.name, check.status.as_str(), details
+		).unwrap();
+	}
+	md
+}
+
+pub fn preflight(args: Preflight) {
+	// the panic messages are shown in the table
+	let hook = panic::take_hook();
+	panic::set_hook(Box::new(|_| {}));
+
+	let checks = vec![
+		run_check("parts list", |f| check_parts_list(&args, f)),
+		run_check("stock", |f| check_stock(&args, f)),
+		run_check("bom / cpl", |f| check_bom_cpl(&args, f)),
+		run_check("values", |f| check_values(&args, f)),
+		run_check("rotations", |f| check_rotations(&args, f)),
+		run_check("gerbers", check_gerbers),
+		run_check("board size", |f| check_board_size(&args, f)),
+		run_check("stackup", |f| check_stackup(&args, f)),
+		run_check("library", check_library),
+		run_check("pin map", |f| check_pin_map(&args, f))
+	];
+
+	panic::set_hook(hook);
+
+	for check in &checks {
+		let mut details = check.details.iter();
+		println!(
+			"{:<12} {:<5} {}",
+			check.name, check.status.as_str(),
+			details.next().map(|s| s.as_str()).unwrap_or("")
+		);
+		for detail in details {
+			println!("{:<12} {:<5} {}", "", "", detail);
+		}
+	}
+
+	create_build_dir();
+	let path = format!("{}/preflight.md", BUILD_DIR);
+	fs::write(&path, markdown(&checks)).expect("could not write summary");
+	println!("summary written to {}", path);
+
+	if checks.iter().any(|c| c.status == Status::Fail) {
+		process::exit(1);
+	}
+}
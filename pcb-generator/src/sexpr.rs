@@ -0,0 +1,109 @@
+//! A minimal parser for the s-expression files kicad writes
+//! (.kicad_pcb, .kicad_mod, .kicad_sym, .kicad_sch, fp-lib-table)
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sexpr {
+	/// Symbols and quoted strings (without quotes)
+	Atom(String),
+	List(Vec<Sexpr>)
+}
+
+impl Sexpr {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let mut chars = s.char_indices().peekable();
+		let mut stack: Vec<Vec<Sexpr>> = vec![];
+		let mut root = None;
+
+		while let Some((i, c)) = chars.next() {
+			match c {
+				'(' => stack.push(vec![]),
+				')' => {
+					let list = stack.pop()
+						.ok_or_else(|| format!("unexpected ) at {}", i))?;
+					match stack.last_mut() {
+						Some(parent) => parent.push(Sexpr::List(list)),
+						None => {
+							root = Some(Sexpr::List(list));
+							break
+						}
+					}
+				},
+				'"' => {
+					let mut atom = String::new();
+					loop {
+						match chars.next() {
+							Some((_, '\\')) => match chars.next() {
+								Some((_, 'n')) => atom.push('\n'),
+								Some((_, c)) => atom.push(c),
+								None => break
+							},
+							Some((_, '"')) => break,
+							Some((_, c)) => atom.push(c),
+							None => return Err("unterminated string".into())
+						}
+					}
+					stack.last_mut()
+						.ok_or_else(|| format!("string outside list at {}", i))?
+						.push(Sexpr::Atom(atom));
+				},
+				c if c.is_whitespace() => {},
+				c => {
+					let mut atom = c.to_string();
+					while let Some((_, c)) = chars.peek() {
+						if c.is_whitespace() || *c == '(' || *c == ')' {
+							break
+						}
+						atom.push(*c);
+						chars.next();
+					}
+					stack.last_mut()
+						.ok_or_else(|| format!("atom outside list at {}", i))?
+						.push(Sexpr::Atom(atom));
+				}
+			}
+		}
+
+		root.ok_or_else(|| "unterminated list".into())
+	}
+
+	pub fn as_atom(&self) -> Option<&str> {
+		match self {
+			Self::Atom(a) => Some(a),
+			Self::List(_) => None
+		}
+	}
+
+	pub fn as_list(&self) -> &[Sexpr] {
+		match self {
+			Self::Atom(_) => &[],
+			Self::List(l) => l
+		}
+	}
+
+	/// The first atom of a list `(name ...)`
+	pub fn name(&self) -> Option<&str> {
+		self.as_list().first()?.as_atom()
+	}
+
+	/// Returns the nth atom after the name
+	pub fn atom(&self, n: usize) -> Option<&str> {
+		self.as_list().get(n + 1)?.as_atom()
+	}
+
+	/// Returns the nth number after the name
+	pub fn num(&self, n: usize) -> Option<f64> {
+		self.atom(n)?.parse().ok()
+	}
+
+	/// Returns all child lists with the given name
+	pub fn children<'a>(
+		&'a self,
+		name: &'a str
+	) -> impl Iterator<Item=&'a Sexpr> + 'a {
+		self.as_list().iter().filter(move |s| s.name() == Some(name))
+	}
+
+	pub fn child(&self, name: &str) -> Option<&Sexpr> {
+		self.as_list().iter().find(|s| s.name() == Some(name))
+	}
+}
@@ -1,7 +1,11 @@
 
 use std::{fs, env};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::SystemTime;
 
 pub const BUILD_DIR: &str = "./build";
 const CONFIG_DIR: &str = ".config/pcb-generator";
@@ -25,11 +29,135 @@ pub fn create_config_dir() -> String {
 pub fn create_zip(name: &str, path: &str) {
 	let status = Command::new("zip")
 		.arg("-r")
-		.args(&[name, path])
+		.args([name, path])
 		.status()
 		.expect("could not load zip");
 
 	if !status.success() {
 		panic!("could not zip {}", path);
 	}
-}
\ No newline at end of file
+}
+
+/// Escapes text for html or svg
+pub fn escape(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+/// Compares two strings treating numbers as numbers
+///
+/// `C3 < C11` instead of `C11 < C3`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+	let mut a = a.chars().peekable();
+	let mut b = b.chars().peekable();
+
+	loop {
+		match (a.peek(), b.peek()) {
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(ca), Some(cb))
+				if ca.is_ascii_digit() && cb.is_ascii_digit() =>
+			{
+				let na = take_number(&mut a);
+				let nb = take_number(&mut b);
+				match na.cmp(&nb) {
+					Ordering::Equal => {},
+					ord => return ord
+				}
+			},
+			(Some(ca), Some(cb)) => {
+				match ca.cmp(cb) {
+					Ordering::Equal => {
+						a.next();
+						b.next();
+					},
+					ord => return ord
+				}
+			}
+		}
+	}
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> u64 {
+	let mut n = 0u64;
+	while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+		n = n.saturating_mul(10).saturating_add(d as u64);
+		chars.next();
+	}
+	n
+}
+
+pub fn extract_zip(zip: &str, dir: &str) {
+	let status = Command::new("unzip")
+		.args(["-o", "-q", zip, "-d", dir])
+		.status()
+		.expect("could not load unzip");
+
+	if !status.success() {
+		panic!("could not unzip {}", zip);
+	}
+}
+
+/// Returns the directory the content of an extracted zip is in
+///
+/// Zips created with `create_zip` contain a single top level directory.
+pub fn extracted_root(dir: &Path) -> PathBuf {
+	let mut dir = dir.to_path_buf();
+	loop {
+		let entries: Vec<_> = fs::read_dir(&dir)
+			.unwrap_or_else(|e| panic!("could not read {:?} {:?}", dir, e))
+			.map(|e| e.unwrap().path())
+			.collect();
+
+		match entries.as_slice() {
+			[single] if single.is_dir() => dir = single.clone(),
+			_ => return dir
+		}
+	}
+}
+
+/// Returns the current date (UTC) as `YYYY-MM-DD`
+pub fn today() -> String {
+	let secs = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.expect("time before 1970")
+		.as_secs();
+	format_date((secs / 86400) as i64)
+}
+
+/// Formats days since 1970-01-01 as `YYYY-MM-DD`
+pub fn format_date(days: i64) -> String {
+	// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+	format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+/// Parses `YYYY-MM-DD` to days since 1970-01-01
+pub fn parse_date(s: &str) -> Option<i64> {
+	let mut parts = s.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+	let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+	if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+		return None
+	}
+
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = y.div_euclid(400);
+	let yoe = y.rem_euclid(400);
+	let mp = if m > 2 { m - 3 } else { m + 9 };
+	let doy = (153 * mp + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+	Some(era * 146097 + doe - 719468)
+}
@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::Parser;
+
+/// The directory kicad exports the gerber, drill and position files to
+const EXPORT_DIR: &str = "./output";
+/// Files in the project dir which affect the generated outputs
+const INPUT_FILES: &[&str] = &[
+	"./bom.csv",
+	"./rotation-table.csv",
+	"./package-aliases.csv"
+];
+
+/// Watches the kicad export dir and the bom and regenerates the outputs
+/// which are affected by a change
+#[derive(Debug, Parser)]
+pub struct Watch {
+	/// How often the files get checked in milliseconds
+	#[clap(long, default_value_t = 500)]
+	interval: u64,
+	/// How long the files need to be unchanged before regenerating, kicad
+	/// writes the files one after another
+	#[clap(long, default_value_t = 1000)]
+	debounce: u64,
+	/// Don't generate everything on start
+	#[clap(long)]
+	no_initial: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Output {
+	Gerber,
+	Cpl,
+	Bom
+}
+
+impl Output {
+	const ALL: &'static [Output] = &[Output::Gerber, Output::Cpl, Output::Bom];
+
+	fn subcommand(&self) -> &'static str {
+		match self {
+			Self::Gerber => "gerber",
+			Self::Cpl => "cpl",
+			Self::Bom => "bom"
+		}
+	}
+
+	/// Returns the outputs which depend on the file
+	fn affected_by(path: &Path) -> &'static [Output] {
+		let name = path.file_name()
+			.and_then(|n| n.to_str())
+			.unwrap_or("");
+
+		match name {
+			"bom.csv" | "package-aliases.csv" => &[Output::Bom],
+			"rotation-table.csv" => &[Output::Cpl],
+			// the bom checks the values and footprints against the positions
+			n if n.ends_with("pos.csv") => &[Output::Cpl, Output::Bom],
+			n if n.ends_with(".gbr") || n.ends_with(".drl") => &[Output::Gerber],
+			_ => &[]
+		}
+	}
+}
+
+type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+fn snapshot() -> Snapshot {
+	let mut files = Snapshot::new();
+
+	let mut add = |path: PathBuf| {
+		if let Ok(meta) = fs::metadata(&path) {
+			if meta.is_file() {
+				let modified = meta.modified()
+					.unwrap_or(SystemTime::UNIX_EPOCH);
+				files.insert(path, (modified, meta.len()));
+			}
+		}
+	};
+
+	for file in INPUT_FILES {
+		add(PathBuf::from(file));
+	}
+
+	if let Ok(read_dir) = fs::read_dir(EXPORT_DIR) {
+		for entry in read_dir.filter_map(|e| e.ok()) {
+			add(entry.path());
+		}
+	}
+
+	files
+}
+
+/// Returns every file which was added, removed or modified
+fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+	let paths: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+	paths.into_iter()
+		.filter(|p| old.get(*p) != new.get(*p))
+		.cloned()
+		.collect()
+}
+
+/// Runs the subcommands in a separate process so a panic or exit does not
+/// stop watching
+///
+/// Validation findings, like value or package mismatches, are printed by
+/// the subcommands themselves.
+fn regenerate(outputs: &BTreeSet<Output>) {
+	let exe = env::current_exe().expect("could not find executable");
+
+	let names: Vec<_> = outputs.iter().map(|o| o.subcommand()).collect();
+	println!("==> regenerating {}", names.join(", "));
+
+	for output in outputs {
+		let status = Command::new(&exe)
+			.arg(output.subcommand())
+			.status()
+			.expect("could not run pcb-generator");
+		if !status.success() {
+			eprintln!("error: {} failed", output.subcommand());
+		}
+	}
+
+	println!("==> done, watching for changes");
+}
+
+pub fn watch(args: Watch) {
+	let interval = Duration::from_millis(args.interval);
+	let debounce = Duration::from_millis(args.debounce);
+
+	let mut current = snapshot();
+	let mut pending: BTreeSet<Output> = BTreeSet::new();
+	let mut last_change = Instant::now();
+
+	if args.no_initial {
+		println!("==> watching for changes");
+	} else {
+		regenerate(&Output::ALL.iter().copied().collect());
+	}
+
+	loop {
+		thread::sleep(interval);
+
+		let new = snapshot();
+		let changed = changed_files(&current, &new);
+		current = new;
+
+		if !changed.is_empty() {
+			for path in &changed {
+				pending.extend(Output::affected_by(path));
+			}
+			last_change = Instant::now();
+		}
+
+		if !pending.is_empty() && last_change.elapsed() >= debounce {
+			regenerate(&pending);
+			pending.clear();
+			// the outputs are written to ./build which is not watched
+			current = snapshot();
+		}
+	}
+}
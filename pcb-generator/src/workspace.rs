@@ -0,0 +1,286 @@
+use crate::bom::{group_entries, read_custom_entries};
+use crate::partslist::find_in_parts_list;
+use crate::util::{create_build_dir, natural_cmp, BUILD_DIR};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use clap::Parser;
+
+/// Directories which never contain a board project
+const SKIP_DIRS: &[&str] = &["build", "output", "target", "node_modules"];
+const MAX_DEPTH: usize = 3;
+
+/// Works with every board project below a workspace root
+///
+/// A board project is a directory containing a `.kicad_pro` file or a
+/// `bom.csv`.
+#[derive(Debug, Parser)]
+pub struct Workspace {
+	/// The workspace root, defaults to the current directory
+	#[clap(long)]
+	root: Option<PathBuf>,
+	/// Only use the boards with these names
+	#[clap(long)]
+	only: Vec<String>,
+	#[clap(subcommand)]
+	subcmd: WorkspaceCommand
+}
+
+#[derive(Debug, Parser)]
+enum WorkspaceCommand {
+	/// Lists the board projects
+	List,
+	/// Runs a pcb-generator subcommand in every board project
+	///
+	/// For example `workspace run -- bom`.
+	Run {
+		/// Continue with the next board if one fails
+		#[clap(long)]
+		keep_going: bool,
+		#[clap(last = true, required = true)]
+		args: Vec<String>
+	},
+	/// Writes a purchasing bom summed over a build plan
+	Purchase(Purchase)
+}
+
+#[derive(Debug, Parser)]
+struct Purchase {
+	/// How many of each board get built, for example `pcb-v1=5`
+	#[clap(long, required = true)]
+	plan: Vec<String>,
+	/// Extra parts in percent to cover losses
+	#[clap(long, default_value_t = 0f64)]
+	spare: f64,
+	#[clap(long, default_value_t = true)]
+	uses_comma: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+	/// The path relative to the workspace root, for example `pcb-v1`
+	pub name: String,
+	pub dir: PathBuf
+}
+
+fn is_project(dir: &Path) -> bool {
+	if dir.join("bom.csv").is_file() {
+		return true
+	}
+
+	fs::read_dir(dir)
+		.map(|entries| entries.filter_map(|e| e.ok()).any(|e| {
+			e.path().extension().is_some_and(|e| e == "kicad_pro")
+		}))
+		.unwrap_or(false)
+}
+
+/// Returns every board project below `root`, sorted by name
+///
+/// Projects are not searched for nested projects.
+pub fn find_projects(root: &Path) -> Vec<Project> {
+	let mut projects = vec![];
+	find_projects_in(root, root, 0, &mut projects);
+	projects.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+	projects
+}
+
+fn find_projects_in(
+	root: &Path,
+	dir: &Path,
+	depth: usize,
+	projects: &mut Vec<Project>
+) {
+	if is_project(dir) {
+		let name = dir.strip_prefix(root).unwrap_or(dir);
+		let name = if name.as_os_str().is_empty() {
+			dir.canonicalize().ok()
+				.and_then(|d| d.file_name().map(|n| n.to_os_string()))
+				.unwrap_or_else(|| ".".into())
+				.to_string_lossy()
+				.into_owned()
+		} else {
+			name.to_string_lossy().into_owned()
+		};
+		projects.push(Project { name, dir: dir.to_path_buf() });
+		return
+	}
+
+	if depth >= MAX_DEPTH {
+		return
+	}
+
+	let Ok(entries) = fs::read_dir(dir) else { return };
+	for entry in entries.filter_map(|e| e.ok()) {
+		let path = entry.path();
+		let name = entry.file_name();
+		let name = name.to_string_lossy();
+		if !path.is_dir() || name.starts_with('.') ||
+			SKIP_DIRS.contains(&name.as_ref())
+		{
+			continue
+		}
+		find_projects_in(root, &path, depth + 1, projects);
+	}
+}
+
+pub fn workspace(args: Workspace) {
+	let root = args.root.clone()
+		.unwrap_or_else(|| env::current_dir().expect("no current dir"));
+
+	let mut projects = find_projects(&root);
+	for only in &args.only {
+		assert!(
+			projects.iter().any(|p| &p.name == only),
+			"board {:?} not found in {:?}", only, root
+		);
+	}
+	if !args.only.is_empty() {
+		projects.retain(|p| args.only.contains(&p.name));
+	}
+
+	if projects.is_empty() {
+		eprintln!("no board projects found in {:?}", root);
+		process::exit(1);
+	}
+
+	match args.subcmd {
+		WorkspaceCommand::List => {
+			for project in &projects {
+				println!("{:<20} {:?}", project.name, project.dir);
+			}
+		},
+		WorkspaceCommand::Run { keep_going, args } => {
+			run(&projects, &args, keep_going);
+		},
+		WorkspaceCommand::Purchase(purchase_args) => {
+			purchase(&root, &projects, purchase_args);
+		}
+	}
+}
+
+/// Runs this executable once per project with the project as the working
+/// directory
+fn run(projects: &[Project], args: &[String], keep_going: bool) {
+	let exe = env::current_exe().expect("could not find executable");
+	let mut failed = vec![];
+
+	for project in projects {
+		println!("==> {}", project.name);
+
+		let status = Command::new(&exe)
+			.args(args)
+			.current_dir(&project.dir)
+			.status()
+			.expect("could not run pcb-generator");
+
+		if !status.success() {
+			failed.push(project.name.clone());
+			if !keep_going {
+				break
+			}
+		}
+	}
+
+	if !failed.is_empty() {
+		eprintln!("failed: {}", failed.join(", "));
+		process::exit(1);
+	}
+}
+
+fn parse_plan(plan: &[String], projects: &[Project]) -> Vec<(Project, usize)> {
+	plan.iter()
+		.map(|entry| {
+			let (name, count) = entry.split_once('=').unwrap_or_else(|| {
+				panic!("expected NAME=COUNT got {:?}", entry)
+			});
+			let count = count.trim().parse()
+				.unwrap_or_else(|_| panic!("invalid count in {:?}", entry));
+			let project = projects.iter()
+				.find(|p| p.name == name.trim())
+				.unwrap_or_else(|| panic!("board {:?} not found", name));
+			(project.clone(), count)
+		})
+		.collect()
+}
+
+#[derive(Debug, Default)]
+struct PurchaseLine {
+	quantity: usize,
+	/// For example `pcb-v1 5x3`
+	usage: Vec<String>
+}
+
+fn purchase(root: &Path, projects: &[Project], args: Purchase) {
+	let plan = parse_plan(&args.plan, projects);
+
+	let mut lines: BTreeMap<String, PurchaseLine> = BTreeMap::new();
+	for (project, count) in &plan {
+		let entries = read_custom_entries(
+			project.dir.join("bom.csv"),
+			args.uses_comma
+		);
+		for (jlcpcb_part, designators) in group_entries(&entries) {
+			let line = lines.entry(jlcpcb_part.into()).or_default();
+			line.quantity += designators.len() * count;
+			line.usage.push(format!(
+				"{} {}x{}", project.name, count, designators.len()
+			));
+		}
+	}
+
+	let parts = find_in_parts_list(|p| lines.contains_key(p.lcsc.trim()));
+
+	env::set_current_dir(root).expect("could not change to workspace root");
+	create_build_dir();
+	let path = format!("{}/purchase.csv", BUILD_DIR);
+	let mut w = csv::Writer::from_path(&path).unwrap();
+	w.write_record([
+		"JLCPCB Part", "MFR.Part", "Description", "Quantity", "Unit Price",
+		"Total", "Stock", "Used by"
+	]).unwrap();
+
+	let mut total = 0f64;
+	for (jlcpcb_part, line) in &lines {
+		let quantity = (line.quantity as f64 * (1f64 + args.spare / 100f64))
+			.ceil() as usize;
+		let part = parts.iter().find(|p| p.lcsc.trim() == jlcpcb_part);
+		let unit_price = part.and_then(|p| p.unit_price(quantity));
+		if let Some(price) = unit_price {
+			total += price * quantity as f64;
+		}
+
+		if let Some(part) = part {
+			if part.stock < quantity {
+				eprintln!(
+					"warning: {} needs {} but only {} are in stock",
+					jlcpcb_part, quantity, part.stock
+				);
+			}
+		} else {
+			eprintln!("warning: {} not found in the parts list", jlcpcb_part);
+		}
+
+		let fmt_price = |p: Option<f64>| p
+			.map(|p| format!("{:.4}", p))
+			.unwrap_or_default();
+		w.write_record([
+			jlcpcb_part.as_str(),
+			part.map(|p| p.mfr_part.as_str()).unwrap_or(""),
+			part.map(|p| p.desc.as_str()).unwrap_or(""),
+			&quantity.to_string(),
+			&fmt_price(unit_price),
+			&fmt_price(unit_price.map(|p| p * quantity as f64)),
+			&part.map(|p| p.stock.to_string()).unwrap_or_default(),
+			&line.usage.join(", ")
+		]).unwrap();
+	}
+	w.flush().unwrap();
+
+	println!("{} parts, total {:.2}", lines.len(), total);
+	println!("written to {:?}", path);
+}
@@ -0,0 +1,6 @@
+#!/bin/sh
+# Stands in for libcamera-vid by writing a h264 file to stdout, pi-os
+# restarts it once the file ended.
+#
+# usage: pi-os 0.0.0.0:8080 "./scripts/fake-camera.sh ./h264.h264"
+exec cat "${1:-./h264.h264}"
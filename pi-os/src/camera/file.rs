@@ -1,66 +1,83 @@
-use super::{Camera, CameraError};
-use crate::webrtc::Sample;
+use super::h264::AccessUnitReader;
+use super::{Camera, CameraError, Codec, Frame};
 
-use std::fs::File;
-use std::io::BufReader;
+use std::time::{Duration, Instant};
 
-use webrtc::media::io::h264_reader::H264Reader;
+use async_trait::async_trait;
 
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+/// Used if the sps contains no timing info
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(1000 / 30);
+
+/// Plays a h264 file frame by frame as if it was a camera
 pub struct FileCamera {
-	reader: H264Reader<BufReader<File>>,
+	path: String,
+	looping: bool,
+	/// Opened with the first frame
+	reader: Option<AccessUnitReader<BufReader<File>>>,
 }
 
 impl FileCamera {
 	pub fn new(path: &str) -> Self {
-		let file = File::open("./h264.h264").unwrap();
-		let reader = H264Reader::new(BufReader::new(file));
+		Self {
+			path: path.to_owned(),
+			looping: false,
+			reader: None,
+		}
+	}
 
-		Self { reader }
+	/// Starts from the beginning once the end of the file is reached
+	pub fn looping(mut self, looping: bool) -> Self {
+		self.looping = looping;
+		self
+	}
+
+	async fn open(
+		&self,
+	) -> Result<AccessUnitReader<BufReader<File>>, CameraError> {
+		let file = File::open(&self.path).await?;
+		Ok(AccessUnitReader::new(BufReader::new(file)))
 	}
 }
 
+#[async_trait]
 impl Camera for FileCamera {
-	fn next_sample(&mut self) -> Result<Sample, CameraError> {
-		let nal = match self.reader.next_nal() {
-			Ok(n) => n,
-			Err(e) => {
-				eprintln!("failed to read nal probably file streamed {:?}", e);
-				return Err(CameraError::Disconnected);
+	async fn next_frame(&mut self) -> Result<Frame, CameraError> {
+		let mut rewound = false;
+		let au = loop {
+			let reader = match &mut self.reader {
+				Some(reader) => reader,
+				None => self.reader.insert(self.open().await?),
+			};
+
+			match reader.next_access_unit().await? {
+				Some(au) => break au,
+				// a file without a single frame would loop forever
+				None if self.looping && !rewound => {
+					self.reader = None;
+					rewound = true;
+				}
+				None => {
+					eprintln!("end of {} reached", self.path);
+					return Err(CameraError::Disconnected);
+				}
 			}
 		};
 
-		Ok(Sample {
-			data: nal.data.freeze(),
-			..Default::default()
+		// cannot fail, the access unit was just read
+		let sps = self.reader.as_ref().unwrap().sps();
+
+		Ok(Frame {
+			data: au.to_annex_b(),
+			timestamp: Instant::now(),
+			duration: sps
+				.and_then(|sps| sps.frame_duration)
+				.unwrap_or(DEFAULT_FRAME_DURATION),
+			keyframe: au.is_keyframe(),
+			codec: Codec::H264,
+			resolution: sps.map(|sps| sps.resolution),
 		})
 	}
 }
-
-/*
-let file = File::open("./h264.h264").unwrap();
-let reader = BufReader::new(file);
-let mut h264 = H264Reader::new(reader);
-
-notify_video.notified().await;
-
-eprintln!("play from disk");
-
-let mut ticker = tokio::time::interval(Duration::from_millis(33));
-loop {
-	let nal = match h264.next_nal() {
-		Ok(nal) => nal,
-		Err(e) => {
-			eprintln!("All video frames parsed and sent: {:?}", e);
-			break
-		}
-	};
-
-	video_track.write_sample(&Sample {
-		data: nal.data.freeze(),
-		duration: Duration::from_secs(1),
-		..Default::default()
-	}).await.unwrap();
-
-	let _ = ticker.tick().await;
-}
-*/
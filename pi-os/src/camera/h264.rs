@@ -0,0 +1,636 @@
+//! Just enough h264 parsing to group nals into frames and to read the
+//! stream parameters
+
+use super::Resolution;
+
+use std::io;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_IDR: u8 = 5;
+pub const NAL_SEI: u8 = 6;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+pub const NAL_AUD: u8 = 9;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// The nal without the start code
+pub fn nal_type(nal: &[u8]) -> u8 {
+	nal.first().map(|b| b & 0x1f).unwrap_or(0)
+}
+
+fn is_vcl(nal_type: u8) -> bool {
+	matches!(nal_type, NAL_SLICE..=NAL_IDR)
+}
+
+/// All nals of one picture
+#[derive(Debug, Default)]
+pub struct AccessUnit {
+	nals: Vec<Bytes>,
+	has_vcl: bool,
+}
+
+impl AccessUnit {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns false if the nal belongs to the next access unit
+	///
+	/// Only the first slice of a picture is expected to have a
+	/// `first_mb_in_slice` of zero.
+	pub fn accepts(&self, nal: &[u8]) -> bool {
+		if !self.has_vcl {
+			return true;
+		}
+
+		let ty = nal_type(nal);
+		match ty {
+			NAL_SEI | NAL_SPS | NAL_PPS | NAL_AUD => false,
+			// first_mb_in_slice is the first exp golomb value, zero is
+			// encoded as a single one bit
+			_ if is_vcl(ty) => nal.get(1).is_none_or(|b| b & 0x80 == 0),
+			_ => true,
+		}
+	}
+
+	pub fn push(&mut self, nal: Bytes) {
+		self.has_vcl |= is_vcl(nal_type(&nal));
+		self.nals.push(nal);
+	}
+
+	pub fn is_empty(&self) -> bool {
+		!self.has_vcl
+	}
+
+	pub fn is_keyframe(&self) -> bool {
+		self.nals.iter().any(|n| nal_type(n) == NAL_IDR)
+	}
+
+	pub fn contains(&self, ty: u8) -> bool {
+		self.nals.iter().any(|n| nal_type(n) == ty)
+	}
+
+	/// Inserts the parameter sets which are missing in front of the
+	/// picture
+	pub fn prepend_missing(&mut self, parameter_sets: &[Bytes]) {
+		let missing: Vec<_> = parameter_sets
+			.iter()
+			.filter(|p| !self.contains(nal_type(p)))
+			.cloned()
+			.collect();
+		self.nals.splice(0..0, missing);
+	}
+
+	/// The nals with start codes in front of each
+	pub fn to_annex_b(&self) -> Bytes {
+		let len = self.nals.iter().map(|n| n.len() + START_CODE.len()).sum();
+		let mut data = BytesMut::with_capacity(len);
+		for nal in &self.nals {
+			data.put_slice(&START_CODE);
+			data.put_slice(nal);
+		}
+		data.freeze()
+	}
+}
+
+/// Splits an annex b stream at the start codes
+pub struct NalReader<R> {
+	reader: R,
+	buf: BytesMut,
+	/// How much of the buffer was already searched for a start code
+	searched: usize,
+	started: bool,
+	eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> NalReader<R> {
+	pub fn new(reader: R) -> Self {
+		Self {
+			reader,
+			buf: BytesMut::with_capacity(64 * 1024),
+			searched: 0,
+			started: false,
+			eof: false,
+		}
+	}
+
+	/// Returns the next nal without its start code, `None` at the end of
+	/// the stream
+	///
+	/// A nal is only complete once the next start code was read.
+	pub async fn next_nal(&mut self) -> io::Result<Option<Bytes>> {
+		loop {
+			while let Some(pos) = self.find_start_code() {
+				let nal = self.buf.split_to(pos);
+				self.buf.advance(3);
+
+				// anything in front of the first start code is garbage
+				let started = std::mem::replace(&mut self.started, true);
+				match trim_trailing_zeros(nal) {
+					Some(nal) if started => return Ok(Some(nal)),
+					_ => {}
+				}
+			}
+
+			if self.eof {
+				let rest = self.buf.split();
+				self.searched = 0;
+				return Ok(trim_trailing_zeros(rest).filter(|_| self.started));
+			}
+
+			if self.reader.read_buf(&mut self.buf).await? == 0 {
+				self.eof = true;
+			}
+		}
+	}
+
+	fn find_start_code(&mut self) -> Option<usize> {
+		let pos = self.buf[self.searched..]
+			.windows(3)
+			.position(|w| w == [0, 0, 1]);
+		match pos {
+			Some(pos) => {
+				let pos = self.searched + pos;
+				self.searched = 0;
+				Some(pos)
+			}
+			None => {
+				// the last two bytes could be the beginning of a start code
+				self.searched = self.buf.len().saturating_sub(2);
+				None
+			}
+		}
+	}
+}
+
+/// The zeros belong to a four byte start code or are padding
+fn trim_trailing_zeros(nal: BytesMut) -> Option<Bytes> {
+	let len = nal.iter().rposition(|b| *b != 0)? + 1;
+	Some(nal.freeze().slice(..len))
+}
+
+/// Groups the nals into access units and repeats the parameter sets in
+/// front of every keyframe so a decoder can start with any of them
+pub struct AccessUnitReader<R> {
+	nals: NalReader<R>,
+	/// The first nal of the next access unit
+	next_nal: Option<Bytes>,
+	sps: Option<Bytes>,
+	pps: Option<Bytes>,
+	info: Option<Sps>,
+}
+
+impl<R: AsyncRead + Unpin> AccessUnitReader<R> {
+	pub fn new(reader: R) -> Self {
+		Self {
+			nals: NalReader::new(reader),
+			next_nal: None,
+			sps: None,
+			pps: None,
+			info: None,
+		}
+	}
+
+	/// The last sequence parameter set which could be parsed
+	pub fn sps(&self) -> Option<Sps> {
+		self.info
+	}
+
+	/// Returns `None` at the end of the stream
+	///
+	/// Without access unit delimiters an access unit is only complete once
+	/// the first nal of the next one was read.
+	pub async fn next_access_unit(&mut self) -> io::Result<Option<AccessUnit>> {
+		let mut au = AccessUnit::new();
+		loop {
+			let nal = match self.next_nal.take() {
+				Some(nal) => nal,
+				None => match self.nals.next_nal().await? {
+					Some(nal) => nal,
+					None => break,
+				},
+			};
+
+			if !au.accepts(&nal) {
+				self.next_nal = Some(nal);
+				break;
+			}
+			self.remember_parameter_set(&nal);
+			au.push(nal);
+		}
+
+		if au.is_empty() {
+			return Ok(None);
+		}
+
+		if au.is_keyframe() {
+			let parameter_sets: Vec<_> =
+				self.sps.iter().chain(&self.pps).cloned().collect();
+			au.prepend_missing(&parameter_sets);
+		}
+
+		Ok(Some(au))
+	}
+
+	fn remember_parameter_set(&mut self, nal: &Bytes) {
+		match nal_type(nal) {
+			NAL_SPS => {
+				if let Some(sps) = Sps::parse(nal) {
+					self.info = Some(sps);
+				}
+				self.sps = Some(nal.clone());
+			}
+			NAL_PPS => self.pps = Some(nal.clone()),
+			_ => {}
+		}
+	}
+}
+
+/// The parts of a sequence parameter set which are of interest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sps {
+	pub resolution: Resolution,
+	/// Only known if the stream contains timing info
+	pub frame_duration: Option<Duration>,
+}
+
+impl Sps {
+	/// Returns `None` if the sps is truncated or uses unknown values
+	pub fn parse(nal: &[u8]) -> Option<Self> {
+		if nal_type(nal) != NAL_SPS {
+			return None;
+		}
+		let rbsp = remove_emulation_prevention(nal.get(1..)?);
+		let mut r = BitReader::new(&rbsp);
+
+		let profile_idc = r.bits(8)?;
+		// constraint flags and level
+		r.skip(16)?;
+		let _sps_id = r.ue()?;
+
+		let mut chroma_format_idc = 1;
+		let mut separate_colour_plane = false;
+		if matches!(
+			profile_idc,
+			100 | 110
+				| 122 | 244 | 44
+				| 83 | 86 | 118
+				| 128 | 138 | 139
+				| 134 | 135
+		) {
+			chroma_format_idc = r.ue()?;
+			if chroma_format_idc == 3 {
+				separate_colour_plane = r.flag()?;
+			}
+			// bit depths
+			r.ue()?;
+			r.ue()?;
+			// qpprime_y_zero_transform_bypass
+			r.skip(1)?;
+			if r.flag()? {
+				let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+				for i in 0..lists {
+					if r.flag()? {
+						r.skip_scaling_list(if i < 6 { 16 } else { 64 })?;
+					}
+				}
+			}
+		}
+
+		let _log2_max_frame_num = r.ue()?;
+		match r.ue()? {
+			0 => {
+				let _log2_max_pic_order_cnt_lsb = r.ue()?;
+			}
+			1 => {
+				// delta_pic_order_always_zero
+				r.skip(1)?;
+				r.se()?;
+				r.se()?;
+				for _ in 0..r.ue()? {
+					r.se()?;
+				}
+			}
+			_ => {}
+		}
+		let _max_num_ref_frames = r.ue()?;
+		// gaps_in_frame_num_allowed
+		r.skip(1)?;
+		let width_in_mbs = r.ue()?.checked_add(1)?;
+		let height_in_map_units = r.ue()?.checked_add(1)?;
+		let frame_mbs_only = r.flag()?;
+		if !frame_mbs_only {
+			// mb_adaptive_frame_field
+			r.skip(1)?;
+		}
+		// direct_8x8_inference
+		r.skip(1)?;
+
+		let mut crop = [0; 4];
+		if r.flag()? {
+			for c in &mut crop {
+				*c = r.ue()?;
+			}
+		}
+		let [left, right, top, bottom] = crop;
+
+		let chroma_array_type = if separate_colour_plane {
+			0
+		} else {
+			chroma_format_idc
+		};
+		let (crop_x, crop_y) = match chroma_array_type {
+			1 => (2, 2),
+			2 => (2, 1),
+			_ => (1, 1),
+		};
+		let fields = 2 - frame_mbs_only as u32;
+		let crop_y = crop_y * fields;
+		let height_in_mbs = height_in_map_units.checked_mul(fields)?;
+
+		// the values come from the stream and can be anything
+		let size = |mbs: u32, crop: u32, a: u32, b: u32| {
+			let cropped = a.checked_add(b)?.checked_mul(crop)?;
+			mbs.checked_mul(16)?.checked_sub(cropped)
+		};
+		let width = size(width_in_mbs, crop_x, left, right)?;
+		let height = size(height_in_mbs, crop_y, top, bottom)?;
+
+		let frame_duration = if r.flag()? {
+			parse_vui_timing(&mut r)
+		} else {
+			None
+		};
+
+		Some(Self {
+			resolution: Resolution { width, height },
+			frame_duration,
+		})
+	}
+}
+
+/// Returns `None` if the vui doesn't contain timing info
+fn parse_vui_timing(r: &mut BitReader) -> Option<Duration> {
+	// aspect_ratio_info_present
+	if r.flag()? {
+		const EXTENDED_SAR: u32 = 255;
+		if r.bits(8)? == EXTENDED_SAR {
+			r.skip(32)?;
+		}
+	}
+	// overscan_info_present
+	if r.flag()? {
+		r.skip(1)?;
+	}
+	// video_signal_type_present
+	if r.flag()? {
+		r.skip(4)?;
+		// colour_description_present
+		if r.flag()? {
+			r.skip(24)?;
+		}
+	}
+	// chroma_loc_info_present
+	if r.flag()? {
+		r.ue()?;
+		r.ue()?;
+	}
+
+	if !r.flag()? {
+		return None;
+	}
+	let num_units_in_tick = r.bits(32)?;
+	let time_scale = r.bits(32)?;
+	if num_units_in_tick == 0 || time_scale == 0 {
+		return None;
+	}
+
+	// a frame takes two ticks since a tick is one field
+	Some(Duration::from_secs_f64(
+		2.0 * num_units_in_tick as f64 / time_scale as f64,
+	))
+}
+
+/// Removes the 3 from every `00 00 03`
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut zeros = 0;
+	for &b in data {
+		if zeros >= 2 && b == 3 {
+			zeros = 0;
+			continue;
+		}
+		zeros = if b == 0 { zeros + 1 } else { 0 };
+		out.push(b);
+	}
+	out
+}
+
+struct BitReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		Self { data, pos: 0 }
+	}
+
+	fn flag(&mut self) -> Option<bool> {
+		let byte = self.data.get(self.pos / 8)?;
+		let bit = byte >> (7 - self.pos % 8) & 1;
+		self.pos += 1;
+		Some(bit == 1)
+	}
+
+	/// Reads up to 32 bits
+	fn bits(&mut self, n: usize) -> Option<u32> {
+		let mut v = 0;
+		for _ in 0..n {
+			v = v << 1 | self.flag()? as u32;
+		}
+		Some(v)
+	}
+
+	fn skip(&mut self, n: usize) -> Option<()> {
+		if self.pos + n > self.data.len() * 8 {
+			return None;
+		}
+		self.pos += n;
+		Some(())
+	}
+
+	/// Unsigned exp golomb
+	fn ue(&mut self) -> Option<u32> {
+		let mut zeros = 0;
+		while !self.flag()? {
+			zeros += 1;
+			if zeros > 31 {
+				return None;
+			}
+		}
+		Some(((1u64 << zeros) - 1 + self.bits(zeros)? as u64) as u32)
+	}
+
+	/// Signed exp golomb
+	fn se(&mut self) -> Option<i32> {
+		let v = self.ue()? as i64;
+		let v = if v % 2 == 1 { (v + 1) / 2 } else { -v / 2 };
+		Some(v as i32)
+	}
+
+	fn skip_scaling_list(&mut self, size: usize) -> Option<()> {
+		let mut last = 8;
+		let mut next = 8;
+		for _ in 0..size {
+			if next != 0 {
+				let delta = self.se()?;
+				next = (last + delta + 256) % 256;
+			}
+			if next != 0 {
+				last = next;
+			}
+		}
+		Some(())
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	/// A 1920x1080 sps with 25 fps, a pps, an idr and two p pictures, where
+	/// the first idr and the second p have two slices, and another idr
+	pub(crate) const SAMPLE: &[u8] = &[
+		0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1f, 0xac, 0xe8, 0x07, 0x80,
+		0x22, 0x7e, 0x5c, 0x05, 0xa2, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x00,
+		0x03, 0x00, 0x65, 0x80, 0x00, 0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80,
+		0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01,
+		0x65, 0x48, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01, 0x41, 0x9a, 0x01, 0x00,
+		0x00, 0x00, 0x01, 0x41, 0x9a, 0x02, 0x00, 0x00, 0x00, 0x01, 0x41, 0x30,
+		0x02, 0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x11,
+	];
+
+	async fn read_all(data: &[u8]) -> Vec<AccessUnit> {
+		let mut reader = AccessUnitReader::new(data);
+		let mut aus = Vec::new();
+		while let Some(au) = reader.next_access_unit().await.unwrap() {
+			aus.push(au);
+		}
+		aus
+	}
+
+	fn types(au: &AccessUnit) -> Vec<u8> {
+		au.nals.iter().map(|n| nal_type(n)).collect()
+	}
+
+	#[tokio::test]
+	async fn groups_slices() {
+		let aus = read_all(SAMPLE).await;
+		let types: Vec<_> = aus.iter().map(types).collect();
+		assert_eq!(
+			types,
+			[
+				vec![NAL_SPS, NAL_PPS, NAL_IDR, NAL_IDR],
+				vec![NAL_SLICE],
+				vec![NAL_SLICE, NAL_SLICE],
+				// the parameter sets are repeated
+				vec![NAL_SPS, NAL_PPS, NAL_IDR],
+			]
+		);
+
+		let keyframes: Vec<_> = aus.iter().map(|au| au.is_keyframe()).collect();
+		assert_eq!(keyframes, [true, false, false, true]);
+		assert_eq!(aus[3].nals[..2], aus[0].nals[..2]);
+	}
+
+	#[tokio::test]
+	async fn reads_the_sps() {
+		let mut reader = AccessUnitReader::new(SAMPLE);
+		reader.next_access_unit().await.unwrap();
+
+		let sps = reader.sps().unwrap();
+		assert_eq!(
+			sps.resolution,
+			Resolution {
+				width: 1920,
+				height: 1080
+			}
+		);
+		assert_eq!(sps.frame_duration, Some(Duration::from_millis(40)));
+	}
+
+	#[tokio::test]
+	async fn splits_three_and_four_byte_start_codes() {
+		let data = [0xff, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x41, 0x9a, 0, 0];
+		let mut nals = NalReader::new(&data[..]);
+		assert_eq!(nals.next_nal().await.unwrap().unwrap(), &[0x09, 0xf0][..]);
+		assert_eq!(nals.next_nal().await.unwrap().unwrap(), &[0x41, 0x9a][..]);
+		assert!(nals.next_nal().await.unwrap().is_none());
+	}
+
+	/// Appends an unsigned exp golomb value
+	fn ue(bits: &mut String, v: u32) {
+		let v = format!("{:b}", v as u64 + 1);
+		bits.push_str(&"0".repeat(v.len() - 1));
+		bits.push_str(&v);
+	}
+
+	/// A baseline sps without vui and emulation prevention bytes
+	fn sps(width_in_mbs: u32, height_in_mbs: u32, crop: [u32; 4]) -> Vec<u8> {
+		let mut bits = String::new();
+		// sps id, log2_max_frame_num, pic_order_cnt_type, ref frames
+		for v in [0, 0, 2, 1] {
+			ue(&mut bits, v);
+		}
+		// gaps_in_frame_num_allowed
+		bits.push('0');
+		ue(&mut bits, width_in_mbs - 1);
+		ue(&mut bits, height_in_mbs - 1);
+		// frame_mbs_only, direct_8x8_inference, frame_cropping
+		bits.push_str("111");
+		for c in crop {
+			ue(&mut bits, c);
+		}
+		// no vui and the stop bit
+		bits.push_str("01");
+		while !bits.len().is_multiple_of(8) {
+			bits.push('0');
+		}
+
+		let mut nal = vec![NAL_SPS | 0x60, 66, 0, 0x1f];
+		for byte in bits.as_bytes().chunks(8) {
+			let byte = std::str::from_utf8(byte).unwrap();
+			nal.push(u8::from_str_radix(byte, 2).unwrap());
+		}
+		nal
+	}
+
+	#[test]
+	fn crops_the_size() {
+		let sps = Sps::parse(&sps(120, 68, [0, 0, 0, 4])).unwrap();
+		assert_eq!(
+			sps.resolution,
+			Resolution {
+				width: 1920,
+				height: 1080
+			}
+		);
+		assert_eq!(sps.frame_duration, None);
+	}
+
+	#[test]
+	fn rejects_overflowing_sizes() {
+		assert!(Sps::parse(&sps(u32::MAX, 68, [0; 4])).is_none());
+		assert!(Sps::parse(&sps(120, 68, [u32::MAX - 1, 1, 0, 0])).is_none());
+		assert!(Sps::parse(&sps(120, 68, [u32::MAX / 2, 0, 0, 0])).is_none());
+		// more cropped than there is
+		assert!(Sps::parse(&sps(120, 68, [0, 0, 600, 0])).is_none());
+	}
+}
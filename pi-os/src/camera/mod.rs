@@ -1,16 +1,79 @@
 mod file;
+mod h264;
+mod process;
+mod shared;
 
 pub use file::FileCamera;
+pub use process::{ProcessCamera, ProcessConfig};
+pub use shared::SharedCamera;
 
-use crate::webrtc::Sample;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, Stream};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CameraError {
 	#[error("Camera was disconnected")]
 	Disconnected,
+	#[error("Camera io error {0}")]
+	Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+	H264,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+	pub width: u32,
+	pub height: u32,
+}
+
+impl fmt::Display for Resolution {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}x{}", self.width, self.height)
+	}
+}
+
+/// One encoded picture
+#[derive(Debug, Clone)]
+pub struct Frame {
+	/// A h264 access unit in annex b format
+	pub data: Bytes,
+	/// When the picture was taken
+	pub timestamp: Instant,
+	/// How long the picture should be shown
+	pub duration: Duration,
+	/// A decoder can start with this frame
+	pub keyframe: bool,
+	pub codec: Codec,
+	/// Unknown until the stream contained it
+	pub resolution: Option<Resolution>,
 }
 
-pub trait Camera {
-	/// duration get's written by the caller
-	fn next_sample(&mut self) -> Result<Sample, CameraError>;
+/// Frames are returned as soon as they are read, so they can arrive in
+/// bursts, the caller paces them by their duration
+#[async_trait]
+pub trait Camera: Send {
+	async fn next_frame(&mut self) -> Result<Frame, CameraError>;
+}
+
+pub type FrameStream =
+	Pin<Box<dyn Stream<Item = Result<Frame, CameraError>> + Send>>;
+
+/// The frames of the camera, ends after the first error
+pub fn frames(camera: Box<dyn Camera>) -> FrameStream {
+	Box::pin(stream::unfold(Some(camera), |camera| async move {
+		let mut camera = camera?;
+		match camera.next_frame().await {
+			Ok(frame) => Some((Ok(frame), Some(camera))),
+			Err(e) => Some((Err(e), None)),
+		}
+	}))
 }
@@ -0,0 +1,281 @@
+//! An external encoder like `libcamera-vid` or `ffmpeg` which writes an
+//! annex b h264 stream to its stdout
+
+use super::h264::AccessUnitReader;
+use super::{Camera, CameraError, Codec, Frame, Resolution};
+
+use std::io;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::time;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ProcessConfig {
+	/// The program followed by its arguments
+	///
+	/// `{width}`, `{height}`, `{framerate}` and `{bitrate}` get replaced
+	/// with the values below.
+	pub command: Vec<String>,
+	pub width: u32,
+	pub height: u32,
+	pub framerate: u32,
+	/// In bits per second
+	pub bitrate: u32,
+	/// How often the encoder is restarted without producing a frame
+	/// before the camera gives up
+	pub max_restarts: usize,
+}
+
+impl ProcessConfig {
+	/// Splits the command at whitespace, quoting is not supported
+	pub fn from_command_line(line: &str) -> Self {
+		Self {
+			command: line.split_whitespace().map(Into::into).collect(),
+			..Default::default()
+		}
+	}
+
+	fn args(&self) -> Vec<String> {
+		self.command
+			.iter()
+			.map(|arg| {
+				arg.replace("{width}", &self.width.to_string())
+					.replace("{height}", &self.height.to_string())
+					.replace("{framerate}", &self.framerate.to_string())
+					.replace("{bitrate}", &self.bitrate.to_string())
+			})
+			.collect()
+	}
+}
+
+impl Default for ProcessConfig {
+	/// The raspberry pi camera, with a keyframe every second so another
+	/// viewer doesn't wait long
+	fn default() -> Self {
+		let command = "libcamera-vid --nopreview -t 0 --inline \
+			--codec h264 --profile baseline --width {width} \
+			--height {height} --framerate {framerate} --bitrate {bitrate} \
+			--intra {framerate} -o -";
+
+		Self {
+			command: command.split_whitespace().map(Into::into).collect(),
+			width: 1280,
+			height: 720,
+			framerate: 30,
+			bitrate: 2_000_000,
+			max_restarts: 3,
+		}
+	}
+}
+
+pub struct ProcessCamera {
+	config: ProcessConfig,
+	child: Option<Child>,
+	reader: Option<AccessUnitReader<ChildStdout>>,
+	failed_starts: usize,
+}
+
+impl ProcessCamera {
+	/// The encoder gets started with the first frame
+	pub fn new(config: ProcessConfig) -> Self {
+		Self {
+			config,
+			child: None,
+			reader: None,
+			failed_starts: 0,
+		}
+	}
+
+	fn start(&mut self) -> io::Result<()> {
+		let args = self.config.args();
+		let (program, args) = args.split_first().ok_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidInput, "empty command")
+		})?;
+
+		let mut child = Command::new(program)
+			.args(args)
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()?;
+		// cannot fail since stdout is piped
+		let stdout = child.stdout.take().unwrap();
+
+		eprintln!("encoder started {:?}", program);
+		self.reader = Some(AccessUnitReader::new(stdout));
+		self.child = Some(child);
+		Ok(())
+	}
+
+	async fn stop(&mut self) {
+		self.reader = None;
+		if let Some(mut child) = self.child.take() {
+			// the process might already have exited
+			let _ = child.start_kill();
+			match child.wait().await {
+				Ok(status) => eprintln!("encoder stopped {}", status),
+				Err(e) => eprintln!("could not wait for the encoder {}", e),
+			}
+		}
+	}
+
+	/// Prefers the values from the stream over the configured ones
+	fn stream_info(&self) -> (Duration, Resolution) {
+		let sps = self.reader.as_ref().and_then(|r| r.sps());
+		let duration =
+			sps.and_then(|sps| sps.frame_duration).unwrap_or_else(|| {
+				Duration::from_secs(1) / self.config.framerate.max(1)
+			});
+		let resolution = sps.map(|sps| sps.resolution).unwrap_or(Resolution {
+			width: self.config.width,
+			height: self.config.height,
+		});
+		(duration, resolution)
+	}
+}
+
+#[async_trait]
+impl Camera for ProcessCamera {
+	async fn next_frame(&mut self) -> Result<Frame, CameraError> {
+		loop {
+			let reader = match &mut self.reader {
+				Some(r) => r,
+				None => {
+					if self.failed_starts > self.config.max_restarts {
+						return Err(CameraError::Disconnected);
+					}
+					if self.failed_starts > 0 {
+						time::sleep(RESTART_DELAY).await;
+					}
+
+					if let Err(e) = self.start() {
+						eprintln!("could not start the encoder {}", e);
+						self.failed_starts += 1;
+					}
+					continue;
+				}
+			};
+
+			match reader.next_access_unit().await {
+				Ok(Some(au)) => {
+					self.failed_starts = 0;
+					let (duration, resolution) = self.stream_info();
+					return Ok(Frame {
+						data: au.to_annex_b(),
+						// the encoder doesn't tell when it took the picture
+						timestamp: Instant::now(),
+						duration,
+						keyframe: au.is_keyframe(),
+						codec: Codec::H264,
+						resolution: Some(resolution),
+					});
+				}
+				Ok(None) => eprintln!("encoder output ended"),
+				Err(e) => eprintln!("could not read the encoder output {}", e),
+			}
+
+			self.stop().await;
+			self.failed_starts += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::camera::h264::tests::SAMPLE;
+
+	use std::fs;
+	use std::path::PathBuf;
+
+	/// Removes the file once the test is done
+	struct TempFile(PathBuf);
+
+	impl TempFile {
+		fn new(name: &str, data: &[u8]) -> Self {
+			let path = std::env::temp_dir().join(format!(
+				"pi-os-{}-{}",
+				std::process::id(),
+				name
+			));
+			fs::write(&path, data).unwrap();
+			Self(path)
+		}
+	}
+
+	impl Drop for TempFile {
+		fn drop(&mut self) {
+			let _ = fs::remove_file(&self.0);
+		}
+	}
+
+	fn fake_camera(video: &TempFile) -> ProcessConfig {
+		let script =
+			concat!(env!("CARGO_MANIFEST_DIR"), "/scripts/fake-camera.sh");
+		ProcessConfig {
+			max_restarts: 1,
+			..ProcessConfig::from_command_line(&format!(
+				"{} {}",
+				script,
+				video.0.display()
+			))
+		}
+	}
+
+	#[test]
+	fn replaces_the_placeholders() {
+		let config = ProcessConfig::default();
+		let args = config.args().join(" ");
+		assert!(args.contains("--width 1280 --height 720 --framerate 30"));
+		assert!(args.contains("--bitrate 2000000 --intra 30"));
+	}
+
+	#[tokio::test]
+	async fn restarts_at_the_end() {
+		let video = TempFile::new("restart.h264", SAMPLE);
+		let mut camera = ProcessCamera::new(fake_camera(&video));
+
+		let mut keyframes = Vec::new();
+		for _ in 0..6 {
+			let frame = camera.next_frame().await.unwrap();
+			assert_eq!(frame.duration, Duration::from_millis(40));
+			assert_eq!(
+				frame.resolution,
+				Some(Resolution {
+					width: 1920,
+					height: 1080
+				})
+			);
+			keyframes.push(frame.keyframe);
+		}
+		// the sample has four frames, the second run starts with the first
+		assert_eq!(keyframes, [true, false, false, true, true, false]);
+	}
+
+	#[tokio::test]
+	async fn gives_up_without_frames() {
+		let video = TempFile::new("empty.h264", &[]);
+		let mut camera = ProcessCamera::new(fake_camera(&video));
+		assert!(matches!(
+			camera.next_frame().await,
+			Err(CameraError::Disconnected)
+		));
+
+		let config = ProcessConfig {
+			max_restarts: 0,
+			..ProcessConfig::from_command_line("./does-not-exist")
+		};
+		let mut camera = ProcessCamera::new(config);
+		assert!(matches!(
+			camera.next_frame().await,
+			Err(CameraError::Disconnected)
+		));
+	}
+}
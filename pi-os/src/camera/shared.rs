@@ -0,0 +1,216 @@
+//! One camera watched by several connections
+//!
+//! An encoder like `libcamera-vid` can only open the camera once, so every
+//! connection gets a viewer of the same camera instead of its own.
+
+use super::{Camera, CameraError, Frame};
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// How many frames a viewer can fall behind before it skips ahead
+const BACKLOG: usize = 16;
+
+type OpenCamera = Box<dyn Fn() -> Box<dyn Camera> + Send + Sync>;
+
+struct Inner {
+	open: OpenCamera,
+	/// Set while the camera is running
+	tx: Mutex<Option<broadcast::Sender<Frame>>>,
+}
+
+/// Opens the camera with the first viewer and closes it once the last
+/// viewer is gone
+#[derive(Clone)]
+pub struct SharedCamera {
+	inner: Arc<Inner>,
+}
+
+impl SharedCamera {
+	pub fn new<F>(open: F) -> Self
+	where
+		F: Fn() -> Box<dyn Camera> + Send + Sync + 'static,
+	{
+		Self {
+			inner: Arc::new(Inner {
+				open: Box::new(open),
+				tx: Mutex::new(None),
+			}),
+		}
+	}
+
+	/// Needs to be called inside the tokio runtime
+	pub fn viewer(&self) -> Box<dyn Camera> {
+		let mut tx = self.inner.tx.lock().unwrap();
+		let rx = match &*tx {
+			Some(tx) => tx.subscribe(),
+			None => {
+				let (new_tx, rx) = broadcast::channel(BACKLOG);
+				*tx = Some(new_tx.clone());
+				let camera = (self.inner.open)();
+				tokio::spawn(run(self.inner.clone(), camera, new_tx));
+				rx
+			}
+		};
+
+		Box::new(Viewer {
+			rx,
+			waiting_for_keyframe: true,
+		})
+	}
+}
+
+/// Reads the camera until it fails or nobody watches anymore
+async fn run(
+	inner: Arc<Inner>,
+	mut camera: Box<dyn Camera>,
+	tx: broadcast::Sender<Frame>,
+) {
+	loop {
+		let frame = match camera.next_frame().await {
+			Ok(frame) => frame,
+			Err(e) => {
+				eprintln!("shared camera closed {}", e);
+				break;
+			}
+		};
+		if tx.send(frame).is_ok() {
+			continue;
+		}
+
+		// a viewer could have subscribed since, so the count is checked
+		// while holding the lock
+		let mut shared = inner.tx.lock().unwrap();
+		if tx.receiver_count() == 0 {
+			eprintln!("no viewers left, closing the camera");
+			*shared = None;
+			return;
+		}
+	}
+
+	// the viewers get disconnected once every sender is dropped
+	*inner.tx.lock().unwrap() = None;
+}
+
+/// Starts with a keyframe and skips to the next one if it falls behind
+struct Viewer {
+	rx: broadcast::Receiver<Frame>,
+	waiting_for_keyframe: bool,
+}
+
+#[async_trait]
+impl Camera for Viewer {
+	async fn next_frame(&mut self) -> Result<Frame, CameraError> {
+		loop {
+			match self.rx.recv().await {
+				Ok(frame) if self.waiting_for_keyframe && !frame.keyframe => {}
+				Ok(frame) => {
+					self.waiting_for_keyframe = false;
+					return Ok(frame);
+				}
+				Err(RecvError::Lagged(n)) => {
+					eprintln!("viewer skipped {} frames", n);
+					self.waiting_for_keyframe = true;
+				}
+				Err(RecvError::Closed) => {
+					return Err(CameraError::Disconnected)
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::camera::Codec;
+
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::{Duration, Instant};
+
+	use bytes::Bytes;
+
+	/// Counts the frames, every third one is a keyframe
+	struct Counter {
+		next: u8,
+		open: Arc<AtomicUsize>,
+	}
+
+	impl Drop for Counter {
+		fn drop(&mut self) {
+			self.open.fetch_sub(1, Ordering::SeqCst);
+		}
+	}
+
+	#[async_trait]
+	impl Camera for Counter {
+		async fn next_frame(&mut self) -> Result<Frame, CameraError> {
+			tokio::time::sleep(Duration::from_millis(1)).await;
+			self.next += 1;
+			Ok(Frame {
+				data: Bytes::copy_from_slice(&[self.next]),
+				timestamp: Instant::now(),
+				duration: Duration::from_millis(1),
+				keyframe: self.next % 3 == 1,
+				codec: Codec::H264,
+				resolution: None,
+			})
+		}
+	}
+
+	fn counter(open: &Arc<AtomicUsize>) -> SharedCamera {
+		let open = open.clone();
+		SharedCamera::new(move || {
+			open.fetch_add(1, Ordering::SeqCst);
+			Box::new(Counter {
+				next: 0,
+				open: open.clone(),
+			})
+		})
+	}
+
+	#[tokio::test]
+	async fn viewers_share_the_camera() {
+		let open = Arc::new(AtomicUsize::new(0));
+		let camera = counter(&open);
+
+		let mut first = camera.viewer();
+		let frame = first.next_frame().await.unwrap();
+		assert!(frame.keyframe);
+
+		let mut second = camera.viewer();
+		assert_eq!(open.load(Ordering::SeqCst), 1);
+		let frame = second.next_frame().await.unwrap();
+		assert!(frame.keyframe);
+		assert!(frame.data[0] > 1);
+
+		// both see the same frames from now on
+		while first.next_frame().await.unwrap().data != frame.data {}
+		assert_eq!(
+			first.next_frame().await.unwrap().data,
+			second.next_frame().await.unwrap().data
+		);
+	}
+
+	#[tokio::test]
+	async fn closes_without_viewers() {
+		let open = Arc::new(AtomicUsize::new(0));
+		let camera = counter(&open);
+
+		let mut viewer = camera.viewer();
+		viewer.next_frame().await.unwrap();
+		drop(viewer);
+		while open.load(Ordering::SeqCst) > 0 {
+			tokio::time::sleep(Duration::from_millis(1)).await;
+		}
+
+		// and opens it again for the next one
+		let mut viewer = camera.viewer();
+		viewer.next_frame().await.unwrap();
+		assert_eq!(open.load(Ordering::SeqCst), 1);
+	}
+}
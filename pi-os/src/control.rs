@@ -0,0 +1,356 @@
+//! Control messages the browser sends over the webrtc data channels
+//!
+//! Every message is a json object like
+//! `{"v": 1, "seq": 42, "time": 1690000000000, "type": "sticks",
+//! "steering": 0.1, "throttle": -0.5, "aux": [0, 1]}`
+//! where `time` is the client time in ms. The other types are `arm` and
+//! `disarm` without any further fields.
+//!
+//! Sticks are sent many times a second on the unordered and unreliable
+//! `sticks` channel, everything else on the reliable `control` channel.
+//! Sticks which arrive after a newer one are dropped. The other messages
+//! are answered with the link state and their `seq` and `time`, so the
+//! client can measure the round trip. State changes, like entering the
+//! failsafe, are also sent on the `control` channel, as is the telemetry
+//! of the stm32 like `{"battery_mv": 7400, "temperature_c": 25.0,
+//! "uptime_ms": 1000, "stm32_failsafe": false}`.
+
+mod failsafe;
+mod output;
+
+pub use failsafe::{Failsafe, FailsafeConfig, LinkState};
+pub use output::{
+	Outputs, SpiOutput, TelemetryReceiver, VehicleOutput, AUX_CHANNELS,
+};
+
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use tokio::sync::watch;
+use tokio::time;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const CONTROL_CHANNEL: &str = "control";
+pub const STICKS_CHANNEL: &str = "sticks";
+
+/// How often the watchdog checks the control link
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+	#[error("invalid message {0}")]
+	Decode(#[from] serde_json::Error),
+	#[error("unsupported protocol version {0}")]
+	Version(u32),
+	#[error("value out of range")]
+	OutOfRange,
+}
+
+/// A message with its header, the version is checked while decoding
+#[derive(Debug, Clone, Deserialize)]
+pub struct Envelope {
+	pub seq: u32,
+	/// client time in ms
+	pub time: f64,
+	#[serde(flatten)]
+	pub message: Message,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+	/// All values are between -1 and 1
+	Sticks {
+		steering: f32,
+		throttle: f32,
+		#[serde(default)]
+		aux: Vec<f32>,
+	},
+	Arm,
+	Disarm,
+}
+
+#[derive(Debug, Deserialize)]
+struct Version {
+	#[serde(rename = "v")]
+	version: u32,
+}
+
+/// Decodes a message and checks the version and the values
+pub fn decode(data: &[u8]) -> Result<Envelope, ProtocolError> {
+	// the version is checked first so newer messages get a useful error
+	let Version { version } = serde_json::from_slice(data)?;
+	if version != PROTOCOL_VERSION {
+		return Err(ProtocolError::Version(version));
+	}
+
+	let envelope: Envelope = serde_json::from_slice(data)?;
+	if let Message::Sticks {
+		steering,
+		throttle,
+		aux,
+	} = &envelope.message
+	{
+		let valid = |v: &f32| v.is_finite() && (-1.0..=1.0).contains(v);
+		if !valid(steering)
+			|| !valid(throttle)
+			|| aux.len() > AUX_CHANNELS
+			|| !aux.iter().all(valid)
+		{
+			return Err(ProtocolError::OutOfRange);
+		}
+	}
+
+	Ok(envelope)
+}
+
+/// The state of one connected client
+#[derive(Debug, Default)]
+pub struct Session {
+	last_sticks_seq: Option<u32>,
+}
+
+impl Session {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns `None` if the message is older than the last sticks
+	pub fn receive(
+		&mut self,
+		data: &[u8],
+	) -> Result<Option<Envelope>, ProtocolError> {
+		let envelope = decode(data)?;
+
+		if let Message::Sticks { .. } = envelope.message {
+			if self.last_sticks_seq.is_some_and(|s| envelope.seq <= s) {
+				return Ok(None);
+			}
+			self.last_sticks_seq = Some(envelope.seq);
+		}
+
+		Ok(Some(envelope))
+	}
+}
+
+/// Routes the messages to the outputs
+///
+/// Sticks are only used while armed, disarming returns to neutral and a
+/// silent link triggers the failsafe.
+pub struct Vehicle {
+	output: Box<dyn VehicleOutput + Send>,
+	outputs: Outputs,
+	failsafe: Failsafe,
+	state_tx: watch::Sender<LinkState>,
+}
+
+pub type SharedVehicle = Arc<Mutex<Vehicle>>;
+
+impl Vehicle {
+	pub fn new(
+		output: Box<dyn VehicleOutput + Send>,
+		failsafe: FailsafeConfig,
+	) -> Self {
+		let (state_tx, _) = watch::channel(LinkState::Disarmed);
+		let mut this = Self {
+			output,
+			outputs: Outputs::default(),
+			failsafe: Failsafe::new(failsafe),
+			state_tx,
+		};
+		this.apply();
+		this
+	}
+
+	pub fn shared(self) -> SharedVehicle {
+		Arc::new(Mutex::new(self))
+	}
+
+	pub fn state(&self) -> LinkState {
+		self.failsafe.state()
+	}
+
+	/// Gets notified on every state change
+	pub fn subscribe(&self) -> watch::Receiver<LinkState> {
+		self.state_tx.subscribe()
+	}
+
+	/// Gets notified on every telemetry of the output, if it has any
+	pub fn subscribe_telemetry(&self) -> Option<TelemetryReceiver> {
+		self.output.subscribe_telemetry()
+	}
+
+	pub fn handle(&mut self, msg: &Message, now: Instant) {
+		// a message after the timeout should not revive the link
+		self.tick(now);
+
+		let state = self.failsafe.message(now, msg);
+		match (msg, state) {
+			(
+				Message::Sticks {
+					steering,
+					throttle,
+					aux,
+				},
+				LinkState::Armed,
+			) => {
+				self.outputs.steering = *steering;
+				self.outputs.throttle = *throttle;
+				self.outputs.aux = [0.0; AUX_CHANNELS];
+				self.outputs.aux[..aux.len()].copy_from_slice(aux);
+			}
+			(Message::Sticks { .. }, _) => return,
+			(Message::Arm, _) => {
+				self.outputs = Outputs {
+					armed: true,
+					..Default::default()
+				};
+			}
+			(Message::Disarm, _) => {
+				self.outputs = Outputs::default();
+			}
+		}
+
+		self.apply();
+		self.state_tx.send_if_modified(|s| {
+			let changed = *s != state;
+			*s = state;
+			changed
+		});
+	}
+
+	/// Checks if the link timed out
+	pub fn tick(&mut self, now: Instant) {
+		if self.failsafe.check(now) {
+			eprintln!("control link lost, failsafe");
+			self.outputs = self.failsafe.outputs();
+			self.apply();
+			self.state_tx.send_replace(LinkState::Failsafe);
+		}
+	}
+
+	fn apply(&mut self) {
+		self.output.apply(&self.outputs);
+	}
+}
+
+/// Checks the control link of the vehicle until it is dropped
+pub async fn watchdog(vehicle: Weak<Mutex<Vehicle>>) {
+	let mut interval = time::interval(WATCHDOG_INTERVAL);
+	loop {
+		interval.tick().await;
+		let Some(vehicle) = vehicle.upgrade() else {
+			return;
+		};
+		vehicle.lock().unwrap().tick(Instant::now());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BRAKE: FailsafeConfig = FailsafeConfig {
+		timeout: Duration::from_millis(500),
+		steering: 0.0,
+		throttle: -0.5,
+	};
+
+	/// Remembers every applied output
+	#[derive(Clone, Default)]
+	struct Recorder(Arc<Mutex<Vec<Outputs>>>);
+
+	impl Recorder {
+		fn last(&self) -> Outputs {
+			self.0.lock().unwrap().last().unwrap().clone()
+		}
+	}
+
+	impl VehicleOutput for Recorder {
+		fn apply(&mut self, outputs: &Outputs) {
+			self.0.lock().unwrap().push(outputs.clone());
+		}
+	}
+
+	fn vehicle() -> (Vehicle, Recorder) {
+		let recorder = Recorder::default();
+		(Vehicle::new(Box::new(recorder.clone()), BRAKE), recorder)
+	}
+
+	fn sticks(steering: f32, throttle: f32) -> Message {
+		Message::Sticks {
+			steering,
+			throttle,
+			aux: vec![1.0],
+		}
+	}
+
+	#[test]
+	fn sticks_need_an_arm() {
+		let now = Instant::now();
+		let (mut vehicle, outputs) = vehicle();
+
+		vehicle.handle(&sticks(0.5, 0.5), now);
+		assert_eq!(outputs.last(), Outputs::default());
+
+		vehicle.handle(&Message::Arm, now);
+		vehicle.handle(&sticks(0.5, 0.25), now);
+		let mut aux = [0.0; AUX_CHANNELS];
+		aux[0] = 1.0;
+		assert_eq!(
+			outputs.last(),
+			Outputs {
+				armed: true,
+				steering: 0.5,
+				throttle: 0.25,
+				aux,
+			}
+		);
+	}
+
+	#[test]
+	fn disarm_goes_neutral() {
+		let now = Instant::now();
+		let (mut vehicle, outputs) = vehicle();
+		vehicle.handle(&Message::Arm, now);
+		vehicle.handle(&sticks(0.5, 0.5), now);
+
+		vehicle.handle(&Message::Disarm, now);
+		assert_eq!(vehicle.state(), LinkState::Disarmed);
+		assert_eq!(outputs.last(), Outputs::default());
+	}
+
+	#[test]
+	fn timeout_applies_the_failsafe() {
+		let now = Instant::now();
+		let (mut vehicle, outputs) = vehicle();
+		let state = vehicle.subscribe();
+		vehicle.handle(&Message::Arm, now);
+
+		vehicle.tick(now + Duration::from_secs(1));
+		assert_eq!(*state.borrow(), LinkState::Failsafe);
+		assert_eq!(outputs.last().throttle, BRAKE.throttle);
+		assert!(!outputs.last().armed);
+
+		vehicle.handle(&sticks(0.5, 0.5), now + Duration::from_secs(1));
+		assert_eq!(outputs.last().throttle, BRAKE.throttle);
+
+		vehicle.handle(&Message::Arm, now + Duration::from_secs(2));
+		assert_eq!(*state.borrow(), LinkState::Armed);
+	}
+
+	#[test]
+	fn late_message_does_not_revive_the_link() {
+		let now = Instant::now();
+		let (mut vehicle, outputs) = vehicle();
+		vehicle.handle(&Message::Arm, now);
+
+		// the watchdog did not run in between
+		vehicle.handle(&sticks(0.5, 0.5), now + Duration::from_secs(1));
+		assert_eq!(vehicle.state(), LinkState::Failsafe);
+		assert_eq!(outputs.last().throttle, BRAKE.throttle);
+	}
+}
@@ -0,0 +1,168 @@
+use super::{Message, Outputs};
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+	Disarmed,
+	Armed,
+	/// The client stopped sending, needs to be armed again
+	Failsafe,
+}
+
+/// What a vehicle does once the control link is lost
+///
+/// A car can brake with a negative throttle, a plane glides with zero
+/// throttle.
+#[derive(Debug, Clone)]
+pub struct FailsafeConfig {
+	/// How long the link can be silent while armed
+	pub timeout: Duration,
+	pub steering: f32,
+	pub throttle: f32,
+}
+
+impl Default for FailsafeConfig {
+	fn default() -> Self {
+		Self {
+			timeout: Duration::from_millis(500),
+			steering: 0.0,
+			throttle: 0.0,
+		}
+	}
+}
+
+/// The deadman switch of the control link
+///
+/// The time is passed to every call so the state machine can be driven
+/// by a fake clock.
+#[derive(Debug)]
+pub struct Failsafe {
+	config: FailsafeConfig,
+	state: LinkState,
+	last_message: Option<Instant>,
+}
+
+impl Failsafe {
+	pub fn new(config: FailsafeConfig) -> Self {
+		Self {
+			config,
+			state: LinkState::Disarmed,
+			last_message: None,
+		}
+	}
+
+	pub fn state(&self) -> LinkState {
+		self.state
+	}
+
+	/// Gets called with every valid message
+	///
+	/// Only an `Arm` message leaves the failsafe, new sticks don't.
+	pub fn message(&mut self, now: Instant, msg: &Message) -> LinkState {
+		self.last_message = Some(now);
+
+		match msg {
+			Message::Arm => self.state = LinkState::Armed,
+			Message::Disarm => self.state = LinkState::Disarmed,
+			Message::Sticks { .. } => {}
+		}
+
+		self.state
+	}
+
+	/// Returns true if the failsafe was just triggered
+	pub fn check(&mut self, now: Instant) -> bool {
+		if self.state != LinkState::Armed {
+			return false;
+		}
+
+		let silent = self
+			.last_message
+			.is_none_or(|t| now.duration_since(t) > self.config.timeout);
+		if silent {
+			self.state = LinkState::Failsafe;
+		}
+
+		silent
+	}
+
+	pub fn outputs(&self) -> Outputs {
+		Outputs {
+			armed: false,
+			steering: self.config.steering,
+			throttle: self.config.throttle,
+			..Default::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SECOND: Duration = Duration::from_secs(1);
+
+	fn sticks() -> Message {
+		Message::Sticks {
+			steering: 0.5,
+			throttle: 0.5,
+			aux: vec![],
+		}
+	}
+
+	#[test]
+	fn times_out_only_while_armed() {
+		let start = Instant::now();
+		let mut failsafe = Failsafe::new(FailsafeConfig::default());
+
+		assert!(!failsafe.check(start + 10 * SECOND));
+		assert_eq!(failsafe.state(), LinkState::Disarmed);
+
+		failsafe.message(start, &Message::Arm);
+		assert!(!failsafe.check(start + SECOND / 4));
+		assert_eq!(failsafe.state(), LinkState::Armed);
+
+		assert!(failsafe.check(start + SECOND));
+		assert_eq!(failsafe.state(), LinkState::Failsafe);
+		// only reported once
+		assert!(!failsafe.check(start + 2 * SECOND));
+
+		failsafe.message(start + 3 * SECOND, &Message::Disarm);
+		assert!(!failsafe.check(start + 10 * SECOND));
+		assert_eq!(failsafe.state(), LinkState::Disarmed);
+	}
+
+	#[test]
+	fn sticks_do_not_leave_the_failsafe() {
+		let start = Instant::now();
+		let mut failsafe = Failsafe::new(FailsafeConfig::default());
+		failsafe.message(start, &Message::Arm);
+		assert!(failsafe.check(start + SECOND));
+
+		let state = failsafe.message(start + SECOND, &sticks());
+		assert_eq!(state, LinkState::Failsafe);
+		assert!(!failsafe.check(start + SECOND));
+
+		let state = failsafe.message(start + 2 * SECOND, &Message::Arm);
+		assert_eq!(state, LinkState::Armed);
+	}
+
+	#[test]
+	fn outputs_use_the_config() {
+		let failsafe = Failsafe::new(FailsafeConfig {
+			timeout: SECOND,
+			steering: 0.1,
+			throttle: -0.5,
+		});
+
+		let outputs = failsafe.outputs();
+		assert!(!outputs.armed);
+		assert_eq!(outputs.steering, 0.1);
+		assert_eq!(outputs.throttle, -0.5);
+		assert_eq!(outputs.aux, [0.0; crate::control::AUX_CHANNELS]);
+	}
+}
@@ -0,0 +1,263 @@
+use crate::spi::{
+	ConfigKey, Link, LinkError, OutputValues, Telemetry, Transport,
+};
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+pub const AUX_CHANNELS: usize = 4;
+
+/// What the vehicle should do, the values are between -1 and 1
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outputs {
+	/// False while disarmed or in failsafe, the values still get applied
+	pub armed: bool,
+	/// negative is left
+	pub steering: f32,
+	/// negative is reverse or brake
+	pub throttle: f32,
+	pub aux: [f32; AUX_CHANNELS],
+}
+
+/// Drives the motors and servos of a vehicle
+pub trait VehicleOutput {
+	/// Gets called with the complete outputs on every change
+	fn apply(&mut self, outputs: &Outputs);
+
+	/// Gets notified with every telemetry the vehicle reports, `None` if it
+	/// doesn't report any
+	fn subscribe_telemetry(&self) -> Option<TelemetryReceiver> {
+		None
+	}
+}
+
+/// Holds the last telemetry, `None` until the first one arrives
+pub type TelemetryReceiver = watch::Receiver<Option<Telemetry>>;
+
+/// The outputs are resent this often, so the stm32 knows the pi is alive
+const KEEPALIVE: Duration = Duration::from_millis(100);
+/// The stm32 applies its own failsafe if the pi stops sending
+const STM32_FAILSAFE_TIMEOUT: Duration = Duration::from_millis(300);
+/// The telemetry is shown on the page, so it should not get too old
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// A failed handshake is repeated after this delay, doubling every time
+const HANDSHAKE_DELAY: Duration = Duration::from_millis(100);
+const MAX_HANDSHAKE_DELAY: Duration = Duration::from_secs(5);
+/// After this many failed outputs in a row the stm32 probably restarted
+/// and needs a new handshake
+const MAX_OUTPUT_FAILURES: usize = 5;
+
+/// Sends the outputs to the stm32
+///
+/// The spi transfers block so the link lives in its own thread.
+pub struct SpiOutput {
+	tx: mpsc::Sender<Outputs>,
+	telemetry: TelemetryReceiver,
+}
+
+impl SpiOutput {
+	pub fn spawn<T>(link: Link<T>) -> Self
+	where
+		T: Transport + Send + 'static,
+	{
+		let (tx, rx) = mpsc::channel();
+		let (telemetry_tx, telemetry) = watch::channel(None);
+		thread::spawn(move || link_thread(link, rx, telemetry_tx));
+		Self { tx, telemetry }
+	}
+}
+
+impl VehicleOutput for SpiOutput {
+	fn apply(&mut self, outputs: &Outputs) {
+		// the link thread only stops if this is dropped
+		let _ = self.tx.send(outputs.clone());
+	}
+
+	fn subscribe_telemetry(&self) -> Option<TelemetryReceiver> {
+		Some(self.telemetry.clone())
+	}
+}
+
+fn scale(v: f32) -> i16 {
+	(v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+impl From<&Outputs> for OutputValues {
+	fn from(o: &Outputs) -> Self {
+		Self {
+			armed: o.armed,
+			steering: scale(o.steering),
+			throttle: scale(o.throttle),
+			aux: o.aux.map(scale),
+		}
+	}
+}
+
+fn handshake<T: Transport>(link: &mut Link<T>) -> Result<(), LinkError> {
+	let timeout = STM32_FAILSAFE_TIMEOUT.as_millis() as i32;
+	link.hello()?;
+	link.set_config(ConfigKey::FailsafeTimeoutMs, timeout)
+}
+
+/// Keeps the outputs flowing, the handshake is repeated until the stm32
+/// answers
+fn link_thread<T: Transport>(
+	mut link: Link<T>,
+	rx: mpsc::Receiver<Outputs>,
+	telemetry: watch::Sender<Option<Telemetry>>,
+) {
+	let mut connected = false;
+	let mut next_handshake = Instant::now();
+	let mut handshake_delay = HANDSHAKE_DELAY;
+	let mut failures = 0;
+
+	let mut outputs = Outputs::default();
+	let mut next_telemetry = Instant::now();
+	loop {
+		if !connected && Instant::now() >= next_handshake {
+			match handshake(&mut link) {
+				Ok(()) => {
+					eprintln!("stm32 link started");
+					connected = true;
+					handshake_delay = HANDSHAKE_DELAY;
+					failures = 0;
+				}
+				Err(e) => {
+					eprintln!(
+						"could not start the stm32 link {}, retrying in {:?}",
+						e, handshake_delay
+					);
+					next_handshake = Instant::now() + handshake_delay;
+					handshake_delay =
+						(handshake_delay * 2).min(MAX_HANDSHAKE_DELAY);
+				}
+			}
+		}
+
+		match rx.recv_timeout(KEEPALIVE) {
+			// only the newest outputs matter
+			Ok(o) => outputs = rx.try_iter().last().unwrap_or(o),
+			Err(RecvTimeoutError::Timeout) => {}
+			Err(RecvTimeoutError::Disconnected) => return,
+		}
+
+		if !connected {
+			continue;
+		}
+
+		match link.set_outputs((&outputs).into()) {
+			Ok(()) => failures = 0,
+			Err(e) => {
+				eprintln!("could not send outputs {}", e);
+				failures += 1;
+			}
+		}
+		if failures >= MAX_OUTPUT_FAILURES {
+			eprintln!("stm32 link lost, repeating the handshake");
+			connected = false;
+			continue;
+		}
+
+		if Instant::now() >= next_telemetry {
+			next_telemetry = Instant::now() + TELEMETRY_INTERVAL;
+			match link.telemetry() {
+				// works without any subscriber
+				Ok(t) => {
+					telemetry.send_replace(Some(t));
+				}
+				Err(e) => eprintln!("could not read telemetry {}", e),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::spi::Loopback;
+
+	use protocol::{Frame, Request};
+
+	use std::io;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	/// A `Loopback` which can be unplugged and counts the sent outputs
+	#[derive(Clone, Default)]
+	struct Plug {
+		online: Arc<AtomicBool>,
+		hellos: Arc<AtomicUsize>,
+		outputs: Arc<AtomicUsize>,
+	}
+
+	struct PluggedLoopback(Loopback, Plug);
+
+	impl Transport for PluggedLoopback {
+		fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+			let plug = &self.1;
+			if !plug.online.load(Ordering::SeqCst) {
+				rx.fill(0);
+				return Ok(());
+			}
+
+			let req = Frame::decode(tx)
+				.and_then(|f| Request::decode(f.msg_type, f.payload));
+			match req {
+				Ok(Request::Hello) => {
+					plug.hellos.fetch_add(1, Ordering::SeqCst)
+				}
+				Ok(Request::SetOutputs(_)) => {
+					plug.outputs.fetch_add(1, Ordering::SeqCst)
+				}
+				_ => 0,
+			};
+			self.0.transfer(tx, rx)
+		}
+	}
+
+	fn wait_for(what: &AtomicUsize, more_than: usize) {
+		let start = Instant::now();
+		while what.load(Ordering::SeqCst) <= more_than {
+			assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+			thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	#[test]
+	fn retries_the_handshake() {
+		let plug = Plug::default();
+		let link = Link::new(PluggedLoopback(Loopback::new(), plug.clone()));
+		let mut output = SpiOutput::spawn(link);
+		output.apply(&Outputs::default());
+
+		thread::sleep(HANDSHAKE_DELAY * 3);
+		assert_eq!(plug.outputs.load(Ordering::SeqCst), 0);
+
+		plug.online.store(true, Ordering::SeqCst);
+		wait_for(&plug.outputs, 0);
+		assert_eq!(plug.hellos.load(Ordering::SeqCst), 1);
+
+		// the stm32 restarted
+		plug.online.store(false, Ordering::SeqCst);
+		thread::sleep(KEEPALIVE * (MAX_OUTPUT_FAILURES as u32 * 2));
+		plug.online.store(true, Ordering::SeqCst);
+		wait_for(&plug.hellos, 1);
+	}
+
+	#[test]
+	fn publishes_the_telemetry() {
+		let output = SpiOutput::spawn(Link::new(Loopback::new()));
+		let mut telemetry = output.subscribe_telemetry().unwrap();
+
+		let start = Instant::now();
+		while telemetry.borrow_and_update().is_none() {
+			assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+			thread::sleep(Duration::from_millis(10));
+		}
+		assert_eq!(telemetry.borrow().unwrap().battery_mv, 7400);
+	}
+}
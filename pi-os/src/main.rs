@@ -1,37 +1,121 @@
 mod camera;
+mod control;
+mod signaling;
+mod spi;
+mod ui;
 mod webrtc;
 
-use crate::webrtc::Description;
-use camera::FileCamera;
+use camera::{Camera, FileCamera, ProcessCamera, ProcessConfig, SharedCamera};
+use control::{FailsafeConfig, SpiOutput, Vehicle};
+use signaling::CameraFactory;
+use spi::{Link, Loopback, Spidev};
 
-use std::fs::{self, File};
+use std::env;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::time::{sleep, Duration};
+const DEFAULT_ADDR: &str = "0.0.0.0:8080";
+const DEFAULT_VIDEO: &str = "./h264.h264";
+const SPI_SPEED_HZ: u32 = 1_000_000;
 
+/// usage: pi-os [address] [video] [spidev]
+///
+/// The video is either a `.h264` file which is played in a loop, `camera`
+/// for the raspberry pi camera or an encoder command writing h264 to stdout
+/// like `"./scripts/fake-camera.sh ./h264.h264"`. The encoder is shared by
+/// every connection, its `{width}`, `{height}`, `{framerate}` and
+/// `{bitrate}` can be set with `CAMERA_WIDTH`, `CAMERA_HEIGHT`,
+/// `CAMERA_FRAMERATE` and `CAMERA_BITRATE` (in bits per second).
+///
+/// Without a spidev like `/dev/spidev0.0` the stm32 is simulated.
+///
+/// What happens once the control link is lost can be set with
+/// `FAILSAFE_TIMEOUT_MS`, `FAILSAFE_STEERING` and `FAILSAFE_THROTTLE`
+/// (between -1 and 1), a car brakes with a negative throttle.
 #[tokio::main]
 async fn main() {
 	env_logger::init();
 
-	let offer = fs::read_to_string("./session.txt").unwrap();
-	let offer = base64::decode(offer.as_str()).unwrap();
-	let offer = serde_json::from_slice::<Description>(&offer).unwrap();
+	let mut args = env::args().skip(1);
+	let addr: SocketAddr = args
+		.next()
+		.as_deref()
+		.unwrap_or(DEFAULT_ADDR)
+		.parse()
+		.expect("invalid address");
+	let video = args.next().unwrap_or_else(|| DEFAULT_VIDEO.to_owned());
 
-	let webrtc = crate::webrtc::Webrtc::new();
-	let camera = FileCamera::new("./h264.h264");
-	let con = webrtc
-		.create_connection(offer, Box::new(camera))
-		.await
-		.unwrap();
+	let output = match args.next() {
+		Some(path) => {
+			let spidev = Spidev::open(&path, SPI_SPEED_HZ)
+				.expect("could not open spidev");
+			SpiOutput::spawn(Link::new(spidev))
+		}
+		None => SpiOutput::spawn(Link::new(Loopback::new())),
+	};
 
-	eprintln!("connection created");
+	let vehicle = Vehicle::new(Box::new(output), failsafe_config()).shared();
+	tokio::spawn(control::watchdog(Arc::downgrade(&vehicle)));
+	let webrtc = crate::webrtc::Webrtc::new(vehicle);
 
-	let answer = con.description().await;
-	let answer = serde_json::to_string(&answer).unwrap();
-	let answer = base64::encode(answer.as_str());
+	signaling::serve(addr, webrtc, camera_factory(video)).await;
+}
+
+/// Every connection plays the file on its own but there is only one
+/// encoder
+fn camera_factory(video: String) -> CameraFactory {
+	if video.ends_with(".h264") {
+		return Box::new(move || {
+			Box::new(FileCamera::new(&video).looping(true)) as Box<dyn Camera>
+		});
+	}
+
+	let mut config = if video == "camera" {
+		ProcessConfig::default()
+	} else {
+		ProcessConfig::from_command_line(&video)
+	};
+	config.width = env_var("CAMERA_WIDTH").unwrap_or(config.width);
+	config.height = env_var("CAMERA_HEIGHT").unwrap_or(config.height);
+	config.framerate = env_var("CAMERA_FRAMERATE").unwrap_or(config.framerate);
+	config.bitrate = env_var("CAMERA_BITRATE").unwrap_or(config.bitrate);
 
-	eprintln!("answer {:?}", answer);
+	let camera =
+		SharedCamera::new(move || Box::new(ProcessCamera::new(config.clone())));
+	Box::new(move || camera.viewer())
+}
+
+/// Reads an optional environment variable, panics if it is invalid
+fn env_var<T>(name: &str) -> Option<T>
+where
+	T: FromStr,
+	T::Err: Debug,
+{
+	let value = env::var(name).ok()?;
+	Some(
+		value
+			.parse()
+			.unwrap_or_else(|e| panic!("invalid {} {:?}", name, e)),
+	)
+}
 
-	sleep(Duration::from_secs(25)).await;
+fn failsafe_config() -> FailsafeConfig {
+	let default = FailsafeConfig::default();
+	let config = FailsafeConfig {
+		timeout: env_var("FAILSAFE_TIMEOUT_MS")
+			.map(Duration::from_millis)
+			.unwrap_or(default.timeout),
+		steering: env_var("FAILSAFE_STEERING").unwrap_or(default.steering),
+		throttle: env_var("FAILSAFE_THROTTLE").unwrap_or(default.throttle),
+	};
 
-	con.close().await;
+	let valid = |v: f32| (-1.0..=1.0).contains(&v);
+	assert!(
+		valid(config.steering) && valid(config.throttle),
+		"the failsafe values need to be between -1 and 1"
+	);
+	config
 }
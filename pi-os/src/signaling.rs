@@ -0,0 +1,183 @@
+//! Http signaling so a browser can connect by opening the control page
+//!
+//! The browser posts its offer to `/offer` and gets back the answer and an
+//! id. The ice candidates of both sides are then exchanged over the
+//! websocket at `/candidates/:id`, each message is a json
+//! `RTCIceCandidateInit` or `null` once all candidates were sent.
+//!
+//! Other players can use the standard whep endpoint at `/whep`.
+
+mod whep;
+
+use crate::camera::Camera;
+use crate::ui;
+use crate::webrtc::{self, Connection, Description, Webrtc};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use serde::Serialize;
+
+use ::webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+
+/// Gets called once for every connection
+pub type CameraFactory = Box<dyn Fn() -> Box<dyn Camera> + Send + Sync>;
+
+struct Inner {
+	webrtc: Webrtc,
+	camera: CameraFactory,
+	connections: Mutex<HashMap<u64, Arc<Connection>>>,
+	next_id: AtomicU64,
+}
+
+impl Inner {
+	/// Creates a connection which is forgotten once it is gone
+	async fn connect(
+		self: &Arc<Self>,
+		offer: Description,
+	) -> Result<(u64, Arc<Connection>), webrtc::Error> {
+		let camera = (self.camera)();
+		let con = Arc::new(self.webrtc.create_connection(offer, camera).await?);
+
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		self.connections.lock().unwrap().insert(id, con.clone());
+		eprintln!("connection {} created", id);
+
+		let this = self.clone();
+		let closed = con.clone();
+		tokio::spawn(async move {
+			closed.closed().await;
+			this.close(id).await;
+		});
+
+		Ok((id, con))
+	}
+
+	fn connection(&self, id: u64) -> Option<Arc<Connection>> {
+		self.connections.lock().unwrap().get(&id).cloned()
+	}
+
+	/// Returns false if the connection does not exist (anymore)
+	async fn close(&self, id: u64) -> bool {
+		let con = self.connections.lock().unwrap().remove(&id);
+		match con {
+			Some(con) => {
+				con.close().await;
+				eprintln!("connection {} closed", id);
+				true
+			}
+			None => false,
+		}
+	}
+}
+
+type AppState = Arc<Inner>;
+
+#[derive(Debug, Serialize)]
+struct OfferResponse {
+	id: u64,
+	answer: Description,
+}
+
+pub async fn serve(addr: SocketAddr, webrtc: Webrtc, camera: CameraFactory) {
+	let state = Arc::new(Inner {
+		webrtc,
+		camera,
+		connections: Mutex::new(HashMap::new()),
+		next_id: AtomicU64::new(1),
+	});
+
+	let app = Router::new()
+		.merge(ui::routes())
+		.route("/offer", post(offer))
+		.route("/candidates/:id", get(candidates))
+		.merge(whep::routes())
+		.with_state(state);
+
+	eprintln!("listening on http://{}", addr);
+
+	axum::Server::bind(&addr)
+		.serve(app.into_make_service())
+		.await
+		.expect("http server failed");
+}
+
+async fn offer(
+	State(state): State<AppState>,
+	Json(offer): Json<Description>,
+) -> Result<Json<OfferResponse>, Response> {
+	let (id, con) = state.connect(offer).await.map_err(|e| {
+		eprintln!("could not create connection {:?}", e);
+		(StatusCode::BAD_REQUEST, "invalid offer").into_response()
+	})?;
+
+	let answer = con.description().await;
+
+	Ok(Json(OfferResponse { id, answer }))
+}
+
+async fn candidates(
+	State(state): State<AppState>,
+	Path(id): Path<u64>,
+	ws: WebSocketUpgrade,
+) -> Response {
+	match state.connection(id) {
+		Some(con) => ws.on_upgrade(move |socket| trickle(socket, con)),
+		None => StatusCode::NOT_FOUND.into_response(),
+	}
+}
+
+/// Sends the local candidates and adds the remote ones until either side
+/// closes the websocket
+async fn trickle(mut socket: WebSocket, con: Arc<Connection>) {
+	let Some(mut local) = con.take_candidates() else {
+		eprintln!("candidates already taken");
+		return;
+	};
+	let mut local_done = false;
+
+	loop {
+		tokio::select! {
+			c = local.recv(), if !local_done => {
+				let c = c.flatten();
+				local_done = c.is_none();
+				let msg = serde_json::to_string(&c).unwrap();
+				if socket.send(Message::Text(msg)).await.is_err() {
+					return;
+				}
+			}
+			msg = socket.recv() => {
+				let msg = match msg {
+					Some(Ok(Message::Text(msg))) => msg,
+					Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+						return;
+					}
+					Some(Ok(_)) => continue,
+				};
+
+				let c = serde_json::from_str::<Option<RTCIceCandidateInit>>(
+					&msg,
+				);
+				match c {
+					Ok(Some(c)) => {
+						if let Err(e) = con.add_ice_candidate(c).await {
+							eprintln!("could not add candidate {:?}", e);
+						}
+					}
+					// the remote side has sent all candidates
+					Ok(None) => {}
+					Err(e) => eprintln!("invalid candidate {:?}", e),
+				}
+			}
+		}
+	}
+}
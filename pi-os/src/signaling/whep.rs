@@ -0,0 +1,165 @@
+//! The camera as a whep resource, see
+//! https://datatracker.ietf.org/doc/draft-ietf-wish-whep/
+//!
+//! Server candidates are sent in the answer, the client can trickle its
+//! candidates with `PATCH`. Ice restarts are not supported.
+
+use super::AppState;
+use crate::webrtc::Description;
+
+use axum::extract::{Path, State};
+use axum::http::header::{self, HeaderMap, HeaderValue};
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum::Router;
+
+use ::webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+
+const SDP: &str = "application/sdp";
+const SDP_FRAG: &str = "application/trickle-ice-sdpfrag";
+
+pub fn routes() -> Router<AppState> {
+	Router::new()
+		.route("/whep", post(offer).options(preflight))
+		.route(
+			"/whep/:id",
+			delete(teardown).patch(trickle).options(preflight),
+		)
+		.layer(middleware::from_fn(cors))
+}
+
+fn has_content_type(headers: &HeaderMap, expected: &str) -> bool {
+	headers
+		.get(header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.split(';').next())
+		.is_some_and(|v| v.trim().eq_ignore_ascii_case(expected))
+}
+
+async fn offer(
+	State(state): State<AppState>,
+	headers: HeaderMap,
+	body: String,
+) -> Response {
+	if !has_content_type(&headers, SDP) {
+		return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+	}
+
+	let Ok(offer) = Description::offer(body) else {
+		return (StatusCode::BAD_REQUEST, "invalid offer").into_response();
+	};
+
+	let (id, con) = match state.connect(offer).await {
+		Ok(c) => c,
+		Err(e) => {
+			eprintln!("could not create connection {:?}", e);
+			return (StatusCode::BAD_REQUEST, "invalid offer").into_response();
+		}
+	};
+
+	let answer = con.gathered_description().await;
+
+	(
+		StatusCode::CREATED,
+		[
+			(header::CONTENT_TYPE, SDP.to_owned()),
+			(header::LOCATION, format!("/whep/{}", id)),
+		],
+		answer.sdp,
+	)
+		.into_response()
+}
+
+async fn trickle(
+	State(state): State<AppState>,
+	Path(id): Path<u64>,
+	headers: HeaderMap,
+	body: String,
+) -> StatusCode {
+	if !has_content_type(&headers, SDP_FRAG) {
+		return StatusCode::UNSUPPORTED_MEDIA_TYPE;
+	}
+
+	let Some(con) = state.connection(id) else {
+		return StatusCode::NOT_FOUND;
+	};
+
+	for candidate in parse_sdp_frag(&body) {
+		if let Err(e) = con.add_ice_candidate(candidate).await {
+			eprintln!("could not add candidate {:?}", e);
+			return StatusCode::BAD_REQUEST;
+		}
+	}
+
+	StatusCode::NO_CONTENT
+}
+
+async fn teardown(
+	State(state): State<AppState>,
+	Path(id): Path<u64>,
+) -> StatusCode {
+	if state.close(id).await {
+		StatusCode::OK
+	} else {
+		StatusCode::NOT_FOUND
+	}
+}
+
+async fn preflight() -> StatusCode {
+	StatusCode::NO_CONTENT
+}
+
+/// Allows players on other origins
+async fn cors<B>(req: Request<B>, next: Next<B>) -> Response {
+	let mut res = next.run(req).await;
+	let headers = res.headers_mut();
+	let mut set = |name, value| {
+		headers.insert(name, HeaderValue::from_static(value));
+	};
+	set(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+	set(
+		header::ACCESS_CONTROL_ALLOW_METHODS,
+		"POST, PATCH, DELETE, OPTIONS",
+	);
+	set(
+		header::ACCESS_CONTROL_ALLOW_HEADERS,
+		"content-type, if-match",
+	);
+	set(header::ACCESS_CONTROL_EXPOSE_HEADERS, "location");
+	res
+}
+
+/// Returns the candidates of a trickle ice sdp fragment (rfc 8840)
+///
+/// The fragment contains the ice credentials and for every media section
+/// an `m=` and `a=mid:` line followed by its candidates.
+fn parse_sdp_frag(frag: &str) -> Vec<RTCIceCandidateInit> {
+	let mut candidates = vec![];
+	let mut ufrag = None;
+	let mut mline_index: Option<u16> = None;
+	let mut mid = None;
+
+	for line in frag.lines().map(str::trim) {
+		if let Some(v) = line.strip_prefix("a=ice-ufrag:") {
+			ufrag = Some(v.to_owned());
+		} else if line.starts_with("m=") {
+			mline_index = Some(mline_index.map_or(0, |i| i + 1));
+			mid = None;
+		} else if let Some(v) = line.strip_prefix("a=mid:") {
+			mid = Some(v.to_owned());
+		} else if let Some(v) = line.strip_prefix("a=") {
+			if v.starts_with("candidate:") {
+				candidates.push(RTCIceCandidateInit {
+					candidate: v.to_owned(),
+					sdp_mid: mid.clone(),
+					sdp_mline_index: mline_index,
+					username_fragment: ufrag.clone(),
+				});
+			}
+		}
+	}
+
+	candidates
+}
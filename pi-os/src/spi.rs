@@ -0,0 +1,360 @@
+//! The spi link to the stm32
+//!
+//! The pi is the master and sends a request, then polls until the stm32
+//! clocks out the response with the same sequence number. Requests are
+//! repeated if the response is corrupted or does not arrive in time, so
+//! every request needs to be idempotent.
+
+mod spidev;
+mod transport;
+
+pub use protocol::{
+	ConfigKey, DecodeError, ErrorCode, OutputValues, Request, Response,
+	Telemetry, PROTOCOL_VERSION,
+};
+pub use spidev::Spidev;
+pub use transport::{Loopback, Transport};
+
+use protocol::{encode_frame, Frame, MAX_FRAME, MAX_PAYLOAD};
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// How often the response is polled before the request is repeated
+const POLL_ATTEMPTS: usize = 5;
+/// Gives the stm32 time to prepare the response
+const POLL_DELAY: Duration = Duration::from_micros(200);
+const RETRIES: usize = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LinkError {
+	#[error("spi transfer failed {0}")]
+	Io(#[from] io::Error),
+	#[error("invalid response {0}")]
+	Decode(#[from] DecodeError),
+	#[error("no response")]
+	Timeout,
+	#[error("expected sequence {expected} got {got}")]
+	SeqMismatch { expected: u8, got: u8 },
+	#[error("the stm32 rejected the request: {0}")]
+	Device(ErrorCode),
+	#[error("stm32 uses protocol version {0}")]
+	Version(u8),
+	#[error("unexpected response {0:?}")]
+	Unexpected(Response),
+}
+
+impl LinkError {
+	/// Errors from a corrupted or lost frame
+	fn is_transient(&self) -> bool {
+		matches!(
+			self,
+			Self::Decode(_) | Self::Timeout | Self::SeqMismatch { .. }
+		)
+	}
+}
+
+pub struct Link<T> {
+	transport: T,
+	seq: u8,
+}
+
+impl<T: Transport> Link<T> {
+	pub fn new(transport: T) -> Self {
+		Self { transport, seq: 0 }
+	}
+
+	/// Checks that the stm32 speaks the same protocol version
+	pub fn hello(&mut self) -> Result<(), LinkError> {
+		match self.request(&Request::Hello)? {
+			Response::Hello { version } if version == PROTOCOL_VERSION => {
+				Ok(())
+			}
+			Response::Hello { version } => Err(LinkError::Version(version)),
+			res => Err(LinkError::Unexpected(res)),
+		}
+	}
+
+	pub fn set_outputs(
+		&mut self,
+		outputs: OutputValues,
+	) -> Result<(), LinkError> {
+		self.expect_ack(&Request::SetOutputs(outputs))
+	}
+
+	pub fn set_config(
+		&mut self,
+		key: ConfigKey,
+		value: i32,
+	) -> Result<(), LinkError> {
+		self.expect_ack(&Request::SetConfig(key, value))
+	}
+
+	pub fn telemetry(&mut self) -> Result<Telemetry, LinkError> {
+		match self.request(&Request::GetTelemetry)? {
+			Response::Telemetry(t) => Ok(t),
+			res => Err(LinkError::Unexpected(res)),
+		}
+	}
+
+	fn expect_ack(&mut self, req: &Request) -> Result<(), LinkError> {
+		match self.request(req)? {
+			Response::Ack => Ok(()),
+			res => Err(LinkError::Unexpected(res)),
+		}
+	}
+
+	/// Sends a request and waits for its response, retrying on transient
+	/// errors
+	pub fn request(&mut self, req: &Request) -> Result<Response, LinkError> {
+		self.seq = self.seq.wrapping_add(1);
+
+		let mut attempt = 0;
+		loop {
+			match self.try_request(req) {
+				Ok(Response::Error(code)) => {
+					return Err(LinkError::Device(code))
+				}
+				Ok(res) => return Ok(res),
+				Err(e) if e.is_transient() && attempt < RETRIES => {
+					eprintln!("spi request failed, retrying {}", e);
+					attempt += 1;
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+
+	fn try_request(&mut self, req: &Request) -> Result<Response, LinkError> {
+		let mut payload = [0; MAX_PAYLOAD];
+		let payload_len = req.encode(&mut payload);
+		let mut tx = [0; MAX_FRAME];
+		encode_frame(
+			self.seq,
+			req.msg_type(),
+			&payload[..payload_len],
+			&mut tx,
+		);
+
+		let mut rx = [0; MAX_FRAME];
+		self.transport.transfer(&tx, &mut rx)?;
+
+		// the stm32 sends idle bytes until the response is ready
+		let idle = [0; MAX_FRAME];
+		let mut last_error = LinkError::Timeout;
+		for _ in 0..POLL_ATTEMPTS {
+			thread::sleep(POLL_DELAY);
+			self.transport.transfer(&idle, &mut rx)?;
+
+			let frame = match Frame::decode(&rx) {
+				Ok(f) => f,
+				Err(DecodeError::NoFrame) => continue,
+				Err(e) => {
+					last_error = e.into();
+					continue;
+				}
+			};
+
+			if frame.seq != self.seq {
+				// probably the late response to an earlier attempt
+				last_error = LinkError::SeqMismatch {
+					expected: self.seq,
+					got: frame.seq,
+				};
+				continue;
+			}
+
+			return Ok(Response::decode(frame.msg_type, frame.payload)?);
+		}
+
+		Err(last_error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	/// A `Loopback` which lets the test change every received frame
+	struct Faulty<F> {
+		inner: Loopback,
+		fault: F,
+	}
+
+	impl<F: FnMut(&mut [u8])> Faulty<F> {
+		fn new(fault: F) -> Self {
+			Self {
+				inner: Loopback::new(),
+				fault,
+			}
+		}
+	}
+
+	impl<F: FnMut(&mut [u8])> Transport for Faulty<F> {
+		fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+			self.inner.transfer(tx, rx)?;
+			if Frame::decode(rx).is_ok() {
+				(self.fault)(rx);
+			}
+			Ok(())
+		}
+	}
+
+	/// Re-encodes the response frame in `rx`
+	fn rewrite(rx: &mut [u8], f: impl FnOnce(&mut u8, &mut Response)) {
+		let frame = Frame::decode(rx).unwrap();
+		let mut seq = frame.seq;
+		let mut res = Response::decode(frame.msg_type, frame.payload).unwrap();
+		f(&mut seq, &mut res);
+
+		let mut payload = [0; MAX_PAYLOAD];
+		let len = res.encode(&mut payload);
+		let mut out = [0; MAX_FRAME];
+		encode_frame(seq, res.msg_type(), &payload[..len], &mut out);
+		rx.copy_from_slice(&out[..rx.len()]);
+	}
+
+	/// Applies `fault` to the first `n` responses, returns how many
+	/// responses were received
+	fn first<F: FnMut(&mut [u8])>(
+		n: usize,
+		mut fault: F,
+	) -> (Rc<Cell<usize>>, impl FnMut(&mut [u8])) {
+		let count = Rc::new(Cell::new(0));
+		let counter = count.clone();
+		let fault = move |rx: &mut [u8]| {
+			counter.set(counter.get() + 1);
+			if counter.get() <= n {
+				fault(rx);
+			}
+		};
+		(count, fault)
+	}
+
+	#[test]
+	fn hello_outputs_and_telemetry() {
+		let mut link = Link::new(Loopback::new());
+		link.hello().unwrap();
+		link.set_outputs(OutputValues {
+			armed: true,
+			steering: 100,
+			throttle: -100,
+			aux: [1, 2, 3, 4],
+		})
+		.unwrap();
+		link.set_config(ConfigKey::FailsafeTimeoutMs, 300).unwrap();
+
+		let telemetry = link.telemetry().unwrap();
+		assert_eq!(telemetry.battery_mv, 7400);
+		assert!(!telemetry.failsafe);
+	}
+
+	#[test]
+	fn rejected_config() {
+		let mut link = Link::new(Loopback::new());
+		for (key, value) in [
+			(ConfigKey::FailsafeTimeoutMs, 0),
+			(ConfigKey::SteeringTrim, 10),
+		] {
+			let res = link.set_config(key, value);
+			assert!(
+				matches!(
+					res,
+					Err(LinkError::Device(ErrorCode::UnsupportedConfig))
+				),
+				"{:?}",
+				res
+			);
+		}
+	}
+
+	#[test]
+	fn version_mismatch() {
+		let mut link = Link::new(Faulty::new(|rx: &mut [u8]| {
+			rewrite(rx, |_, res| {
+				*res = Response::Hello {
+					version: PROTOCOL_VERSION + 1,
+				}
+			})
+		}));
+
+		let res = link.hello();
+		let Err(LinkError::Version(version)) = res else {
+			panic!("{:?}", res);
+		};
+		assert_eq!(version, PROTOCOL_VERSION + 1);
+	}
+
+	#[test]
+	fn unexpected_response() {
+		let mut link = Link::new(Faulty::new(|rx: &mut [u8]| {
+			rewrite(rx, |_, res| *res = Response::Ack)
+		}));
+		assert!(matches!(link.hello(), Err(LinkError::Unexpected(_))));
+	}
+
+	#[test]
+	fn retries_corrupted_response() {
+		let (count, fault) = first(1, |rx: &mut [u8]| {
+			// the crc no longer matches
+			rx[1] ^= 0xff;
+		});
+		let mut link = Link::new(Faulty::new(fault));
+
+		link.hello().unwrap();
+		assert_eq!(count.get(), 2);
+	}
+
+	#[test]
+	fn retries_dropped_response() {
+		let (count, fault) = first(2, |rx: &mut [u8]| rx.fill(0));
+		let mut link = Link::new(Faulty::new(fault));
+
+		link.telemetry().unwrap();
+		assert_eq!(count.get(), 3);
+	}
+
+	#[test]
+	fn retries_late_response() {
+		let (count, fault) = first(1, |rx: &mut [u8]| {
+			rewrite(rx, |seq, _| *seq = seq.wrapping_sub(1))
+		});
+		let mut link = Link::new(Faulty::new(fault));
+
+		link.hello().unwrap();
+		assert_eq!(count.get(), 2);
+	}
+
+	#[test]
+	fn gives_up_on_seq_mismatch() {
+		let mut link = Link::new(Faulty::new(|rx: &mut [u8]| {
+			rewrite(rx, |seq, _| *seq = seq.wrapping_add(1))
+		}));
+
+		let res = link.hello();
+		assert!(
+			matches!(
+				res,
+				Err(LinkError::SeqMismatch {
+					expected: 1,
+					got: 2
+				})
+			),
+			"{:?}",
+			res
+		);
+	}
+
+	#[test]
+	fn gives_up_without_response() {
+		let (count, fault) = first(usize::MAX, |rx: &mut [u8]| rx.fill(0));
+		let mut link = Link::new(Faulty::new(fault));
+
+		assert!(matches!(link.hello(), Err(LinkError::Timeout)));
+		assert_eq!(count.get(), RETRIES + 1);
+	}
+}
@@ -0,0 +1,78 @@
+use super::transport::Transport;
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// see linux/spi/spidev.h
+const SPI_IOC_WR_MODE: u32 = 0x4001_6b01;
+const SPI_IOC_WR_BITS_PER_WORD: u32 = 0x4001_6b03;
+const SPI_IOC_WR_MAX_SPEED_HZ: u32 = 0x4004_6b04;
+/// SPI_IOC_MESSAGE(1)
+const SPI_IOC_MESSAGE_1: u32 = 0x4020_6b00;
+
+const SPI_MODE_0: u8 = 0;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SpiIocTransfer {
+	tx_buf: u64,
+	rx_buf: u64,
+	len: u32,
+	speed_hz: u32,
+	delay_usecs: u16,
+	bits_per_word: u8,
+	cs_change: u8,
+	tx_nbits: u8,
+	rx_nbits: u8,
+	word_delay_usecs: u8,
+	pad: u8,
+}
+
+/// A linux spidev device like `/dev/spidev0.0`
+pub struct Spidev {
+	file: File,
+	speed_hz: u32,
+}
+
+impl Spidev {
+	pub fn open(path: impl AsRef<Path>, speed_hz: u32) -> io::Result<Self> {
+		let file = OpenOptions::new().read(true).write(true).open(path)?;
+		let this = Self { file, speed_hz };
+
+		this.ioctl(SPI_IOC_WR_MODE, &SPI_MODE_0)?;
+		this.ioctl(SPI_IOC_WR_BITS_PER_WORD, &8u8)?;
+		this.ioctl(SPI_IOC_WR_MAX_SPEED_HZ, &speed_hz)?;
+
+		Ok(this)
+	}
+
+	fn ioctl<T>(&self, request: u32, arg: &T) -> io::Result<()> {
+		// safety: the request matches the type of the argument which
+		// lives until the call returns
+		let ret = unsafe {
+			libc::ioctl(self.file.as_raw_fd(), request as _, arg as *const T)
+		};
+		if ret < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+}
+
+impl Transport for Spidev {
+	fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+		assert_eq!(tx.len(), rx.len(), "spi transfers are full duplex");
+
+		let transfer = SpiIocTransfer {
+			tx_buf: tx.as_ptr() as u64,
+			rx_buf: rx.as_mut_ptr() as u64,
+			len: tx.len() as u32,
+			speed_hz: self.speed_hz,
+			bits_per_word: 8,
+			..Default::default()
+		};
+		self.ioctl(SPI_IOC_MESSAGE_1, &transfer)
+	}
+}
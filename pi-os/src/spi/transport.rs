@@ -0,0 +1,109 @@
+use protocol::{
+	respond, ConfigKey, ErrorCode, OutputValues, Request, Response, Telemetry,
+	MAX_FRAME, PROTOCOL_VERSION,
+};
+
+use std::io;
+use std::time::Instant;
+
+/// A full duplex spi bus with the stm32 as the only device
+pub trait Transport {
+	/// Clocks out `tx` while reading the same amount of bytes into `rx`
+	fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()>;
+}
+
+/// Simulates the stm32 in memory
+///
+/// Like the real device the response to a request is only clocked out
+/// with the next transfer, until then idle bytes are returned.
+pub struct Loopback {
+	device: SimulatedDevice,
+	pending: Option<([u8; MAX_FRAME], usize)>,
+}
+
+impl Loopback {
+	pub fn new() -> Self {
+		Self {
+			device: SimulatedDevice::new(),
+			pending: None,
+		}
+	}
+}
+
+impl Transport for Loopback {
+	fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+		rx.fill(0);
+		if let Some((frame, len)) = self.pending.take() {
+			let len = len.min(rx.len());
+			rx[..len].copy_from_slice(&frame[..len]);
+		}
+
+		// idle bytes from the host don't contain a frame
+		let mut out = [0; MAX_FRAME];
+		let device = &mut self.device;
+		if let Some(len) = respond(tx, &mut out, |req| device.handle(req)) {
+			self.pending = Some((out, len));
+		}
+
+		Ok(())
+	}
+}
+
+/// What the stm32 does with the requests, prints the outputs instead of
+/// driving the motors
+struct SimulatedDevice {
+	started: Instant,
+	outputs: OutputValues,
+}
+
+impl SimulatedDevice {
+	fn new() -> Self {
+		Self {
+			started: Instant::now(),
+			outputs: OutputValues::default(),
+		}
+	}
+
+	/// Small changes of the sticks are not printed
+	fn changed(&self, outputs: &OutputValues) -> bool {
+		const STEP: i32 = i16::MAX as i32 / 20;
+		let moved = |a: i16, b: i16| (a as i32 - b as i32).abs() >= STEP;
+		let last = &self.outputs;
+
+		last.armed != outputs.armed
+			|| moved(last.steering, outputs.steering)
+			|| moved(last.throttle, outputs.throttle)
+			|| last
+				.aux
+				.iter()
+				.zip(&outputs.aux)
+				.any(|(a, b)| moved(*a, *b))
+	}
+
+	fn handle(&mut self, req: Request) -> Response {
+		match req {
+			Request::Hello => Response::Hello {
+				version: PROTOCOL_VERSION,
+			},
+			Request::SetOutputs(outputs) => {
+				if self.changed(&outputs) {
+					eprintln!("stm32 outputs {:?}", outputs);
+					self.outputs = outputs;
+				}
+				Response::Ack
+			}
+			Request::GetTelemetry => Response::Telemetry(Telemetry {
+				battery_mv: 7400,
+				temperature: 250,
+				uptime_ms: self.started.elapsed().as_millis() as u32,
+				failsafe: false,
+			}),
+			Request::SetConfig(ConfigKey::FailsafeTimeoutMs, ms) if ms > 0 => {
+				Response::Ack
+			}
+			Request::SetConfig(..) => {
+				Response::Error(ErrorCode::UnsupportedConfig)
+			}
+		}
+	}
+}
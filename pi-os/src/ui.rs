@@ -0,0 +1,44 @@
+//! The control page, the assets are embedded so no internet access is needed
+
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+/// (path, content type, content)
+const ASSETS: &[(&str, &str, &str)] = &[
+	(
+		"/",
+		"text/html; charset=utf-8",
+		include_str!("ui/index.html"),
+	),
+	("/style.css", "text/css", include_str!("ui/style.css")),
+	(
+		"/signaling.js",
+		"text/javascript",
+		include_str!("ui/signaling.js"),
+	),
+	(
+		"/control.js",
+		"text/javascript",
+		include_str!("ui/control.js"),
+	),
+];
+
+pub fn routes<S>() -> Router<S>
+where
+	S: Clone + Send + Sync + 'static,
+{
+	ASSETS.iter().fold(
+		Router::new(),
+		|router, &(path, content_type, content)| {
+			router.route(
+				path,
+				get(move || async move {
+					([(header::CONTENT_TYPE, content_type)], content)
+						.into_response()
+				}),
+			)
+		},
+	)
+}
@@ -0,0 +1,238 @@
+'use strict';
+
+// how often the control values get sent in ms
+const SEND_INTERVAL = 50;
+// how far a touch stick can be moved in px
+const STICK_RADIUS = 80;
+const GAMEPAD_DEADZONE = 0.08;
+// see pi-os/src/control.rs
+const PROTOCOL_VERSION = 1;
+
+const $ = id => document.getElementById(id);
+const clamp = v => Math.max(-1, Math.min(1, v));
+
+let seq = 0;
+const message = (type, fields = {}) => JSON.stringify({
+	v: PROTOCOL_VERSION,
+	seq: seq++,
+	time: Date.now(),
+	type,
+	...fields
+});
+
+// the state the car reported, arming is only requested
+let armed = false;
+let toggleArm = () => {};
+
+// every input source reports steering and throttle between -1 and 1
+const sources = {
+	keyboard: { steering: 0, throttle: 0 },
+	touch: { steering: 0, throttle: 0 },
+	gamepad: { steering: 0, throttle: 0 }
+};
+
+// keyboard
+const keys = new Set();
+const updateKeyboard = () => {
+	const has = (...k) => k.some(k => keys.has(k));
+	sources.keyboard.steering =
+		has('ArrowRight', 'KeyD') - has('ArrowLeft', 'KeyA');
+	sources.keyboard.throttle =
+		has('ArrowUp', 'KeyW') - has('ArrowDown', 'KeyS');
+};
+addEventListener('keydown', e => {
+	if (e.code === 'KeyR' && !e.repeat)
+		toggleArm();
+	keys.add(e.code);
+	updateKeyboard();
+});
+addEventListener('keyup', e => { keys.delete(e.code); updateKeyboard(); });
+// don't keep driving if the keyup gets lost
+addEventListener('blur', () => { keys.clear(); updateKeyboard(); });
+
+// touch, the left half is the throttle the right half the steering
+function touchStick(zone, axis, vertical) {
+	const knob = zone.querySelector('.knob');
+	let pointer = null;
+	let origin = null;
+
+	zone.addEventListener('pointerdown', e => {
+		if (pointer !== null)
+			return;
+		pointer = e.pointerId;
+		origin = [e.clientX, e.clientY];
+		zone.setPointerCapture(e.pointerId);
+		zone.classList.add('active');
+		move(e);
+	});
+
+	const move = e => {
+		if (e.pointerId !== pointer)
+			return;
+		const rect = zone.getBoundingClientRect();
+		const dx = clamp((e.clientX - origin[0]) / STICK_RADIUS);
+		const dy = clamp((e.clientY - origin[1]) / STICK_RADIUS);
+		sources.touch[axis] = vertical ? -dy : dx;
+		knob.style.left = `${origin[0] - rect.left +
+			(vertical ? 0 : dx * STICK_RADIUS)}px`;
+		knob.style.top = `${origin[1] - rect.top +
+			(vertical ? dy * STICK_RADIUS : 0)}px`;
+	};
+	zone.addEventListener('pointermove', move);
+
+	const release = e => {
+		if (e.pointerId !== pointer)
+			return;
+		pointer = null;
+		sources.touch[axis] = 0;
+		zone.classList.remove('active');
+	};
+	zone.addEventListener('pointerup', release);
+	zone.addEventListener('pointercancel', release);
+}
+touchStick($('throttle-zone'), 'throttle', true);
+touchStick($('steering-zone'), 'steering', false);
+
+// gamepad, left stick steers, the triggers are throttle and brake, the
+// right stick is aux 1 and 2 and start toggles arming
+let aux = [0, 0];
+let startPressed = false;
+function updateGamepad() {
+	const pad = [...navigator.getGamepads()].find(p => p && p.connected);
+	if (!pad) {
+		sources.gamepad.steering = 0;
+		sources.gamepad.throttle = 0;
+		aux = [0, 0];
+		return;
+	}
+
+	const deadzone = v => Math.abs(v) < GAMEPAD_DEADZONE ? 0 : v;
+	sources.gamepad.steering = deadzone(pad.axes[0] || 0);
+	aux = [deadzone(pad.axes[2] || 0), deadzone(-(pad.axes[3] || 0))];
+
+	const start = pad.buttons[9] ? pad.buttons[9].pressed : false;
+	if (start && !startPressed)
+		toggleArm();
+	startPressed = start;
+	if (pad.mapping === 'standard')
+		sources.gamepad.throttle =
+			deadzone(pad.buttons[7].value - pad.buttons[6].value);
+	else
+		sources.gamepad.throttle = deadzone(-(pad.axes[1] || 0));
+}
+
+// the source which is moved the furthest wins
+function currentInput() {
+	let steering = 0, throttle = 0, active = 'keyboard';
+	for (const [name, s] of Object.entries(sources)) {
+		if (Math.abs(s.steering) > Math.abs(steering)) {
+			steering = s.steering;
+			active = name;
+		}
+		if (Math.abs(s.throttle) > Math.abs(throttle)) {
+			throttle = s.throttle;
+			active = name;
+		}
+	}
+	return { steering, throttle, active };
+}
+
+function showBar(el, v) {
+	el.style.left = `${50 + Math.min(v, 0) * 50}%`;
+	el.style.width = `${Math.abs(v) * 50}%`;
+}
+
+// telemetry, the car sends json objects on the control channel
+const telemetry = new Map();
+function showTelemetry() {
+	const table = $('telemetry');
+	table.replaceChildren();
+	for (const [k, v] of telemetry) {
+		const row = table.insertRow();
+		row.insertCell().textContent = k;
+		row.insertCell().textContent = v;
+	}
+}
+
+async function gatherStats(pc) {
+	let lastBytes = null, lastTime = null;
+	setInterval(async () => {
+		const stats = await pc.getStats();
+		stats.forEach(s => {
+			if (s.type === 'inbound-rtp' && s.kind === 'video') {
+				telemetry.set('fps', s.framesPerSecond ?? '-');
+				if (lastBytes !== null) {
+					const kbit = (s.bytesReceived - lastBytes) * 8 /
+						(s.timestamp - lastTime);
+					telemetry.set('video', `${kbit.toFixed(0)} kbit/s`);
+				}
+				lastBytes = s.bytesReceived;
+				lastTime = s.timestamp;
+			}
+			if (s.type === 'candidate-pair' && s.nominated &&
+				s.currentRoundTripTime !== undefined)
+			{
+				const rtt = s.currentRoundTripTime * 1000;
+				telemetry.set('rtt', `${rtt.toFixed(0)} ms`);
+			}
+		});
+		showTelemetry();
+	}, 1000);
+}
+
+async function main() {
+	const { pc, control, sticks } = await connectCar({
+		onTrack: stream => { $('video').srcObject = stream; },
+		onState: state => { $('state').textContent = state; }
+	});
+
+	toggleArm = () => {
+		if (control.readyState === 'open')
+			control.send(message(armed ? 'disarm' : 'arm'));
+	};
+	$('arm').onclick = toggleArm;
+
+	control.onmessage = e => {
+		let msg;
+		try {
+			msg = JSON.parse(e.data);
+		} catch (err) {
+			console.warn('invalid telemetry', e.data);
+			return;
+		}
+
+		// after a failsafe the car has to be armed again
+		if ('state' in msg) {
+			armed = msg.state === 'armed';
+			$('arm').textContent = armed ? 'disarm' : 'arm';
+			$('arm').classList.toggle('armed', armed);
+		}
+
+		// an answer to a control message
+		if ('seq' in msg) {
+			telemetry.set('control rtt', `${Date.now() - msg.time} ms`);
+			delete msg.seq;
+			delete msg.time;
+		}
+
+		for (const [k, v] of Object.entries(msg))
+			telemetry.set(k, v);
+		showTelemetry();
+	};
+
+	gatherStats(pc);
+
+	setInterval(() => {
+		updateGamepad();
+		const { steering, throttle, active } = currentInput();
+		showBar($('steering-bar'), steering);
+		showBar($('throttle-bar'), throttle);
+		$('input').textContent = active;
+
+		// the car ignores the sticks while disarmed
+		if (armed && sticks.readyState === 'open')
+			sticks.send(message('sticks', { steering, throttle, aux }));
+	}, SEND_INTERVAL);
+}
+
+main().catch(e => { $('state').textContent = e.message; });
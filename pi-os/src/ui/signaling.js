@@ -0,0 +1,58 @@
+'use strict';
+
+// Connects to pi-os, posts the offer to /offer and trickles the candidates
+// over the websocket at /candidates/:id.
+// Returns the peer connection and the control data channels.
+async function connectCar({ onTrack, onState }) {
+	const pc = new RTCPeerConnection({
+		iceServers: [{ urls: 'stun:stun.l.google.com:19302' }]
+	});
+	pc.addTransceiver('video', { direction: 'recvonly' });
+	pc.ontrack = e => onTrack(e.streams[0]);
+	pc.onconnectionstatechange = () => onState(pc.connectionState);
+
+	// need to exist before the offer is created, a lost stick message is
+	// never retransmitted since the next one is newer anyway
+	const control = pc.createDataChannel('control');
+	const sticks = pc.createDataChannel('sticks', {
+		ordered: false,
+		maxRetransmits: 0
+	});
+
+	// candidates are sent once the websocket is open
+	let ws = null;
+	const pending = [];
+	const sendCandidate = c => {
+		if (ws && ws.readyState === WebSocket.OPEN)
+			ws.send(JSON.stringify(c));
+		else
+			pending.push(c);
+	};
+	pc.onicecandidate = e => {
+		sendCandidate(e.candidate ? e.candidate.toJSON() : null);
+	};
+
+	await pc.setLocalDescription(await pc.createOffer());
+	const res = await fetch('/offer', {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		body: JSON.stringify(pc.localDescription)
+	});
+	if (!res.ok)
+		throw new Error(await res.text());
+	const { id, answer } = await res.json();
+	await pc.setRemoteDescription(answer);
+
+	const proto = location.protocol === 'https:' ? 'wss' : 'ws';
+	ws = new WebSocket(`${proto}://${location.host}/candidates/${id}`);
+	ws.onopen = () => {
+		pending.splice(0).forEach(c => ws.send(JSON.stringify(c)));
+	};
+	ws.onmessage = e => {
+		const c = JSON.parse(e.data);
+		if (c)
+			pc.addIceCandidate(c);
+	};
+
+	return { pc, control, sticks };
+}
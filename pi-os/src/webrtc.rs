@@ -2,31 +2,31 @@ mod twcc_interceptor;
 
 use twcc_interceptor::TwccInterceptor;
 
-use crate::camera::Camera;
+use crate::camera::{self, Camera};
+use crate::control::{self, Session, SharedVehicle, TelemetryReceiver};
 
-use std::error::Error as StdError;
-use std::fs::{self, File};
-use std::io::{self, BufReader, Write};
-use std::path::Path;
-use std::sync::Arc;
-use std::thread;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::runtime::Handle as RtHandle;
-use tokio::sync::{mpsc, Notify};
+use futures::StreamExt;
+
+use tokio::sync::{mpsc, watch};
+use tokio::time;
 
 use webrtc::api::interceptor_registry::{
 	configure_nack, configure_rtcp_reports, configure_twcc,
 };
-use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
 use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::data_channel_state::RTCDataChannelState;
 use webrtc::data_channel::RTCDataChannel;
-use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
+use webrtc::ice_transport::ice_candidate::{
+	RTCIceCandidate, RTCIceCandidateInit,
+};
 use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::interceptor::registry::Registry;
-use webrtc::media::io::h264_reader::H264Reader;
-use webrtc::media::io::ogg_reader::OggReader;
 pub use webrtc::media::Sample;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
@@ -37,6 +37,12 @@ use webrtc::stats::StatsReportType;
 use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
 use webrtc::track::track_local::TrackLocal;
 
+/// A disconnected connection can still recover, for example after a short
+/// wifi outage, it is only closed if it stays disconnected this long
+const DISCONNECTED_GRACE: Duration = Duration::from_secs(10);
+/// How often the video bitrate is logged
+const LOG_STATS_EVERY: Duration = Duration::from_secs(10);
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
 	#[error("webrtc error")]
@@ -49,21 +55,23 @@ enum State {
 	Disconnected,
 }
 
-pub struct Webrtc {}
+pub struct Webrtc {
+	vehicle: SharedVehicle,
+}
 
 impl Webrtc {
-	pub fn new() -> Self {
-		Self {}
+	pub fn new(vehicle: SharedVehicle) -> Self {
+		Self { vehicle }
 	}
 
 	pub async fn create_connection(
 		&self,
 		desc: Description,
-		camera: Box<dyn Camera + Send>,
+		camera: Box<dyn Camera>,
 	) -> Result<Connection, Error> {
 		let mut m = MediaEngine::default();
 
-		/// cannot fail, see webrtc impl
+		// cannot fail, see webrtc impl
 		m.register_default_codecs().unwrap();
 
 		let mut registry = Registry::new();
@@ -104,18 +112,16 @@ impl Webrtc {
 			.await?;
 
 		let (state_tx, state_rx) = mpsc::channel(5);
-
-		let camera_peer_connection = peer_connection.clone();
-		let rt_handle = RtHandle::current();
-		thread::spawn(move || {
-			camera_thread(
-				camera,
-				video_track,
-				camera_peer_connection,
-				state_rx,
-				rt_handle,
-			);
-		});
+		let (peer_state_tx, peer_state_rx) =
+			watch::channel(RTCPeerConnectionState::New);
+		let (candidates_tx, candidates_rx) = mpsc::unbounded_channel();
+
+		tokio::spawn(send_video(
+			camera,
+			video_track,
+			peer_connection.clone(),
+			state_rx,
+		));
 
 		// keep the rtp stream going
 		tokio::spawn(async move {
@@ -142,6 +148,16 @@ impl Webrtc {
 			move |c: Option<RTCIceCandidate>| {
 				eprintln!("ice candidate update {:?}", c);
 
+				// none signals the end of the gathering
+				let c = match c.map(|c| c.to_json()).transpose() {
+					Ok(c) => c,
+					Err(e) => {
+						eprintln!("could not serialize candidate {:?}", e);
+						return Box::pin(async {});
+					}
+				};
+				let _ = candidates_tx.send(c);
+
 				Box::pin(async {})
 			},
 		));
@@ -149,13 +165,14 @@ impl Webrtc {
 		peer_connection.on_peer_connection_state_change(Box::new(
 			move |s: RTCPeerConnectionState| {
 				eprintln!("peer connection state change {:?}", s);
+				let _ = peer_state_tx.send(s);
 
 				match s {
 					RTCPeerConnectionState::Connected => {
 						let _ = state_tx.try_send(State::Connected);
 					}
-					RTCPeerConnectionState::Disconnected
-					| RTCPeerConnectionState::Failed
+					// a disconnected connection can recover, the video keeps going
+					RTCPeerConnectionState::Failed
 					| RTCPeerConnectionState::Closed => {
 						let _ = state_tx.try_send(State::Disconnected);
 					}
@@ -166,12 +183,26 @@ impl Webrtc {
 			},
 		));
 
+		// both control channels of a connection share the sequence numbers
+		let session = Arc::new(Mutex::new(Session::new()));
+		let vehicle = self.vehicle.clone();
 		peer_connection.on_data_channel(Box::new(
 			move |d: Arc<RTCDataChannel>| {
 				let d_label = d.label().to_owned();
 				let d_id = d.id();
 				println!("New DataChannel {} {}", d_label, d_id);
 
+				match d_label.as_str() {
+					control::CONTROL_CHANNEL | control::STICKS_CHANNEL => {
+						handle_control_channel(
+							d,
+							session.clone(),
+							vehicle.clone(),
+						);
+					}
+					_ => {}
+				}
+
 				Box::pin(async {})
 			},
 		));
@@ -182,12 +213,21 @@ impl Webrtc {
 
 		peer_connection.set_local_description(answer).await?;
 
-		Ok(Connection { peer_connection })
+		Ok(Connection {
+			peer_connection,
+			state: peer_state_rx,
+			candidates: Mutex::new(Some(candidates_rx)),
+		})
 	}
 }
 
+/// A local ice candidate, `None` once all candidates were gathered
+pub type Candidate = Option<RTCIceCandidateInit>;
+
 pub struct Connection {
 	peer_connection: Arc<RTCPeerConnection>,
+	state: watch::Receiver<RTCPeerConnectionState>,
+	candidates: Mutex<Option<mpsc::UnboundedReceiver<Candidate>>>,
 }
 
 impl Connection {
@@ -195,117 +235,293 @@ impl Connection {
 		self.peer_connection.local_description().await.unwrap()
 	}
 
+	/// Waits until all local candidates were gathered and returns the
+	/// description containing them
+	///
+	/// For peers which don't support trickle ice, takes the candidates.
+	pub async fn gathered_description(&self) -> Description {
+		if let Some(mut candidates) = self.take_candidates() {
+			while let Some(Some(_)) = candidates.recv().await {}
+		}
+		self.description().await
+	}
+
+	/// Returns the local ice candidates as they get gathered
+	///
+	/// Can only be taken once, returns `None` after that.
+	pub fn take_candidates(
+		&self,
+	) -> Option<mpsc::UnboundedReceiver<Candidate>> {
+		self.candidates.lock().unwrap().take()
+	}
+
+	pub async fn add_ice_candidate(
+		&self,
+		candidate: RTCIceCandidateInit,
+	) -> Result<(), Error> {
+		self.peer_connection.add_ice_candidate(candidate).await?;
+		Ok(())
+	}
+
+	/// Waits until the connection failed, was closed or stayed
+	/// disconnected for `DISCONNECTED_GRACE`
+	///
+	/// The camera stops once the connection is lost, so the connection
+	/// cannot be used anymore.
+	pub async fn closed(&self) {
+		let mut state = self.state.clone();
+		loop {
+			let current = *state.borrow_and_update();
+			match current {
+				RTCPeerConnectionState::Failed
+				| RTCPeerConnectionState::Closed => return,
+				RTCPeerConnectionState::Disconnected => {
+					let changed = state.changed();
+					match time::timeout(DISCONNECTED_GRACE, changed).await {
+						Ok(Ok(())) => continue,
+						Ok(Err(_)) => return,
+						Err(_) => {
+							eprintln!("connection stayed disconnected");
+							return;
+						}
+					}
+				}
+				_ => {}
+			}
+
+			if state.changed().await.is_err() {
+				return;
+			}
+		}
+	}
+
 	pub async fn close(&self) {
 		self.peer_connection.close().await.unwrap();
 	}
 }
 
-// every x frames
-const GATHER_STATS_EVERY: usize = 15;
+/// Routes the control messages to the vehicle and answers arm and disarm
+/// with the new state
+fn handle_control_channel(
+	d: Arc<RTCDataChannel>,
+	session: Arc<Mutex<Session>>,
+	vehicle: SharedVehicle,
+) {
+	if d.label() == control::CONTROL_CHANNEL {
+		send_state_changes(d.clone(), &vehicle);
+	}
+
+	let channel = d.clone();
+	d.on_message(Box::new(move |msg: DataChannelMessage| {
+		let envelope = match session.lock().unwrap().receive(&msg.data) {
+			Ok(Some(e)) => e,
+			Ok(None) => return Box::pin(async {}),
+			Err(e) => {
+				eprintln!("control message rejected {}", e);
+				return Box::pin(async {});
+			}
+		};
+
+		let state = {
+			let mut vehicle = vehicle.lock().unwrap();
+			vehicle.handle(&envelope.message, Instant::now());
+			vehicle.state()
+		};
+
+		if matches!(envelope.message, control::Message::Sticks { .. }) {
+			return Box::pin(async {});
+		}
+
+		let channel = channel.clone();
+		Box::pin(async move {
+			let state = serde_json::json!({
+				"state": state,
+				"seq": envelope.seq,
+				"time": envelope.time,
+			})
+			.to_string();
+			if let Err(e) = channel.send_text(state).await {
+				eprintln!("could not send control state {:?}", e);
+			}
+		})
+	}));
+}
+
+/// Tells the client when the link state changes without a message, for
+/// example when the failsafe triggers, and sends every telemetry
+fn send_state_changes(channel: Arc<RTCDataChannel>, vehicle: &SharedVehicle) {
+	let (mut state_rx, telemetry_rx) = {
+		let vehicle = vehicle.lock().unwrap();
+		(vehicle.subscribe(), vehicle.subscribe_telemetry())
+	};
+
+	if let Some(telemetry_rx) = telemetry_rx {
+		tokio::spawn(send_telemetry(channel.clone(), telemetry_rx));
+	}
+
+	tokio::spawn(async move {
+		while state_rx.changed().await.is_ok() {
+			let state = *state_rx.borrow_and_update();
+			let state = serde_json::json!({ "state": state }).to_string();
+			// stops once the channel is closed
+			if channel.send_text(state).await.is_err() {
+				return;
+			}
+		}
+	});
+}
+
+/// Sends the telemetry until the channel is closed
+async fn send_telemetry(
+	channel: Arc<RTCDataChannel>,
+	mut telemetry_rx: TelemetryReceiver,
+) {
+	while telemetry_rx.changed().await.is_ok() {
+		let Some(t) = *telemetry_rx.borrow_and_update() else {
+			continue;
+		};
+		// the telemetry can arrive before the channel is open
+		match channel.ready_state() {
+			RTCDataChannelState::Open => {}
+			RTCDataChannelState::Closing | RTCDataChannelState::Closed => {
+				return
+			}
+			_ => continue,
+		}
 
-fn camera_thread(
-	mut camera: Box<dyn Camera>,
+		let telemetry = serde_json::json!({
+			"battery_mv": t.battery_mv,
+			"temperature_c": t.temperature as f32 / 10.0,
+			"uptime_ms": t.uptime_ms,
+			"stm32_failsafe": t.failsafe,
+		})
+		.to_string();
+		if channel.send_text(telemetry).await.is_err() {
+			return;
+		}
+	}
+}
+
+/// Sends the frames of the camera until the connection is lost
+///
+/// If the camera fails the connection stays open without video, so the
+/// vehicle can still be controlled.
+async fn send_video(
+	camera: Box<dyn Camera>,
 	track: Arc<TrackLocalStaticSample>,
 	peer_connection: Arc<RTCPeerConnection>,
 	mut state_rx: mpsc::Receiver<State>,
-	rt: RtHandle,
 ) {
 	// let's wait until the connection is established
-	match state_rx.blocking_recv() {
+	match state_rx.recv().await {
 		Some(State::Connected) => {}
 		Some(State::Disconnected) | None => return,
 	};
 
-	let mut gather_stats_in = 0;
-	let mut stats;
-	let mut ticks = FrameTicks::new();
+	let mut frames = camera::frames(camera);
+	let mut stats = time::interval(LOG_STATS_EVERY);
+	let mut last_sent = None;
+	let mut pacer = Pacer::new();
+	// a decoder cannot start with anything else
+	let mut waiting_for_keyframe = true;
+	let mut format = None;
 
-	// let's try to get some information
 	loop {
-		if gather_stats_in == 0 {
-			stats = rt.block_on(peer_connection.get_stats());
-			// for (key, stat) in stats.reports {
-			// 	// let stat = match stat {
-			// 	// 	StatsReportType::CandidatePair(stat) |
-			// 	// 	StatsReportType::LocalCandidate(stat) |
-			// 	// 	StatsReportType::RemoteCandidate(stat)
-			// 	// }
-			// 	if let StatsReportType::CandidatePair(stat) = stat {
-			// 		eprintln!("{:?} {:?} {:?}", key, stat.available_incoming_bitrate, stat.available_outgoing_bitrate);
-			// 	}
-			// }
-			gather_stats_in = GATHER_STATS_EVERY;
-		} else {
-			gather_stats_in -= 1;
-		}
-
-		// wait until we should send another frame
-		let frames_skipped = ticks.tick();
-		if frames_skipped > 0 {
-			eprintln!("skipped frames {:?}", frames_skipped);
-		}
+		let frame = tokio::select! {
+			state = state_rx.recv() => match state {
+				Some(State::Connected) => continue,
+				Some(State::Disconnected) | None => return,
+			},
+			_ = stats.tick() => {
+				log_video_bitrate(&peer_connection, &mut last_sent).await;
+				continue;
+			}
+			frame = frames.next() => match frame {
+				Some(Ok(frame)) => frame,
+				Some(Err(e)) => {
+					eprintln!("camera closed {:?}", e);
+					return;
+				}
+				None => return,
+			},
+		};
 
-		// check if the connection already closed
-		match state_rx.try_recv() {
-			Ok(State::Connected) => unreachable!(),
-			Ok(State::Disconnected)
-			| Err(mpsc::error::TryRecvError::Disconnected) => return,
-			Err(mpsc::error::TryRecvError::Empty) => {}
+		if waiting_for_keyframe && !frame.keyframe {
+			continue;
 		}
+		waiting_for_keyframe = false;
 
-		let starts_sample_time = Instant::now();
-		let mut sample = match camera.next_sample() {
-			Ok(s) => s,
-			Err(e) => {
-				// should we close the track??
-				eprintln!("camera closed {:?}", e);
-				return;
+		if format != Some((frame.codec, frame.resolution)) {
+			format = Some((frame.codec, frame.resolution));
+			match frame.resolution {
+				Some(r) => eprintln!("camera sends {:?} {}", frame.codec, r),
+				None => eprintln!("camera sends {:?}", frame.codec),
 			}
-		};
-		eprintln!(
-			"took {:?}ms to get the next sample",
-			starts_sample_time.elapsed().as_millis()
-		);
+		}
 
-		sample.duration = FRAME_DURATION;
+		pacer.wait(frame.duration).await;
 
-		if let Err(e) = rt.block_on(track.write_sample(&sample)) {
+		let sample = Sample {
+			data: frame.data,
+			duration: frame.duration,
+			timestamp: SystemTime::now() - frame.timestamp.elapsed(),
+			..Default::default()
+		};
+		if let Err(e) = track.write_sample(&sample).await {
 			eprintln!("could not write sample {:?}", e);
 			return;
 		}
 	}
 }
 
-const FRAME_DURATION: Duration = Duration::from_millis(1000 / 30);
+/// Logs the bitrate the video was sent with since the last call
+///
+/// webrtc-rs leaves the bitrates of the candidate pair at zero, so it is
+/// calculated from the bytes of the outbound rtp stream.
+async fn log_video_bitrate(
+	peer_connection: &RTCPeerConnection,
+	last_sent: &mut Option<(u64, Instant)>,
+) {
+	let report = peer_connection.get_stats().await;
+	let sent: u64 = report
+		.reports
+		.values()
+		.filter_map(|r| match r {
+			StatsReportType::OutboundRTP(s) if s.kind == "video" => {
+				Some(s.bytes_sent)
+			}
+			_ => None,
+		})
+		.sum();
+
+	let now = Instant::now();
+	if let Some((bytes, at)) = last_sent.replace((sent, now)) {
+		let secs = now.duration_since(at).as_secs_f64();
+		let kbit = sent.saturating_sub(bytes) as f64 * 8.0 / secs / 1000.0;
+		eprintln!("video sent with {:.0} kbit/s", kbit);
+	}
+}
 
-struct FrameTicks {
-	last_tick: Instant,
+/// Spreads out frames which arrive in a burst
+///
+/// Frames which arrive roughly on time are sent right away, so a camera
+/// which is a bit faster than its framerate doesn't build up a delay.
+struct Pacer {
+	last_sent: Option<Instant>,
 }
 
-impl FrameTicks {
+impl Pacer {
 	fn new() -> Self {
-		Self {
-			last_tick: Instant::now(),
-		}
+		Self { last_sent: None }
 	}
 
-	/// wait's until the next tick
-	/// returns the number of tick that already passed
-	fn tick(&mut self) -> usize {
-		let mut elapsed = self.last_tick.elapsed();
-		if elapsed < FRAME_DURATION {
-			thread::sleep(FRAME_DURATION - elapsed);
-			self.last_tick = Instant::now();
-			return 0;
+	async fn wait(&mut self, duration: Duration) {
+		if let Some(last_sent) = self.last_sent {
+			let due = last_sent + duration;
+			if Instant::now() + duration / 2 < due {
+				time::sleep_until(due.into()).await;
+			}
 		}
-
-		let ticks_passed = (elapsed.as_secs_f64()
-			/ FRAME_DURATION.as_secs_f64())
-		.floor() as u32;
-		elapsed -= FRAME_DURATION * ticks_passed;
-		thread::sleep(FRAME_DURATION - elapsed);
-		self.last_tick = Instant::now();
-
-		ticks_passed as usize
+		self.last_sent = Some(Instant::now());
 	}
 }
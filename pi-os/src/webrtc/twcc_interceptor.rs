@@ -7,6 +7,7 @@ use webrtc::interceptor::{
 	Attributes, Error, Interceptor, InterceptorBuilder, RTCPReader, RTCPWriter,
 	RTPReader, RTPWriter,
 };
+use webrtc::util::Unmarshal;
 
 #[derive(Debug)]
 pub struct TwccInterceptorBuilder;
@@ -14,7 +15,7 @@ pub struct TwccInterceptorBuilder;
 impl InterceptorBuilder for TwccInterceptorBuilder {
 	fn build(
 		&self,
-		id: &str,
+		_id: &str,
 	) -> Result<Arc<dyn Interceptor + Send + Sync>, Error> {
 		Ok(Arc::new(TwccInterceptor))
 	}
@@ -88,26 +89,27 @@ impl RTCPReader for TwccInterceptorRtcpReader {
 		let (n, attr) = self.parent_reader.read(buf, a).await?;
 
 		let mut b = &buf[..n];
-		let packet = webrtc::rtcp::packet::unmarshal(&mut b)?;
+		let _packet = webrtc::rtcp::packet::unmarshal(&mut b)?;
 
-		// eprintln!("rtcp packet {:?}", packet);
+		// eprintln!("rtcp packet {:?}", _packet);
 
 		Ok((n, attr))
 	}
 }
 
+#[allow(dead_code)]
 pub struct TwccInterceptorRtpReader {
 	parent_reader: Arc<dyn RTPReader + Send + Sync>,
 }
 
 #[async_trait]
-impl RTPReader for ReceiverStream {
+impl RTPReader for TwccInterceptorRtpReader {
 	/// read a rtp packet
 	async fn read(
 		&self,
 		buf: &mut [u8],
 		a: &Attributes,
-	) -> Result<(usize, Attributes)> {
+	) -> Result<(usize, Attributes), Error> {
 		let (n, attr) = self.parent_reader.read(buf, a).await?;
 
 		let mut b = &buf[..n];
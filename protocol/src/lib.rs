@@ -0,0 +1,671 @@
+//! Frames and messages exchanged between pi-os and the stm32 over spi
+//!
+//! A frame is `SYNC seq type len payload crc` where the crc is a
+//! CRC-16/CCITT-FALSE over everything after the sync byte, little endian
+//! like every other field. Requests have a type below `0x80`, responses
+//! one above and answer with the sequence number of their request.
+//!
+//! Changing a message means bumping `PROTOCOL_VERSION`, pi-os checks it
+//! with a `Hello` before sending anything else.
+
+#![no_std]
+
+use core::fmt;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+pub const SYNC: u8 = 0xa5;
+pub const MAX_PAYLOAD: usize = 32;
+const HEADER_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+pub const MAX_FRAME: usize = HEADER_LEN + MAX_PAYLOAD + CRC_LEN;
+
+pub const AUX_CHANNELS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+	NoFrame,
+	Incomplete,
+	Crc,
+	UnknownType(u8),
+	InvalidPayload,
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::NoFrame => f.write_str("no frame found"),
+			Self::Incomplete => f.write_str("frame is incomplete"),
+			Self::Crc => f.write_str("crc mismatch"),
+			Self::UnknownType(t) => {
+				write!(f, "unknown message type {:#04x}", t)
+			}
+			Self::InvalidPayload => f.write_str("invalid payload"),
+		}
+	}
+}
+
+impl core::error::Error for DecodeError {}
+
+pub fn crc16(data: &[u8]) -> u16 {
+	let mut crc = 0xffffu16;
+	for byte in data {
+		crc ^= (*byte as u16) << 8;
+		for _ in 0..8 {
+			crc = if crc & 0x8000 != 0 {
+				(crc << 1) ^ 0x1021
+			} else {
+				crc << 1
+			};
+		}
+	}
+	crc
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+	pub seq: u8,
+	pub msg_type: u8,
+	pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+	/// Returns the length of the frame written to `out`
+	///
+	/// ## Panics
+	/// If the payload is longer than `MAX_PAYLOAD` or `out` is shorter
+	/// than the frame.
+	pub fn encode(&self, out: &mut [u8]) -> usize {
+		assert!(self.payload.len() <= MAX_PAYLOAD, "payload too long");
+		let len = HEADER_LEN + self.payload.len();
+
+		out[0] = SYNC;
+		out[1] = self.seq;
+		out[2] = self.msg_type;
+		out[3] = self.payload.len() as u8;
+		out[HEADER_LEN..len].copy_from_slice(self.payload);
+		let crc = crc16(&out[1..len]);
+		out[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+		len + CRC_LEN
+	}
+
+	/// Finds the first valid frame in `buf`, bytes before the sync byte are
+	/// skipped since the other side clocks out idle bytes
+	///
+	/// A stale or partial frame in front of the real one is skipped as
+	/// well. If there is no valid frame, the error of the first sync byte
+	/// is returned.
+	pub fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> {
+		let mut first_error = None;
+		let mut rest = buf;
+		while let Some(start) = rest.iter().position(|b| *b == SYNC) {
+			match Self::decode_at(&rest[start..]) {
+				Ok(frame) => return Ok(frame),
+				Err(e) => {
+					first_error.get_or_insert(e);
+				}
+			}
+			rest = &rest[start + 1..];
+		}
+
+		Err(first_error.unwrap_or(DecodeError::NoFrame))
+	}
+
+	/// Decodes the frame at the start of `buf`
+	fn decode_at(buf: &'a [u8]) -> Result<Self, DecodeError> {
+		if buf.len() < HEADER_LEN {
+			return Err(DecodeError::Incomplete);
+		}
+		let payload_len = buf[3] as usize;
+		if payload_len > MAX_PAYLOAD {
+			return Err(DecodeError::InvalidPayload);
+		}
+		let len = HEADER_LEN + payload_len;
+		if buf.len() < len + CRC_LEN {
+			return Err(DecodeError::Incomplete);
+		}
+
+		let crc = u16::from_le_bytes([buf[len], buf[len + 1]]);
+		if crc != crc16(&buf[1..len]) {
+			return Err(DecodeError::Crc);
+		}
+
+		Ok(Self {
+			seq: buf[1],
+			msg_type: buf[2],
+			payload: &buf[HEADER_LEN..len],
+		})
+	}
+}
+
+/// The outputs scaled to `i16::MAX`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputValues {
+	pub armed: bool,
+	pub steering: i16,
+	pub throttle: i16,
+	pub aux: [i16; AUX_CHANNELS],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Telemetry {
+	pub battery_mv: u16,
+	/// in 0.1 °C
+	pub temperature: i16,
+	pub uptime_ms: u32,
+	/// The stm32 lost the link to the pi and applies its failsafe
+	pub failsafe: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConfigKey {
+	/// How long the stm32 waits for outputs before its own failsafe
+	FailsafeTimeoutMs = 1,
+	SteeringTrim = 2,
+	ThrottleLimit = 3,
+}
+
+impl ConfigKey {
+	fn from_u8(v: u8) -> Option<Self> {
+		match v {
+			1 => Some(Self::FailsafeTimeoutMs),
+			2 => Some(Self::SteeringTrim),
+			3 => Some(Self::ThrottleLimit),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+	UnknownType = 1,
+	InvalidPayload = 2,
+	UnsupportedConfig = 3,
+}
+
+impl fmt::Display for ErrorCode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::UnknownType => "unknown message type",
+			Self::InvalidPayload => "invalid payload",
+			Self::UnsupportedConfig => "unsupported config",
+		})
+	}
+}
+
+impl core::error::Error for ErrorCode {}
+
+impl ErrorCode {
+	fn from_u8(v: u8) -> Option<Self> {
+		match v {
+			1 => Some(Self::UnknownType),
+			2 => Some(Self::InvalidPayload),
+			3 => Some(Self::UnsupportedConfig),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+	Hello,
+	SetOutputs(OutputValues),
+	GetTelemetry,
+	SetConfig(ConfigKey, i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+	Hello { version: u8 },
+	Ack,
+	Telemetry(Telemetry),
+	Error(ErrorCode),
+}
+
+/// Reads little endian values from a payload
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+	fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+		if self.0.len() < N {
+			return Err(DecodeError::InvalidPayload);
+		}
+		let (bytes, rest) = self.0.split_at(N);
+		self.0 = rest;
+		Ok(bytes.try_into().unwrap())
+	}
+
+	fn u8(&mut self) -> Result<u8, DecodeError> {
+		Ok(self.bytes::<1>()?[0])
+	}
+
+	fn bool(&mut self) -> Result<bool, DecodeError> {
+		Ok(self.u8()? != 0)
+	}
+
+	fn i16(&mut self) -> Result<i16, DecodeError> {
+		Ok(i16::from_le_bytes(self.bytes()?))
+	}
+
+	fn u16(&mut self) -> Result<u16, DecodeError> {
+		Ok(u16::from_le_bytes(self.bytes()?))
+	}
+
+	fn i32(&mut self) -> Result<i32, DecodeError> {
+		Ok(i32::from_le_bytes(self.bytes()?))
+	}
+
+	fn u32(&mut self) -> Result<u32, DecodeError> {
+		Ok(u32::from_le_bytes(self.bytes()?))
+	}
+
+	/// Trailing bytes are an error so a version mismatch is noticed
+	fn finish(self) -> Result<(), DecodeError> {
+		if self.0.is_empty() {
+			Ok(())
+		} else {
+			Err(DecodeError::InvalidPayload)
+		}
+	}
+}
+
+/// Writes little endian values to a payload
+struct Writer<'a> {
+	buf: &'a mut [u8],
+	len: usize,
+}
+
+impl<'a> Writer<'a> {
+	fn new(buf: &'a mut [u8]) -> Self {
+		Self { buf, len: 0 }
+	}
+
+	fn bytes(&mut self, bytes: &[u8]) {
+		self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+		self.len += bytes.len();
+	}
+}
+
+impl Request {
+	pub fn msg_type(&self) -> u8 {
+		match self {
+			Self::Hello => 0x01,
+			Self::SetOutputs(_) => 0x02,
+			Self::GetTelemetry => 0x03,
+			Self::SetConfig(..) => 0x04,
+		}
+	}
+
+	/// Returns the length of the payload
+	pub fn encode(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+		let mut w = Writer::new(payload);
+		match self {
+			Self::Hello | Self::GetTelemetry => {}
+			Self::SetOutputs(o) => {
+				w.bytes(&[o.armed as u8]);
+				w.bytes(&o.steering.to_le_bytes());
+				w.bytes(&o.throttle.to_le_bytes());
+				for aux in &o.aux {
+					w.bytes(&aux.to_le_bytes());
+				}
+			}
+			Self::SetConfig(key, value) => {
+				w.bytes(&[*key as u8]);
+				w.bytes(&value.to_le_bytes());
+			}
+		}
+		w.len
+	}
+
+	pub fn decode(msg_type: u8, payload: &[u8]) -> Result<Self, DecodeError> {
+		let mut r = Reader(payload);
+		let req = match msg_type {
+			0x01 => Self::Hello,
+			0x02 => {
+				let armed = r.bool()?;
+				let steering = r.i16()?;
+				let throttle = r.i16()?;
+				let mut aux = [0; AUX_CHANNELS];
+				for a in &mut aux {
+					*a = r.i16()?;
+				}
+				Self::SetOutputs(OutputValues {
+					armed,
+					steering,
+					throttle,
+					aux,
+				})
+			}
+			0x03 => Self::GetTelemetry,
+			0x04 => {
+				let key = ConfigKey::from_u8(r.u8()?)
+					.ok_or(DecodeError::InvalidPayload)?;
+				Self::SetConfig(key, r.i32()?)
+			}
+			t => return Err(DecodeError::UnknownType(t)),
+		};
+		r.finish()?;
+		Ok(req)
+	}
+}
+
+impl Response {
+	pub fn msg_type(&self) -> u8 {
+		match self {
+			Self::Hello { .. } => 0x81,
+			Self::Ack => 0x82,
+			Self::Telemetry(_) => 0x83,
+			Self::Error(_) => 0xff,
+		}
+	}
+
+	/// Returns the length of the payload
+	pub fn encode(&self, payload: &mut [u8; MAX_PAYLOAD]) -> usize {
+		let mut w = Writer::new(payload);
+		match self {
+			Self::Hello { version } => w.bytes(&[*version]),
+			Self::Ack => {}
+			Self::Telemetry(t) => {
+				w.bytes(&t.battery_mv.to_le_bytes());
+				w.bytes(&t.temperature.to_le_bytes());
+				w.bytes(&t.uptime_ms.to_le_bytes());
+				w.bytes(&[t.failsafe as u8]);
+			}
+			Self::Error(code) => w.bytes(&[*code as u8]),
+		}
+		w.len
+	}
+
+	pub fn decode(msg_type: u8, payload: &[u8]) -> Result<Self, DecodeError> {
+		let mut r = Reader(payload);
+		let res = match msg_type {
+			0x81 => Self::Hello { version: r.u8()? },
+			0x82 => Self::Ack,
+			0x83 => Self::Telemetry(Telemetry {
+				battery_mv: r.u16()?,
+				temperature: r.i16()?,
+				uptime_ms: r.u32()?,
+				failsafe: r.bool()?,
+			}),
+			0xff => Self::Error(
+				ErrorCode::from_u8(r.u8()?)
+					.ok_or(DecodeError::InvalidPayload)?,
+			),
+			t => return Err(DecodeError::UnknownType(t)),
+		};
+		r.finish()?;
+		Ok(res)
+	}
+}
+
+/// Encodes a message with its frame, returns the length
+pub fn encode_frame(
+	seq: u8,
+	msg_type: u8,
+	payload: &[u8],
+	out: &mut [u8; MAX_FRAME],
+) -> usize {
+	Frame {
+		seq,
+		msg_type,
+		payload,
+	}
+	.encode(out)
+}
+
+/// Answers the request frame in `rx` for the device side, the response is
+/// written to `out` with the sequence number of the request
+///
+/// Returns the length of the response or `None` if `rx` contains no valid
+/// frame, in that case nothing should be answered.
+pub fn respond(
+	rx: &[u8],
+	out: &mut [u8; MAX_FRAME],
+	handle: impl FnOnce(Request) -> Response,
+) -> Option<usize> {
+	let frame = Frame::decode(rx).ok()?;
+	let res = match Request::decode(frame.msg_type, frame.payload) {
+		Ok(req) => handle(req),
+		Err(DecodeError::UnknownType(_)) => {
+			Response::Error(ErrorCode::UnknownType)
+		}
+		Err(_) => Response::Error(ErrorCode::InvalidPayload),
+	};
+
+	let mut payload = [0; MAX_PAYLOAD];
+	let payload_len = res.encode(&mut payload);
+	Some(encode_frame(
+		frame.seq,
+		res.msg_type(),
+		&payload[..payload_len],
+		out,
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use proptest::collection::vec;
+	use proptest::prelude::*;
+
+	extern crate std;
+	use std::vec::Vec;
+
+	fn requests() -> impl Strategy<Value = Request> {
+		let outputs =
+			(any::<bool>(), any::<i16>(), any::<i16>(), any::<[i16; 4]>())
+				.prop_map(|(armed, steering, throttle, aux)| OutputValues {
+					armed,
+					steering,
+					throttle,
+					aux,
+				});
+		let key = prop_oneof![
+			Just(ConfigKey::FailsafeTimeoutMs),
+			Just(ConfigKey::SteeringTrim),
+			Just(ConfigKey::ThrottleLimit),
+		];
+
+		prop_oneof![
+			Just(Request::Hello),
+			outputs.prop_map(Request::SetOutputs),
+			Just(Request::GetTelemetry),
+			(key, any::<i32>()).prop_map(|(k, v)| Request::SetConfig(k, v)),
+		]
+	}
+
+	fn responses() -> impl Strategy<Value = Response> {
+		let telemetry =
+			(any::<u16>(), any::<i16>(), any::<u32>(), any::<bool>()).prop_map(
+				|(battery_mv, temperature, uptime_ms, failsafe)| Telemetry {
+					battery_mv,
+					temperature,
+					uptime_ms,
+					failsafe,
+				},
+			);
+		let code = prop_oneof![
+			Just(ErrorCode::UnknownType),
+			Just(ErrorCode::InvalidPayload),
+			Just(ErrorCode::UnsupportedConfig),
+		];
+
+		prop_oneof![
+			any::<u8>().prop_map(|version| Response::Hello { version }),
+			Just(Response::Ack),
+			telemetry.prop_map(Response::Telemetry),
+			code.prop_map(Response::Error),
+		]
+	}
+
+	fn encode_request(seq: u8, req: &Request) -> ([u8; MAX_FRAME], usize) {
+		let mut payload = [0; MAX_PAYLOAD];
+		let len = req.encode(&mut payload);
+		let mut out = [0; MAX_FRAME];
+		let len = encode_frame(seq, req.msg_type(), &payload[..len], &mut out);
+		(out, len)
+	}
+
+	/// A valid frame has to be a copy of bytes in `buf`
+	fn contains_frame(buf: &[u8], frame: &Frame) -> bool {
+		let mut out = [0; MAX_FRAME];
+		let len = frame.encode(&mut out);
+		buf.windows(len).any(|w| w == &out[..len])
+	}
+
+	fn bytes() -> impl Strategy<Value = Vec<u8>> {
+		vec(any::<u8>(), 0..128)
+	}
+
+	/// Idle bytes, sync bytes, intact, corrupted and truncated frames
+	fn chunks() -> impl Strategy<Value = (Vec<u8>, bool)> {
+		let frame = (any::<u8>(), requests(), any::<usize>(), any::<u8>());
+		prop_oneof![
+			vec(any::<u8>(), 0..8).prop_map(|b| (b, false)),
+			Just((Vec::from([SYNC]), false)),
+			frame.prop_map(|(seq, req, at, flip)| {
+				let (frame, len) = encode_request(seq, &req);
+				let mut frame = Vec::from(&frame[..len]);
+				match at % 3 {
+					0 => (frame, true),
+					1 => {
+						frame[at % len] ^= flip.max(1);
+						(frame, false)
+					}
+					_ => {
+						frame.truncate(at % len);
+						(frame, false)
+					}
+				}
+			}),
+		]
+	}
+
+	#[test]
+	fn crc() {
+		assert_eq!(crc16(b"123456789"), 0x29b1);
+	}
+
+	#[test]
+	fn skips_stale_frames() {
+		let (stale, stale_len) = encode_request(1, &Request::Hello);
+		let (frame, len) = encode_request(2, &Request::GetTelemetry);
+
+		let mut corrupted = Vec::new();
+		corrupted.extend_from_slice(&stale[..stale_len]);
+		corrupted[stale_len - 1] ^= 0xff;
+		corrupted.extend_from_slice(&frame[..len]);
+		assert_eq!(Frame::decode(&corrupted).unwrap().seq, 2);
+
+		let mut partial = Vec::from([0, 0]);
+		partial.extend_from_slice(&stale[..3]);
+		partial.extend_from_slice(&frame[..len]);
+		assert_eq!(Frame::decode(&partial).unwrap().seq, 2);
+
+		assert_eq!(Frame::decode(&[0; 8]), Err(DecodeError::NoFrame));
+		assert_eq!(
+			Frame::decode(&frame[..len - 1]),
+			Err(DecodeError::Incomplete)
+		);
+		assert_eq!(
+			Frame::decode(&corrupted[..stale_len]),
+			Err(DecodeError::Crc)
+		);
+	}
+
+	#[test]
+	fn answers_unknown_types() {
+		let mut tx = [0; MAX_FRAME];
+		let len = encode_frame(7, 0x42, &[], &mut tx);
+		let mut out = [0; MAX_FRAME];
+		let len = respond(&tx[..len], &mut out, |_| unreachable!()).unwrap();
+
+		let frame = Frame::decode(&out[..len]).unwrap();
+		assert_eq!(frame.seq, 7);
+		assert_eq!(
+			Response::decode(frame.msg_type, frame.payload),
+			Ok(Response::Error(ErrorCode::UnknownType))
+		);
+	}
+
+	proptest! {
+		#[test]
+		fn request_round_trip(seq: u8, req in requests(), idle in 0usize..8) {
+			let (frame, len) = encode_request(seq, &req);
+			let mut buf = Vec::from([0; 8]);
+			buf.truncate(idle);
+			buf.extend_from_slice(&frame[..len]);
+
+			let frame = Frame::decode(&buf).unwrap();
+			prop_assert_eq!(frame.seq, seq);
+			let decoded = Request::decode(frame.msg_type, frame.payload);
+			prop_assert_eq!(decoded, Ok(req));
+		}
+
+		#[test]
+		fn response_round_trip(seq: u8, res in responses()) {
+			let (tx, tx_len) = encode_request(seq, &Request::Hello);
+			let mut out = [0; MAX_FRAME];
+			let len = respond(&tx[..tx_len], &mut out, |_| res).unwrap();
+
+			let frame = Frame::decode(&out[..len]).unwrap();
+			prop_assert_eq!(frame.seq, seq);
+			let decoded = Response::decode(frame.msg_type, frame.payload);
+			prop_assert_eq!(decoded, Ok(res));
+		}
+
+		#[test]
+		fn decode_arbitrary_bytes(buf in bytes()) {
+			if let Ok(frame) = Frame::decode(&buf) {
+				prop_assert!(contains_frame(&buf, &frame));
+			}
+		}
+
+		#[test]
+		fn decode_mixed_bytes(chunks in vec(chunks(), 0..6)) {
+			let intact = chunks.iter().any(|(_, intact)| *intact);
+			let buf: Vec<u8> =
+				chunks.into_iter().flat_map(|(b, _)| b).collect();
+
+			match Frame::decode(&buf) {
+				Ok(frame) => prop_assert!(contains_frame(&buf, &frame)),
+				Err(e) => prop_assert!(!intact, "{:?} in {:x?}", e, buf),
+			}
+		}
+
+		#[test]
+		fn decode_arbitrary_frames(
+			seq: u8,
+			msg_type: u8,
+			payload in vec(any::<u8>(), 0..=MAX_PAYLOAD),
+		) {
+			// valid frames with arbitrary content
+			let mut buf = [0; MAX_FRAME];
+			let len = encode_frame(seq, msg_type, &payload, &mut buf);
+			let frame = Frame::decode(&buf[..len]).unwrap();
+			let _ = Request::decode(frame.msg_type, frame.payload);
+			let _ = Response::decode(frame.msg_type, frame.payload);
+
+			let mut out = [0; MAX_FRAME];
+			let answered = respond(&buf[..len], &mut out, |_| Response::Ack);
+			let answer = Frame::decode(&out[..answered.unwrap()]).unwrap();
+			prop_assert_eq!(answer.seq, seq);
+		}
+
+		#[test]
+		fn respond_to_arbitrary_bytes(buf in bytes()) {
+			let mut out = [0; MAX_FRAME];
+			let answered = respond(&buf, &mut out, |_| Response::Ack);
+			prop_assert_eq!(answered.is_some(), Frame::decode(&buf).is_ok());
+			if let Some(len) = answered {
+				let frame = Frame::decode(&out[..len]).unwrap();
+				let decoded = Response::decode(frame.msg_type, frame.payload);
+				prop_assert!(decoded.is_ok());
+			}
+		}
+	}
+}